@@ -273,6 +273,13 @@ pub fn read_stderr(self) -> xshell::Result<String> {
     pub fn output(self) -> xshell::Result<Output> {
         self.into_resolved().output()
     }
+
+    /// Resolve the command into a [`std::process::Command`], for callers
+    /// that need to manage the child process themselves. Stdin contents and
+    /// the status/echo flags don't carry over.
+    pub fn into_command(self) -> std::process::Command {
+        self.into_resolved().into()
+    }
 }
 
 impl std::fmt::Display for FloweyCmd<'_> {
@@ -3,7 +3,223 @@
 
 use flowey::node::prelude::ReadVar;
 use flowey::pipeline::prelude::*;
+use flowey_lib_hvlite::_jobs::local_install_shrinkwrap::CCA_CONFIG_REPO_NAME;
+use flowey_lib_hvlite::_jobs::local_install_shrinkwrap::DEFAULT_CLONE_RETRIES;
+use flowey_lib_hvlite::_jobs::local_install_shrinkwrap::InstallRepos;
+use flowey_lib_hvlite::_jobs::local_install_shrinkwrap::KERNEL_REPO_NAME;
+use flowey_lib_hvlite::_jobs::local_install_shrinkwrap::RepoUpdatePolicy;
+use flowey_lib_hvlite::_jobs::local_install_shrinkwrap::SHRINKWRAP_REPO_NAME;
+use flowey_lib_hvlite::_jobs::local_install_shrinkwrap::TMK_REPO_NAME;
+use flowey_lib_hvlite::_jobs::local_install_shrinkwrap::ToolchainFlavor;
+use flowey_lib_hvlite::_jobs::local_shrinkwrap_build::OutputMode;
+use flowey_lib_hvlite::_jobs::local_shrinkwrap_build::ShrinkwrapAction;
+use flowey_lib_hvlite::_jobs::local_shrinkwrap_run::RootfsCompression;
+use flowey_lib_hvlite::_jobs::local_shrinkwrap_run::RootfsDownload;
+use flowey_lib_hvlite::_jobs::shrinkwrap_util::DEFAULT_ARM_GNU_TOOLCHAIN_VERSION;
+use flowey_lib_hvlite::_jobs::shrinkwrap_util::HOST_KERNEL_DIR;
+use flowey_lib_hvlite::_jobs::shrinkwrap_util::KERNEL_ARCH;
+use flowey_lib_hvlite::_jobs::shrinkwrap_util::KERNEL_IMAGE_TARGET;
+use flowey_lib_hvlite::_jobs::shrinkwrap_util::PIPELINE_LOCK_FILE;
+use flowey_lib_hvlite::_jobs::shrinkwrap_util::TMK_DIR;
+use flowey_lib_hvlite::_jobs::shrinkwrap_util::TmkVmmTarget;
+use flowey_lib_hvlite::_jobs::shrinkwrap_util::kernel_image_path;
+use flowey_lib_hvlite::_jobs::shrinkwrap_util::shrinkwrap_package_dir;
+use flowey_lib_hvlite::_jobs::shrinkwrap_util::simple_tmk_path;
+use flowey_lib_hvlite::_jobs::shrinkwrap_util::tmk_binary_selected;
+use flowey_lib_hvlite::_jobs::shrinkwrap_util::tmk_vmm_path;
+use std::collections::HashMap;
+use std::path::Path;
 use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(clap::ValueEnum, Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ShrinkwrapActionCli {
+    /// Build the platform
+    Build,
+    /// Clear shrinkwrap's build state for the platform
+    Clean,
+}
+
+impl From<ShrinkwrapActionCli> for ShrinkwrapAction {
+    fn from(cli: ShrinkwrapActionCli) -> Self {
+        match cli {
+            ShrinkwrapActionCli::Build => ShrinkwrapAction::Build,
+            ShrinkwrapActionCli::Clean => ShrinkwrapAction::Clean,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Copy, Clone, PartialEq, Eq, Debug)]
+pub enum OutputModeCli {
+    /// Echo build output live, line by line
+    Streaming,
+    /// Write build output once the build exits (no live view)
+    Buffered,
+}
+
+impl From<OutputModeCli> for OutputMode {
+    fn from(cli: OutputModeCli) -> Self {
+        match cli {
+            OutputModeCli::Streaming => OutputMode::Streaming,
+            OutputModeCli::Buffered => OutputMode::Buffered,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Copy, Clone, PartialEq, Eq, Debug)]
+pub enum RootfsCompressionCli {
+    /// Save the rootfs uncompressed
+    None,
+    /// Compress with gzip (.gz)
+    Gzip,
+    /// Compress with zstd (.zst)
+    Zstd,
+}
+
+impl From<RootfsCompressionCli> for RootfsCompression {
+    fn from(cli: RootfsCompressionCli) -> Self {
+        match cli {
+            RootfsCompressionCli::None => RootfsCompression::None,
+            RootfsCompressionCli::Gzip => RootfsCompression::Gzip,
+            RootfsCompressionCli::Zstd => RootfsCompression::Zstd,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Copy, Clone, PartialEq, Eq, Debug)]
+pub enum RepoUpdatePolicyCli {
+    /// Fetch and fast-forward on every run
+    Always,
+    /// Leave the existing clone as it is
+    Never,
+    /// Fetch, and only update the clone if the upstream has moved
+    IfBehind,
+}
+
+impl From<RepoUpdatePolicyCli> for RepoUpdatePolicy {
+    fn from(cli: RepoUpdatePolicyCli) -> Self {
+        match cli {
+            RepoUpdatePolicyCli::Always => RepoUpdatePolicy::Always,
+            RepoUpdatePolicyCli::Never => RepoUpdatePolicy::Never,
+            RepoUpdatePolicyCli::IfBehind => RepoUpdatePolicy::IfBehind,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TmkVmmTargetCli {
+    /// aarch64-unknown-linux-gnu
+    Gnu,
+    /// aarch64-unknown-linux-musl (statically linked)
+    Musl,
+}
+
+impl From<TmkVmmTargetCli> for TmkVmmTarget {
+    fn from(cli: TmkVmmTargetCli) -> Self {
+        match cli {
+            TmkVmmTargetCli::Gnu => TmkVmmTarget::GnuLinux,
+            TmkVmmTargetCli::Musl => TmkVmmTarget::MuslLinux,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ToolchainFlavorCli {
+    /// The ARM GNU toolchain, downloaded from ARM
+    ArmGnu,
+    /// clang-18, with aarch64-none-elf binutils from PATH
+    LlvmClang,
+}
+
+impl From<ToolchainFlavorCli> for ToolchainFlavor {
+    fn from(cli: ToolchainFlavorCli) -> Self {
+        match cli {
+            ToolchainFlavorCli::ArmGnu => ToolchainFlavor::ArmGnu,
+            ToolchainFlavorCli::LlvmClang => ToolchainFlavor::LlvmClang,
+        }
+    }
+}
+
+/// One configuration of a `--matrix` file.
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MatrixConfig {
+    /// Names the configuration's working dir (`<dir>/matrix/<name>`) and jobs
+    name: String,
+    /// Platform YAML, as for --platform
+    #[serde(default = "default_platform")]
+    platform: PathBuf,
+    /// Overlay YAMLs, as for --overlay
+    #[serde(default)]
+    overlays: Vec<PathBuf>,
+    /// Build-time variables, as for --btvar
+    #[serde(default)]
+    btvars: Vec<String>,
+    /// Runtime variables, in addition to any given with --rtvar
+    #[serde(default)]
+    rtvars: Vec<String>,
+}
+
+fn default_platform() -> PathBuf {
+    PathBuf::from("cca-3world.yaml")
+}
+
+fn default_overlays() -> Vec<PathBuf> {
+    vec![
+        PathBuf::from("buildroot.yaml"),
+        PathBuf::from("planes.yaml"),
+    ]
+}
+
+/// planes.yaml comes from the cca_config repo, which nothing else uses.
+fn uses_planes_overlay(overlays: &[PathBuf]) -> bool {
+    overlays.iter().any(|ov| {
+        ov.file_name()
+            .is_some_and(|name| name.to_string_lossy().contains("planes"))
+    })
+}
+
+/// Read and validate the configurations of a `--matrix` file.
+fn load_matrix(path: &Path) -> anyhow::Result<Vec<MatrixConfig>> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("failed to read matrix file {}: {}", path.display(), e))?;
+    let configs: Vec<MatrixConfig> = serde_yaml::from_str(&contents)
+        .map_err(|e| anyhow::anyhow!("invalid matrix file {}: {}", path.display(), e))?;
+    if configs.is_empty() {
+        anyhow::bail!("matrix file {} has no configurations", path.display());
+    }
+
+    let mut names = std::collections::BTreeSet::new();
+    for config in &configs {
+        // Names are used in paths, job labels and artifact names
+        if config.name.is_empty()
+            || !config
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            anyhow::bail!("invalid matrix configuration name '{}'", config.name);
+        }
+        if !names.insert(config.name.as_str()) {
+            anyhow::bail!("duplicate matrix configuration name '{}'", config.name);
+        }
+    }
+    Ok(configs)
+}
+
+/// What differs between the configurations a pipeline builds and runs.
+struct RunConfig {
+    /// Working dir of the configuration
+    dir: PathBuf,
+    platform: PathBuf,
+    overlays: Vec<PathBuf>,
+    btvars: Vec<String>,
+    rtvars: Vec<String>,
+    rootfs: PathBuf,
+    rootfs_download: Option<RootfsDownload>,
+    shrinkwrap_state_dir: Option<PathBuf>,
+    result_file: Option<PathBuf>,
+}
 
 /// Install Shrinkwrap, Build + run CCA FVP via Shrinkwrap (local)
 #[derive(clap::Args)]
@@ -26,11 +242,32 @@ pub struct CcaFvpCli {
     #[clap(long)]
     pub btvar: Vec<String>,
 
+    /// Extra argument to append to `shrinkwrap build`, after the btvars
+    /// (repeatable, e.g. `--build-arg --no-sync-all`)
+    #[clap(long, allow_hyphen_values = true)]
+    pub build_arg: Vec<String>,
+
     /// Rootfs path to pass at runtime, e.g.
     /// --rootfs /abs/path/.shrinkwrap/package/cca-3world/rootfs.ext2
     /// Default to ${SHRINKWRAP_PACKAGE:-$HOME/.shrinkwrap/package}/cca-3world/rootfs.ext2
+    /// An http(s) URL is downloaded to <dir>/downloads, and a fresh copy of
+    /// it is used for each run.
     #[clap(long)]
-    pub rootfs: Option<PathBuf>,
+    pub rootfs: Option<String>,
+
+    /// Expected sha256 of the rootfs downloaded from a --rootfs URL
+    #[clap(long, requires = "rootfs")]
+    pub rootfs_sha256: Option<String>,
+
+    /// Run with each of these named rootfs images in turn (repeatable),
+    /// instead of --rootfs, e.g. --rootfs-variant minimal=/path/minimal.ext2.
+    /// Each variant's run log is suffixed with its name.
+    #[clap(
+        long,
+        value_name = "NAME=PATH",
+        conflicts_with_all = ["rootfs", "matrix", "rootfs_out", "bench_boot"]
+    )]
+    pub rootfs_variant: Vec<String>,
 
     /// Additional runtime variables (repeatable), besides ROOTFS, e.g. --rtvar FOO=bar
     #[clap(long)]
@@ -40,10 +277,499 @@ pub struct CcaFvpCli {
     #[clap(long, default_value_t = true)]
     pub install_missing_deps: bool,
 
+    /// Create the docker group and add the current user to it (requires sudo)
+    #[clap(long)]
+    pub setup_docker_group: bool,
+
     /// If repo already exists, attempt `git pull --ff-only`
     #[clap(long, default_value_t = true)]
     pub update_shrinkwrap_repo: bool,
 
+    /// Update policy of the host kernel clone, overriding
+    /// --update-shrinkwrap-repo
+    #[clap(long)]
+    pub kernel_update_policy: Option<RepoUpdatePolicyCli>,
+
+    /// Update policy of the OpenVMM TMK clone, overriding
+    /// --update-shrinkwrap-repo
+    #[clap(long)]
+    pub tmk_update_policy: Option<RepoUpdatePolicyCli>,
+
+    /// Update policy of the shrinkwrap clone, overriding
+    /// --update-shrinkwrap-repo
+    #[clap(long)]
+    pub shrinkwrap_update_policy: Option<RepoUpdatePolicyCli>,
+
+    /// Update policy of the cca_config clone, overriding
+    /// --update-shrinkwrap-repo
+    #[clap(long)]
+    pub cca_config_update_policy: Option<RepoUpdatePolicyCli>,
+
+    /// Shrinkwrap subcommand to run with the platform and overlays. Anything
+    /// other than build skips the run job.
+    #[clap(long, value_enum, default_value_t = ShrinkwrapActionCli::Build)]
+    pub shrinkwrap_action: ShrinkwrapActionCli,
+
+    /// If shrinkwrap build fails, rebuild just the failed component and then
+    /// resume the full build
+    #[clap(long)]
+    pub retry_failed_components: bool,
+
+    /// How shrinkwrap build output is written to the console and build log
+    #[clap(long, value_enum, default_value_t = OutputModeCli::Streaming)]
+    pub build_output: OutputModeCli,
+
+    /// Kill shrinkwrap build if a component produces no output for this many
+    /// seconds
+    #[clap(long)]
+    pub component_timeout: Option<u64>,
+
+    /// After the first build, rebuild whenever the platform or overlay YAMLs
+    /// change (Ctrl-C to stop). The run job is skipped.
+    #[clap(long)]
+    pub watch: bool,
+
+    /// Run `shrinkwrap build` even if the platform YAML, overlays and btvars
+    /// are unchanged since the last successful build
+    #[clap(long)]
+    pub force_build: bool,
+
+    /// Don't write <dir>/logs/artifact-index.json (path, size, sha256 and
+    /// mtime of each build artifact) after a successful build, which hashes
+    /// every artifact
+    #[clap(long)]
+    pub no_artifact_index: bool,
+
+    /// Before installing and building, remove the FVP binary artifacts
+    /// (`.img`, `.fd` and kernel `Image*` files) earlier builds left in the
+    /// platform's shrinkwrap build and package dirs and in the TMK target dir
+    /// (if it's under <dir>). The build is then always run.
+    #[clap(long)]
+    pub cleanup_before_build: bool,
+
+    /// With --cleanup-before-build, also remove <dir>/logs
+    #[clap(long, requires = "cleanup_before_build")]
+    pub cleanup_logs: bool,
+
+    /// Remove everything under <dir> (the toolchain, kernel and shrinkwrap
+    /// clones, builds and logs) before installing. Asks for confirmation
+    /// first, unless --yes is passed.
+    #[clap(long, conflicts_with_all = ["check", "cleanup_before_build"])]
+    pub clean: bool,
+
+    /// Don't ask for confirmation before --clean removes anything
+    #[clap(long, requires = "clean")]
+    pub yes: bool,
+
+    /// After the run job, archive the build (including the rootfs with the
+    /// TMK binaries injected) into this tarball with `shrinkwrap package`, to
+    /// run it on another machine without re-running shrinkwrap
+    #[clap(long, value_name = "PATH", conflicts_with_all = ["watch", "matrix"])]
+    pub package: Option<PathBuf>,
+
+    /// Overwrite the --package archive if it already exists, instead of
+    /// skipping packaging
+    #[clap(long, requires = "package")]
+    pub force_package: bool,
+
+    /// Don't check, before running shrinkwrap, that the platform and overlay
+    /// YAMLs exist and parse, and that every `${btvar:NAME}` they reference
+    /// is set
+    #[clap(long)]
+    pub skip_preflight: bool,
+
+    /// Before building, check that every config included by the platform and
+    /// overlay YAMLs exists in the shrinkwrap config dir
+    #[clap(long)]
+    pub check_includes: bool,
+
+    /// Validate the platform and overlay YAMLs before building (using
+    /// `shrinkwrap validate` when the installed shrinkwrap has it)
+    #[clap(long)]
+    pub pre_validate: bool,
+
+    /// Counter file used to give each build a unique, increasing build
+    /// number. The build ID is written to `<dir>/build-id.json`.
+    #[clap(long)]
+    pub build_id_file: Option<PathBuf>,
+
+    /// Redis server (e.g. redis://cache.example.com:6379) to share build
+    /// artifacts through: a build whose inputs are already cached there is
+    /// fetched instead of rebuilt.
+    #[clap(long)]
+    pub redis_cache_url: Option<String>,
+
+    /// Git credential helper to use when cloning/pulling repos (passed as
+    /// `git -c credential.helper=<cmd>`), e.g. for authenticated mirrors
+    #[clap(long)]
+    pub git_credential_helper: Option<String>,
+
+    /// Verify the GPG signature of each cloned repo that is checked out at a
+    /// signed tag, failing if it doesn't verify. Repos on branches or
+    /// unsigned tags are skipped with a warning.
+    #[clap(long)]
+    pub verify_signatures: bool,
+
+    /// GnuPG home dir with the keyring of trusted signers for
+    /// --verify-signatures, instead of the default ~/.gnupg. Set it up with
+    /// `gpg --homedir <dir> --import <signer-key.asc>`, then mark each key as
+    /// trusted with `gpg --homedir <dir> --edit-key <key-id> trust`.
+    #[clap(long, requires = "verify_signatures")]
+    pub gpg_keyring: Option<PathBuf>,
+
+    /// With --update-shrinkwrap-repo, reset repos whose branch has diverged
+    /// from upstream (e.g. after an upstream rebase) to the upstream tip,
+    /// discarding local commits and stashing uncommitted changes
+    #[clap(long, requires = "update_shrinkwrap_repo")]
+    pub allow_reset: bool,
+
+    /// Retry a failed `git clone` or fetch this many times, waiting 2s, 4s,
+    /// 8s, ... in between, before giving up
+    #[clap(long, default_value_t = DEFAULT_CLONE_RETRIES)]
+    pub clone_retries: u32,
+
+    /// Clone the TMK, shrinkwrap and cca_config repos with only their latest
+    /// commit (`git clone --depth 1`), like the kernel. The clones have no
+    /// `git log` history, and are updated by resetting to the upstream tip,
+    /// stashing any uncommitted changes.
+    #[clap(long)]
+    pub shallow_clones: bool,
+
+    /// Depth of the OHCL Linux Kernel clone, which is updated the same way
+    /// as --shallow-clones. 0 clones its full history, which is much slower.
+    #[clap(long, value_name = "N", default_value_t = 1)]
+    pub kernel_clone_depth: u32,
+
+    /// Existing OHCL Linux Kernel clone to check the kernel out from as a
+    /// git worktree, rather than cloning it again (e.g. when iterating over
+    /// several kernel branches, each in its own --dir)
+    #[clap(long, value_name = "PATH")]
+    pub kernel_worktree_base: Option<PathBuf>,
+
+    /// ARM GNU toolchain release to build the host kernel with
+    #[clap(long, default_value = DEFAULT_ARM_GNU_TOOLCHAIN_VERSION)]
+    pub toolchain_version: String,
+
+    /// Compiler to build the host kernel with. llvm-clang skips the ARM GNU
+    /// toolchain download.
+    #[clap(long, value_enum, default_value_t = ToolchainFlavorCli::ArmGnu)]
+    pub toolchain_flavor: ToolchainFlavorCli,
+
+    /// Download the ARM GNU toolchain archive from this URL instead of ARM's
+    /// download site (e.g. a mirror). It must still be the --toolchain-version
+    /// release for this build host.
+    #[clap(long)]
+    pub toolchain_url: Option<String>,
+
+    /// Expected sha256 of the ARM GNU toolchain archive, checked instead of
+    /// the checksum ARM publishes for it (needed to verify a --toolchain-url)
+    #[clap(long)]
+    pub toolchain_sha256: Option<String>,
+
+    /// Clone the OHCL host kernel from this repo (e.g. a mirror) instead of
+    /// the default one
+    #[clap(long)]
+    pub kernel_repo: Option<String>,
+
+    /// Host kernel branch to check out, instead of the default plane0 branch
+    #[clap(long)]
+    pub kernel_branch: Option<String>,
+
+    /// Clone the OpenVMM TMK from this repo instead of the default one
+    #[clap(long)]
+    pub tmk_repo: Option<String>,
+
+    /// OpenVMM TMK branch to check out, instead of the default one
+    #[clap(long)]
+    pub tmk_branch: Option<String>,
+
+    /// Clone cca_config from this repo instead of the default one
+    #[clap(long)]
+    pub cca_config_repo: Option<String>,
+
+    /// Extra kernel config to enable in the host kernel, on top of the ones
+    /// the stack needs (repeatable, e.g. `--enable-config CONFIG_VIRTIO_BLK`)
+    #[clap(long, visible_alias = "enable-config", value_name = "CONFIG_FOO")]
+    pub extra_kernel_config: Vec<String>,
+
+    /// Kernel config to disable in the host kernel, after the others are
+    /// enabled (repeatable)
+    #[clap(long, value_name = "CONFIG_FOO")]
+    pub disable_config: Vec<String>,
+
+    /// Cargo workspace to build the TMK binaries in, for TMK forks with a
+    /// different layout. Defaults to the OpenVMM-TMK clone.
+    #[clap(long)]
+    pub tmk_workspace: Option<PathBuf>,
+
+    /// Cache compiled kernel Images in this dir, keyed by kernel commit,
+    /// config and toolchain version, so they can be reused across working dirs
+    #[clap(long)]
+    pub kernel_cache_dir: Option<PathBuf>,
+
+    /// Use this prebuilt arm64 kernel Image (e.g. a CI artifact) instead of
+    /// cloning and compiling the OHCL Linux Kernel
+    #[clap(long)]
+    pub kernel_image: Option<PathBuf>,
+
+    /// Rebuild the kernel Image even if one already exists (e.g. after
+    /// changing the kernel config)
+    #[clap(long, conflicts_with = "kernel_image")]
+    pub rebuild_kernel: bool,
+
+    /// Module signing key (PEM) to use if the kernel config enables
+    /// CONFIG_MODULE_SIG
+    #[clap(long, conflicts_with = "ephemeral_module_sign_key")]
+    pub module_sign_key: Option<PathBuf>,
+
+    /// Let the kernel build generate a throwaway module signing key if the
+    /// kernel config enables CONFIG_MODULE_SIG
+    #[clap(long)]
+    pub ephemeral_module_sign_key: bool,
+
+    /// Reduce the kernel build's -j if the host doesn't have ~2GB of RAM
+    /// per job (otherwise only warn)
+    #[clap(long)]
+    pub auto_reduce_kernel_jobs: bool,
+
+    /// Number of parallel kernel build jobs (make -j). Defaults to the
+    /// host's CPU count.
+    #[clap(long, value_name = "N", conflicts_with = "auto_reduce_kernel_jobs")]
+    pub jobs: Option<usize>,
+
+    /// TMK binary to build and inject (repeatable): simple_tmk or tmk_vmm.
+    /// Defaults to all of them.
+    #[clap(long)]
+    pub tmk_binary: Vec<String>,
+
+    /// Rust target to build tmk_vmm for. musl links it statically, so it
+    /// runs whatever libc the rootfs has.
+    #[clap(long, value_enum, default_value_t = TmkVmmTargetCli::Gnu)]
+    pub tmk_vmm_target: TmkVmmTargetCli,
+
+    /// Build the TMK binaries one after another rather than concurrently,
+    /// e.g. to read their build output
+    #[clap(long)]
+    pub no_parallel_builds: bool,
+
+    /// Export the kernel build's compile_commands.json (for clangd and other
+    /// IDE tooling) to <dir>/kernel-compile_commands.json
+    #[clap(long)]
+    pub gen_compile_commands: bool,
+
+    /// Don't clone the cca_config repo or copy its planes.yaml into the
+    /// shrinkwrap config dir. It's only cloned when an overlay uses planes.
+    #[clap(long)]
+    pub no_cca_config: bool,
+
+    /// Python interpreter to create the shrinkwrap venv with (e.g. a pyenv
+    /// install), instead of python3 from PATH. Must be Python 3.9 or newer.
+    #[clap(long = "python")]
+    pub python_interpreter: Option<PathBuf>,
+
+    /// Number of CCA Realm VCPUs (1-16), passed as the REALM_VCPUS rtvar
+    #[clap(long)]
+    pub realm_vcpus: Option<u32>,
+
+    /// Host CPUs to pin the Realm VCPUs to, e.g. --realm-vcpu-affinity 0,1,2,3
+    #[clap(long, value_delimiter = ',')]
+    pub realm_vcpu_affinity: Option<Vec<u32>>,
+
+    /// Number of FVP guest cores (1..=8), passed to shrinkwrap run as the
+    /// GUEST_CORES rtvar. Defaults to the platform's core count.
+    #[clap(long)]
+    pub guest_cores: Option<u32>,
+
+    /// Add an entry to the guest's /etc/hosts (repeatable), e.g.
+    /// --guest-host 10.0.0.2 server.test
+    #[clap(long, num_args = 2, value_names = ["IP", "HOSTNAME"])]
+    pub guest_host: Vec<String>,
+
+    /// Share a host dir with the guest over virtio-9p (repeatable), mounted
+    /// via the guest's /etc/fstab. Append `:ro` to mount it read-only, e.g.
+    /// --9p-share data:/srv/data:/mnt/data:ro
+    #[clap(long = "9p-share", value_name = "TAG:HOST_PATH:GUEST_PATH")]
+    pub ninep_share: Vec<String>,
+
+    /// Make sure KASLR is enabled in the OHCL kernel (removes any nokaslr from
+    /// the OHCL_CMDLINE_EXTRA rtvar)
+    #[clap(long, conflicts_with = "disable_kaslr")]
+    pub enable_kaslr: bool,
+
+    /// Disable KASLR in the OHCL kernel for reproducible addresses while
+    /// debugging. This weakens security properties: do not use it for
+    /// production CCA attestation tests.
+    #[clap(long)]
+    pub disable_kaslr: bool,
+
+    /// Extra OHCL kernel command line arguments for this run, appended to
+    /// the OHCL_CMDLINE_EXTRA rtvar, e.g. --kernel-cmdline 'earlycon maxcpus=2'
+    #[clap(long, value_name = "ARGS")]
+    pub kernel_cmdline: Option<String>,
+
+    /// Initial virtio-balloon size in MiB (multiple of 4), passed as the
+    /// BALLOON_INITIAL rtvar
+    #[clap(long)]
+    pub balloon_initial_mb: Option<u32>,
+
+    /// Maximum virtio-balloon size in MiB (multiple of 4), passed as the
+    /// BALLOON_MAX rtvar
+    #[clap(long)]
+    pub balloon_max_mb: Option<u32>,
+
+    /// Enable VMBus in the OHCL guest (VMBUS_ENABLED rtvar)
+    #[clap(long)]
+    pub enable_vmbus: bool,
+
+    /// Number of VMBus channels (VMBUS_CHANNELS rtvar)
+    #[clap(long, default_value_t = 16, requires = "enable_vmbus")]
+    pub vmbus_channels: u32,
+
+    /// Run MSHV in VTL mode (MSHV_VTL rtvar). Requires --enable-vmbus.
+    #[clap(long, requires = "enable_vmbus")]
+    pub mshv_vtl: bool,
+
+    /// Build the RMM from this git repo instead of using the one built by
+    /// shrinkwrap (passed to shrinkwrap run as the RMM rtvar)
+    #[clap(long)]
+    pub rmm_source_repo: Option<String>,
+
+    /// Branch of --rmm-source-repo to build
+    #[clap(long, default_value = "main", requires = "rmm_source_repo")]
+    pub rmm_source_branch: String,
+
+    /// Script that builds the RMM, relative to the RMM repo root. Run with
+    /// CROSS_COMPILE set to the ARM GNU toolchain, if one is installed (it
+    /// isn't with --toolchain-flavor llvm-clang).
+    #[clap(long, default_value = "build.sh", requires = "rmm_source_repo")]
+    pub rmm_build_script: PathBuf,
+
+    /// RMM binary produced by --rmm-build-script, relative to the RMM repo root
+    #[clap(
+        long,
+        default_value = "build/Release/rmm.img",
+        requires = "rmm_source_repo"
+    )]
+    pub rmm_output_binary: PathBuf,
+
+    /// Fail before launching the FVP unless the packaged TF-A bl1.bin is
+    /// this version (e.g. 2.10 matches any 2.10.x)
+    #[clap(long)]
+    pub required_tfa_version: Option<String>,
+
+    /// Fail the whole install/build/run pipeline, killing whatever is running,
+    /// if it takes longer than this many seconds
+    #[clap(long)]
+    pub pipeline_timeout_sec: Option<u64>,
+
+    /// Kill `shrinkwrap run` (and the FVP) and fail the run if it's still
+    /// running after this many seconds, e.g. because the guest hung
+    #[clap(long)]
+    pub timeout_sec: Option<u64>,
+
+    /// Extra argument to append to `shrinkwrap run`, after everything else so
+    /// it can override earlier ones (repeatable, e.g. `--run-arg --dry-run`)
+    #[clap(long, allow_hyphen_values = true)]
+    pub run_arg: Vec<String>,
+
+    /// Log every command the install/build/run would execute, without
+    /// running it. Output dirs are still created and inputs still checked.
+    #[clap(long)]
+    pub dry_run: bool,
+
+    /// Only boot the guest until it reaches the boot marker, then tear down.
+    /// Skips the TMK injection; intended as a fast "is the stack working" gate.
+    #[clap(long)]
+    pub smoke_test: bool,
+
+    /// Fail the pipeline if any test in the run log reports FAIL. The results
+    /// are summarized to <dir>/cca-report.json either way.
+    #[clap(long)]
+    pub fail_on_test_failure: bool,
+
+    /// Inject into rootfs.ext2 with debugfs (from e2fsprogs) instead of
+    /// loop-mounting it with sudo, e.g. in unprivileged containers
+    #[clap(long, conflicts_with = "smoke_test")]
+    pub no_sudo_mount: bool,
+
+    /// Container runtime to check and resize rootfs.ext2 in (e.g. podman),
+    /// instead of the first of docker and podman found on PATH
+    #[clap(long)]
+    pub container_runtime: Option<String>,
+
+    /// Dir of the rootfs to inject the TMK binaries and kernel into,
+    /// relative to its root
+    #[clap(long, default_value = "cca", conflicts_with = "smoke_test")]
+    pub inject_dir: String,
+
+    /// Extra host file to inject into the rootfs (repeatable), as the file
+    /// name DEST in the inject dir, alongside the TMK binaries and kernel
+    #[clap(long, value_name = "SRC=DEST", conflicts_with = "smoke_test")]
+    pub inject: Vec<String>,
+
+    /// Save a copy of the rootfs with the TMK binaries injected to this path
+    /// (e.g. to publish it as a CI artifact), recorded in <dir>/artifacts.json
+    #[clap(long)]
+    pub rootfs_out: Option<PathBuf>,
+
+    /// Compress the rootfs saved with --rootfs-out, appending .gz/.zst to its
+    /// path. Consumers must decompress it before use; artifacts.json records
+    /// the uncompressed sha256 to verify it against.
+    #[clap(long, value_enum, default_value = "none", requires = "rootfs_out")]
+    pub compress_rootfs: RootfsCompressionCli,
+
+    /// Run the TMK suite this many times (sharing one install and build) and
+    /// report how often each test failed, to tell flaky tests from real
+    /// regressions
+    #[clap(long, default_value_t = 1, conflicts_with = "smoke_test")]
+    pub repeat: u32,
+
+    /// Fail the run, rather than warn, if the host kernel and the TMK
+    /// binaries look like they were built for incompatible targets
+    #[clap(long)]
+    pub strict_toolchain_check: bool,
+
+    /// Instead of running the TMK suite, boot the guest this many times and
+    /// report min/median/max wall-clock time from launch to the boot marker,
+    /// to track boot-time regressions
+    #[clap(long, value_name = "ITERATIONS", conflicts_with_all = ["smoke_test", "repeat"])]
+    pub bench_boot: Option<u32>,
+
+    /// Also write the --bench-boot results to this file, as JSON
+    #[clap(long, requires = "bench_boot")]
+    pub metrics_file: Option<PathBuf>,
+
+    /// Only inspect an existing --dir and report whether it is in a runnable
+    /// state (repos, toolchain, kernel Image, TMK binaries, venv), without
+    /// modifying it. Fails if the dir isn't usable.
+    #[clap(long)]
+    pub check: bool,
+
+    /// After installing, list the overlay YAMLs available in the shrinkwrap
+    /// config dir (including those copied from cca_config), then exit
+    #[clap(long)]
+    pub list_overlays: bool,
+
+    /// After installing, start an interactive $SHELL in --dir with the
+    /// shrinkwrap venv, PATH, ARCH and CROSS_COMPILE set up, then exit once
+    /// the shell does. Only supported on the local backend.
+    #[clap(long, conflicts_with_all = ["check", "list_overlays"])]
+    pub interactive_shell: bool,
+
+    /// Build and run each configuration (platform, overlays, btvars, rtvars)
+    /// listed in this YAML file, in its own <dir>/matrix/<name> working dir,
+    /// on top of a single install in --dir, then write a consolidated report
+    /// to <dir>/matrix/matrix-report.json. A failed configuration is recorded
+    /// in the report without stopping the others. Ignores --platform.
+    #[clap(
+        long,
+        conflicts_with_all = [
+            "overlay", "btvar", "check", "list_overlays", "interactive_shell", "watch", "rootfs_out",
+            "metrics_file"
+        ]
+    )]
+    pub matrix: Option<PathBuf>,
+
     /// Verbose pipeline output
     #[clap(long)]
     pub verbose: bool,
@@ -56,10 +782,107 @@ fn into_pipeline(self, backend_hint: PipelineBackendHint) -> anyhow::Result<Pipe
             platform,
             overlay,
             btvar,
+            build_arg,
             rootfs,
+            rootfs_sha256,
+            rootfs_variant,
             rtvar,
             install_missing_deps,
+            setup_docker_group,
             update_shrinkwrap_repo,
+            kernel_update_policy,
+            tmk_update_policy,
+            shrinkwrap_update_policy,
+            cca_config_update_policy,
+            git_credential_helper,
+            allow_reset,
+            clone_retries,
+            shallow_clones,
+            kernel_clone_depth,
+            kernel_worktree_base,
+            toolchain_version,
+            toolchain_flavor,
+            toolchain_url,
+            toolchain_sha256,
+            extra_kernel_config,
+            disable_config,
+            kernel_repo,
+            kernel_branch,
+            tmk_repo,
+            tmk_branch,
+            cca_config_repo,
+            verify_signatures,
+            gpg_keyring,
+            build_id_file,
+            redis_cache_url,
+            shrinkwrap_action,
+            retry_failed_components,
+            build_output,
+            component_timeout,
+            watch,
+            force_build,
+            no_artifact_index,
+            cleanup_before_build,
+            cleanup_logs,
+            clean,
+            yes,
+            skip_preflight,
+            package,
+            force_package,
+            check_includes,
+            pre_validate,
+            tmk_workspace,
+            kernel_cache_dir,
+            kernel_image,
+            rebuild_kernel,
+            module_sign_key,
+            ephemeral_module_sign_key,
+            auto_reduce_kernel_jobs,
+            jobs,
+            tmk_binary,
+            tmk_vmm_target,
+            no_parallel_builds,
+            gen_compile_commands,
+            no_cca_config,
+            python_interpreter,
+            realm_vcpus,
+            realm_vcpu_affinity,
+            guest_cores,
+            guest_host,
+            ninep_share,
+            enable_kaslr,
+            disable_kaslr,
+            kernel_cmdline,
+            balloon_initial_mb,
+            balloon_max_mb,
+            enable_vmbus,
+            vmbus_channels,
+            mshv_vtl,
+            rmm_source_repo,
+            rmm_source_branch,
+            rmm_build_script,
+            rmm_output_binary,
+            required_tfa_version,
+            pipeline_timeout_sec,
+            timeout_sec,
+            run_arg,
+            dry_run,
+            smoke_test,
+            fail_on_test_failure,
+            no_sudo_mount,
+            container_runtime,
+            inject_dir,
+            inject,
+            rootfs_out,
+            compress_rootfs,
+            repeat,
+            strict_toolchain_check,
+            bench_boot,
+            metrics_file,
+            check,
+            list_overlays,
+            interactive_shell,
+            matrix,
             verbose,
         } = self;
 
@@ -74,21 +897,80 @@ fn into_pipeline(self, backend_hint: PipelineBackendHint) -> anyhow::Result<Pipe
 
         // Convert dir to absolute path to ensure consistency across jobs
         // Relative paths are resolved from the repository root
-        let dir = std::fs::canonicalize(&dir)
-            .or_else(|_| {
-                // If dir doesn't exist yet, make it absolute relative to repo root
-                let abs = if dir.is_absolute() {
-                    dir.clone()
-                } else {
-                    crate::repo_root().join(&dir)
-                };
-                Ok::<_, anyhow::Error>(abs)
-            })?;
+        let dir = std::fs::canonicalize(&dir).or_else(|_| {
+            // If dir doesn't exist yet, make it absolute relative to repo root
+            let abs = if dir.is_absolute() {
+                dir.clone()
+            } else {
+                crate::repo_root().join(&dir)
+            };
+            Ok::<_, anyhow::Error>(abs)
+        })?;
+
+        let tmk_workspace = tmk_workspace.map(std::path::absolute).transpose()?;
+
+        let mut repos = InstallRepos::default();
+        if let Some(url) = kernel_repo {
+            repos.kernel.url = url;
+        }
+        if let Some(branch) = kernel_branch {
+            repos.kernel.branch = Some(branch);
+        }
+        if let Some(url) = tmk_repo {
+            repos.tmk.url = url;
+        }
+        if let Some(branch) = tmk_branch {
+            repos.tmk.branch = Some(branch);
+        }
+        if let Some(url) = cca_config_repo {
+            repos.cca_config.url = url;
+        }
+
+        let repo_update_policies: HashMap<String, RepoUpdatePolicy> = [
+            (KERNEL_REPO_NAME, kernel_update_policy),
+            (TMK_REPO_NAME, tmk_update_policy),
+            (SHRINKWRAP_REPO_NAME, shrinkwrap_update_policy),
+            (CCA_CONFIG_REPO_NAME, cca_config_update_policy),
+        ]
+        .into_iter()
+        .filter_map(|(name, policy)| Some((name.to_string(), policy?.into())))
+        .collect();
+        let matrix = matrix.map(std::path::absolute).transpose()?;
+        let kernel_cache_dir = kernel_cache_dir.map(std::path::absolute).transpose()?;
+        let module_sign_key = module_sign_key.map(std::path::absolute).transpose()?;
+        let kernel_image = kernel_image.map(std::path::absolute).transpose()?;
+        let gpg_keyring = gpg_keyring.map(std::path::absolute).transpose()?;
+        let build_id_file = build_id_file.map(std::path::absolute).transpose()?;
+        let rootfs_out = rootfs_out.map(std::path::absolute).transpose()?;
+        let metrics_file = metrics_file.map(std::path::absolute).transpose()?;
 
         // Put Shrinkwrap repo under the pipeline working dir, so it's self-contained.
         let shrinkwrap_dir = dir.join("shrinkwrap");
         let shrinkwrap_config_dir = shrinkwrap_dir.join("config");
 
+        if check {
+            pipeline
+                .new_job(
+                    FlowPlatform::host(backend_hint),
+                    FlowArch::host(backend_hint),
+                    "cca-fvp: check working dir",
+                )
+                .dep_on(
+                    |ctx| flowey_lib_hvlite::_jobs::local_check_shrinkwrap_dir::Params {
+                        shrinkwrap_dir: shrinkwrap_dir.clone(),
+                        tmk_cargo_workspace: tmk_workspace.clone(),
+                        tmk_vmm_target: tmk_vmm_target.into(),
+                        toolchain_flavor: toolchain_flavor.into(),
+                        tmk_binaries: tmk_binary.clone(),
+                        check_cca_config: !no_cca_config,
+                        check_kernel_repo: kernel_image.is_none(),
+                        done: ctx.new_done_handle(),
+                    },
+                )
+                .finish();
+            return Ok(pipeline);
+        }
+
         // Helper to resolve platform/overlay paths:
         // - Absolute paths: use as-is
         // - Simple filenames (no '/'): resolve to <dir>/shrinkwrap/config/
@@ -113,7 +995,8 @@ fn into_pipeline(self, backend_hint: PipelineBackendHint) -> anyhow::Result<Pipe
                 if p_str.starts_with(dir_prefix) || p_str.starts_with(&alt_dir_prefix) {
                     // Valid: path starts with --dir prefix
                     // Strip the prefix and reconstruct using the canonical dir
-                    let stripped = p_str.strip_prefix(dir_prefix)
+                    let stripped = p_str
+                        .strip_prefix(dir_prefix)
                         .or_else(|| p_str.strip_prefix(alt_dir_prefix.as_str()))
                         .unwrap()
                         .trim_start_matches('/');
@@ -124,7 +1007,10 @@ fn into_pipeline(self, backend_hint: PipelineBackendHint) -> anyhow::Result<Pipe
                     anyhow::bail!(
                         "Relative path for {} must start with the --dir value ({}). Got: {}. \
                          Either use an absolute path, a simple filename, or a relative path starting with '{}/'.",
-                        arg_name, original_dir.display(), p.display(), original_dir_str
+                        arg_name,
+                        original_dir.display(),
+                        p.display(),
+                        original_dir_str
                     )
                 }
             }
@@ -132,7 +1018,7 @@ fn into_pipeline(self, backend_hint: PipelineBackendHint) -> anyhow::Result<Pipe
 
         // Apply defaults for options not provided by the user
         let overlay = if overlay.is_empty() {
-            vec![PathBuf::from("buildroot.yaml"), PathBuf::from("planes.yaml")]
+            default_overlays()
         } else {
             overlay
         };
@@ -143,121 +1029,710 @@ fn into_pipeline(self, backend_hint: PipelineBackendHint) -> anyhow::Result<Pipe
             btvar
         };
 
-        let rootfs = rootfs.unwrap_or_else(|| {
-            // First try SHRINKWRAP_PACKAGE env var, then HOME env var
-            let base_path = std::env::var("SHRINKWRAP_PACKAGE")
-                .or_else(|_| std::env::var("HOME").map(|h| format!("{}/.shrinkwrap/package", h)))
-                .expect("Either SHRINKWRAP_PACKAGE or HOME environment variable must be set");
-            PathBuf::from(format!("{}/cca-3world/rootfs.ext2", base_path))
-        });
+        let (rootfs, rootfs_download) = match rootfs {
+            Some(url) if url.starts_with("http://") || url.starts_with("https://") => {
+                let file_name = url
+                    .split(['?', '#'])
+                    .next()
+                    .and_then(|path| path.rsplit('/').next())
+                    .filter(|name| !name.is_empty())
+                    .unwrap_or("rootfs.ext2")
+                    .to_string();
+                let download = RootfsDownload {
+                    url,
+                    sha256: rootfs_sha256,
+                };
+                (dir.join("rootfs").join(file_name), Some(download))
+            }
+            Some(path) => {
+                if rootfs_sha256.is_some() {
+                    anyhow::bail!("--rootfs-sha256 requires --rootfs to be an http(s) URL");
+                }
+                if matrix.is_some() {
+                    anyhow::bail!(
+                        "--matrix configurations can't share a local --rootfs, pass an http(s) URL instead"
+                    );
+                }
+                (PathBuf::from(path), None)
+            }
+            None => {
+                // First try SHRINKWRAP_PACKAGE env var, then HOME env var
+                let base_path = std::env::var("SHRINKWRAP_PACKAGE")
+                    .or_else(|_| {
+                        std::env::var("HOME").map(|h| format!("{}/.shrinkwrap/package", h))
+                    })
+                    .expect("Either SHRINKWRAP_PACKAGE or HOME environment variable must be set");
+                (
+                    PathBuf::from(format!("{}/cca-3world/rootfs.ext2", base_path)),
+                    None,
+                )
+            }
+        };
 
         // Resolve platform YAML path
         let platform = resolve_config_path(platform, "--platform")?;
 
         // Resolve overlay YAML paths
-        let overlay: Vec<PathBuf> = overlay.into_iter()
+        let overlay: Vec<PathBuf> = overlay
+            .into_iter()
             .map(|p| resolve_config_path(p, "--overlay"))
             .collect::<anyhow::Result<Vec<_>>>()?;
 
-        // Create separate jobs to ensure proper ordering
-        let install_job = pipeline
-            .new_job(
-                FlowPlatform::host(backend_hint),
-                FlowArch::host(backend_hint),
-                "cca-fvp: install shrinkwrap",
-            )
-            .dep_on(|_| flowey_lib_hvlite::_jobs::cfg_versions::Request::Init)
-            .dep_on(|_| flowey_lib_hvlite::_jobs::cfg_hvlite_reposource::Params {
-                hvlite_repo_source: openvmm_repo.clone(),
+        let uses_planes = uses_planes_overlay(&overlay);
+        if no_cca_config && uses_planes {
+            log::warn!(
+                "--no-cca-config was given, but a planes overlay was requested; planes.yaml will not be installed"
+            );
+        }
+        let install_cca_config = uses_planes && !no_cca_config;
+
+        let rootfs_variants = rootfs_variant
+            .iter()
+            .map(|variant| {
+                let (name, path) = variant.split_once('=').ok_or_else(|| {
+                    anyhow::anyhow!("invalid --rootfs-variant '{}', expected NAME=PATH", variant)
+                })?;
+                Ok((name.to_string(), std::path::absolute(path)?))
             })
-            .dep_on(|_| flowey_lib_hvlite::_jobs::cfg_common::Params {
-                local_only: Some(flowey_lib_hvlite::_jobs::cfg_common::LocalOnlyParams {
-                    interactive: true,
-                    auto_install: install_missing_deps,
-                    force_nuget_mono: false,
-                    external_nuget_auth: false,
-                    ignore_rust_version: true,
-                }),
-                verbose: ReadVar::from_static(verbose),
-                locked: false,
-                deny_warnings: false,
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        // git resolves the worktree dir relative to this one
+        let kernel_worktree_base = kernel_worktree_base.map(std::path::absolute).transpose()?;
+
+        let extra_injections = inject
+            .iter()
+            .map(|injection| {
+                let (src, dest) = injection.split_once('=').ok_or_else(|| {
+                    anyhow::anyhow!("invalid --inject '{}', expected SRC=DEST", injection)
+                })?;
+                Ok((std::path::absolute(src)?, dest.to_string()))
             })
-            .dep_on(|ctx| flowey_lib_hvlite::_jobs::local_install_shrinkwrap::Params {
-                shrinkwrap_dir: shrinkwrap_dir.clone(),
-                do_installs: install_missing_deps,
-                update_repo: update_shrinkwrap_repo,
-                done: ctx.new_done_handle(),
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let etc_hosts_entries: Vec<(String, String)> = guest_host
+            .chunks_exact(2)
+            .map(|entry| (entry[0].clone(), entry[1].clone()))
+            .collect();
+
+        let ninep_shares = ninep_share
+            .iter()
+            .map(|share| {
+                let (spec, readonly) = match share.strip_suffix(":ro") {
+                    Some(spec) => (spec, true),
+                    None => (share.as_str(), false),
+                };
+                let [mount_tag, host_path, mount_point] = spec
+                    .splitn(3, ':')
+                    .collect::<Vec<_>>()
+                    .try_into()
+                    .map_err(|_| {
+                        anyhow::anyhow!(
+                            "invalid --9p-share '{}', expected TAG:HOST_PATH:GUEST_PATH",
+                            share
+                        )
+                    })?;
+                Ok(flowey_lib_hvlite::_jobs::local_shrinkwrap_run::NinePShare {
+                    host_path: std::path::absolute(host_path)?,
+                    mount_tag: mount_tag.to_string(),
+                    mount_point: PathBuf::from(mount_point),
+                    readonly,
+                })
             })
-            .finish();
+            .collect::<anyhow::Result<Vec<_>>>()?;
 
-        let build_job = pipeline
-            .new_job(
-                FlowPlatform::host(backend_hint),
-                FlowArch::host(backend_hint),
-                "cca-fvp: shrinkwrap build",
+        let kaslr_enabled = match (enable_kaslr, disable_kaslr) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _ => None,
+        };
+
+        let vmbus_config = enable_vmbus.then_some(
+            flowey_lib_hvlite::_jobs::local_shrinkwrap_run::VmbusConfig {
+                enable_vmbus,
+                vmbus_channels,
+                mshv_vtl,
+            },
+        );
+
+        let rmm_source = rmm_source_repo.map(|repo_url| {
+            flowey_lib_hvlite::_jobs::local_shrinkwrap_run::RmmSourceConfig {
+                repo_url,
+                branch: rmm_source_branch,
+                build_script: rmm_build_script,
+                output_binary: rmm_output_binary,
+            }
+        });
+
+        // Wipes the working dir before anything is cloned or built
+        let clean_job = if clean {
+            if crate::repo_root().starts_with(&dir) {
+                anyhow::bail!(
+                    "refusing to --clean {}, it contains the repo",
+                    dir.display()
+                );
+            }
+            Some(
+                pipeline
+                    .new_job(
+                        FlowPlatform::host(backend_hint),
+                        FlowArch::host(backend_hint),
+                        "cca-fvp: clean working dir",
+                    )
+                    .dep_on(
+                        |ctx| flowey_lib_hvlite::_jobs::local_clean_cca_dir::Params {
+                            dir: dir.clone(),
+                            yes,
+                            dry_run: ReadVar::from_static(dry_run),
+                            done: ctx.new_done_handle(),
+                        },
+                    )
+                    .finish(),
             )
-            .dep_on(|_| flowey_lib_hvlite::_jobs::cfg_versions::Request::Init)
-            .dep_on(|_| flowey_lib_hvlite::_jobs::cfg_hvlite_reposource::Params {
-                hvlite_repo_source: openvmm_repo.clone(),
-            })
-            .dep_on(|_| flowey_lib_hvlite::_jobs::cfg_common::Params {
-                local_only: Some(flowey_lib_hvlite::_jobs::cfg_common::LocalOnlyParams {
-                    interactive: true,
-                    auto_install: install_missing_deps,
-                    force_nuget_mono: false,
-                    external_nuget_auth: false,
-                    ignore_rust_version: true,
-                }),
-                verbose: ReadVar::from_static(verbose),
-                locked: false,
-                deny_warnings: false,
-            })
-            .dep_on(|ctx| flowey_lib_hvlite::_jobs::local_shrinkwrap_build::Params {
-                out_dir: dir.clone(),
-                shrinkwrap_dir: shrinkwrap_dir.clone(),
-                platform_yaml: platform.clone(),
-                overlays: overlay.clone(),
-                btvars: btvar.clone(),
-                done: ctx.new_done_handle(),
-            })
-            .finish();
+        } else {
+            None
+        };
+
+        // The install job, and the build and run jobs of one configuration.
+        // A plain run has a single configuration, and a matrix one per entry,
+        // all on top of the same install.
+        let add_install_job = |pipeline: &mut Pipeline, install_cca_config: bool| {
+            let install_job = pipeline
+                .new_job(
+                    FlowPlatform::host(backend_hint),
+                    FlowArch::host(backend_hint),
+                    "cca-fvp: install shrinkwrap",
+                )
+                .dep_on(|_| flowey_lib_hvlite::_jobs::cfg_versions::Request::Init)
+                .dep_on(
+                    |_| flowey_lib_hvlite::_jobs::cfg_hvlite_reposource::Params {
+                        hvlite_repo_source: openvmm_repo.clone(),
+                    },
+                )
+                .dep_on(|_| flowey_lib_hvlite::_jobs::cfg_common::Params {
+                    local_only: Some(flowey_lib_hvlite::_jobs::cfg_common::LocalOnlyParams {
+                        interactive: true,
+                        auto_install: install_missing_deps,
+                        force_nuget_mono: false,
+                        external_nuget_auth: false,
+                        ignore_rust_version: true,
+                    }),
+                    verbose: ReadVar::from_static(verbose),
+                    locked: false,
+                    deny_warnings: false,
+                })
+                .dep_on(
+                    |ctx| flowey_lib_hvlite::_jobs::local_install_shrinkwrap::Params {
+                        shrinkwrap_dir: shrinkwrap_dir.clone(),
+                        do_installs: install_missing_deps,
+                        setup_docker_group,
+                        update_repo: update_shrinkwrap_repo,
+                        repo_update_policies: repo_update_policies.clone(),
+                        tmk_cargo_workspace: tmk_workspace.clone(),
+                        kernel_cache_dir: kernel_cache_dir.clone(),
+                        prebuilt_kernel_image: kernel_image.clone(),
+                        force_kernel_rebuild: rebuild_kernel,
+                        module_sign_key: module_sign_key.clone(),
+                        ephemeral_module_sign_key,
+                        auto_reduce_kernel_jobs,
+                        kernel_build_jobs: jobs,
+                        tmk_binaries: tmk_binary.clone(),
+                        tmk_vmm_target: tmk_vmm_target.into(),
+                        parallel_builds: !no_parallel_builds,
+                        gen_compile_commands,
+                        install_cca_config,
+                        python_interpreter: python_interpreter.clone(),
+                        pipeline_timeout_sec,
+                        pipeline_lock: Some(dir.join(PIPELINE_LOCK_FILE)),
+                        git_credential_helper: git_credential_helper.clone(),
+                        allow_reset,
+                        max_clone_retries: clone_retries,
+                        shallow_clones,
+                        kernel_clone_depth: (kernel_clone_depth > 0).then_some(kernel_clone_depth),
+                        kernel_worktree_base: kernel_worktree_base.clone(),
+                        toolchain_flavor: toolchain_flavor.into(),
+                        toolchain_version: toolchain_version.clone(),
+                        toolchain_url: toolchain_url.clone(),
+                        toolchain_sha256: toolchain_sha256.clone(),
+                        extra_kernel_configs: extra_kernel_config.clone(),
+                        disable_kernel_configs: disable_config.clone(),
+                        repos: repos.clone(),
+                        verify_signatures,
+                        gpg_keyring: gpg_keyring.clone(),
+                        dry_run: ReadVar::from_static(dry_run),
+                        done: ctx.new_done_handle(),
+                    },
+                )
+                .finish();
+            if let Some(clean_job) = &clean_job {
+                pipeline.non_artifact_dep(&install_job, clean_job);
+            }
+            install_job
+        };
+
+        // Removes stale artifacts from the shrinkwrap build/package dirs of
+        // each platform (built in the given state dir, or shrinkwrap's default
+        // dirs for `None`) and from the TMK target dir before the install job
+        let add_cleanup_job =
+            |pipeline: &mut Pipeline, platforms: Vec<(PathBuf, Option<PathBuf>)>| {
+                pipeline
+                    .new_job(
+                        FlowPlatform::host(backend_hint),
+                        FlowArch::host(backend_hint),
+                        "cca-fvp: clean up build artifacts",
+                    )
+                    .dep_on(
+                        |ctx| flowey_lib_hvlite::_jobs::local_cleanup_cca_build::Params {
+                            out_dir: dir.clone(),
+                            platforms,
+                            artifact_dirs: vec![
+                                tmk_workspace
+                                    .clone()
+                                    .unwrap_or_else(|| dir.join(TMK_DIR))
+                                    .join("target"),
+                            ],
+                            keep_logs: !cleanup_logs,
+                            dry_run: ReadVar::from_static(dry_run),
+                            done: ctx.new_done_handle(),
+                        },
+                    )
+                    .finish()
+            };
+
+        let add_build_job = |pipeline: &mut Pipeline,
+                             job_label: &str,
+                             artifact_suffix: &str,
+                             config: &RunConfig| {
+            // The resolved build/run invocations are published as artifacts, so a
+            // CI run can be reproduced locally from its artifacts alone
+            let (publish_build_repro, _use_build_repro) =
+                pipeline.new_artifact(format!("cca-fvp-build-repro{}", artifact_suffix));
+
+            pipeline
+                .new_job(
+                    FlowPlatform::host(backend_hint),
+                    FlowArch::host(backend_hint),
+                    format!("cca-fvp{}: shrinkwrap build", job_label),
+                )
+                .dep_on(|_| flowey_lib_hvlite::_jobs::cfg_versions::Request::Init)
+                .dep_on(
+                    |_| flowey_lib_hvlite::_jobs::cfg_hvlite_reposource::Params {
+                        hvlite_repo_source: openvmm_repo.clone(),
+                    },
+                )
+                .dep_on(|_| flowey_lib_hvlite::_jobs::cfg_common::Params {
+                    local_only: Some(flowey_lib_hvlite::_jobs::cfg_common::LocalOnlyParams {
+                        interactive: true,
+                        auto_install: install_missing_deps,
+                        force_nuget_mono: false,
+                        external_nuget_auth: false,
+                        ignore_rust_version: true,
+                    }),
+                    verbose: ReadVar::from_static(verbose),
+                    locked: false,
+                    deny_warnings: false,
+                })
+                .dep_on(
+                    |ctx| flowey_lib_hvlite::_jobs::local_shrinkwrap_build::Params {
+                        out_dir: config.dir.clone(),
+                        shrinkwrap_dir: shrinkwrap_dir.clone(),
+                        platform_yaml: config.platform.clone(),
+                        overlays: config.overlays.clone(),
+                        btvars: config.btvars.clone(),
+                        extra_args: build_arg.clone(),
+                        action: shrinkwrap_action.into(),
+                        retry_failed_components,
+                        output_mode: build_output.into(),
+                        component_build_timeout_sec: component_timeout,
+                        repro_dir: Some(ctx.publish_artifact(publish_build_repro)),
+                        watch,
+                        check_includes,
+                        pre_validate,
+                        pipeline_timeout_sec,
+                        build_id_file: build_id_file.clone(),
+                        redis_cache: redis_cache_url.clone().map(|url| {
+                            flowey_lib_hvlite::_jobs::local_shrinkwrap_build::RedisCacheConfig {
+                                url,
+                                key_prefix: "cca-fvp-build".into(),
+                            }
+                        }),
+                        shrinkwrap_state_dir: config.shrinkwrap_state_dir.clone(),
+                        result_file: config.result_file.clone(),
+                        // Cleaning up removes the packaged build
+                        force: force_build || cleanup_before_build,
+                        emit_artifact_index: !no_artifact_index,
+                        skip_preflight,
+                        dry_run: ReadVar::from_static(dry_run),
+                        done: ctx.new_done_handle(),
+                    },
+                )
+                .finish()
+        };
+
+        let add_run_job = |pipeline: &mut Pipeline,
+                           job_label: &str,
+                           artifact_suffix: &str,
+                           config: &RunConfig| {
+            let (publish_run_repro, _use_run_repro) =
+                pipeline.new_artifact(format!("cca-fvp-run-repro{}", artifact_suffix));
+
+            pipeline
+                .new_job(
+                    FlowPlatform::host(backend_hint),
+                    FlowArch::host(backend_hint),
+                    format!("cca-fvp{}: shrinkwrap run", job_label),
+                )
+                .dep_on(|_| flowey_lib_hvlite::_jobs::cfg_versions::Request::Init)
+                .dep_on(
+                    |_| flowey_lib_hvlite::_jobs::cfg_hvlite_reposource::Params {
+                        hvlite_repo_source: openvmm_repo.clone(),
+                    },
+                )
+                .dep_on(|_| flowey_lib_hvlite::_jobs::cfg_common::Params {
+                    local_only: Some(flowey_lib_hvlite::_jobs::cfg_common::LocalOnlyParams {
+                        interactive: true,
+                        auto_install: install_missing_deps,
+                        force_nuget_mono: false,
+                        external_nuget_auth: false,
+                        ignore_rust_version: true,
+                    }),
+                    verbose: ReadVar::from_static(verbose),
+                    locked: false,
+                    deny_warnings: false,
+                })
+                .dep_on(
+                    |ctx| flowey_lib_hvlite::_jobs::local_shrinkwrap_run::Params {
+                        out_dir: config.dir.clone(),
+                        shrinkwrap_dir: shrinkwrap_dir.clone(),
+                        platform_yaml: config.platform.clone(),
+                        rootfs_path: config.rootfs.clone(),
+                        rootfs_download: config.rootfs_download.clone(),
+                        rootfs_variants: rootfs_variants.clone(),
+                        tmk_cargo_workspace: tmk_workspace.clone(),
+                        tmk_binaries: tmk_binary.clone(),
+                        tmk_vmm_target: tmk_vmm_target.into(),
+                        inject_dir: inject_dir.clone(),
+                        extra_injections: extra_injections.clone(),
+                        rtvars: config.rtvars.clone(),
+                        extra_args: run_arg.clone(),
+                        smoke_test,
+                        use_debugfs: no_sudo_mount,
+                        container_runtime: container_runtime.clone(),
+                        timeout: timeout_sec.map(Duration::from_secs),
+                        realm_vcpu_count: realm_vcpus,
+                        realm_vcpu_affinity: realm_vcpu_affinity.clone(),
+                        guest_cores,
+                        etc_hosts_entries: etc_hosts_entries.clone(),
+                        ninep_shares: ninep_shares.clone(),
+                        kaslr_enabled,
+                        kernel_cmdline: kernel_cmdline.clone(),
+                        balloon_initial_mb,
+                        balloon_max_mb,
+                        vmbus_config: vmbus_config.clone(),
+                        rmm_source: rmm_source.clone(),
+                        required_tfa_version: required_tfa_version.clone(),
+                        rootfs_out: rootfs_out.clone(),
+                        rootfs_compression: compress_rootfs.into(),
+                        repeat,
+                        strict_toolchain_check,
+                        bench_boot,
+                        metrics_file: metrics_file.clone(),
+                        shrinkwrap_state_dir: config.shrinkwrap_state_dir.clone(),
+                        result_file: config.result_file.clone(),
+                        repro_dir: Some(ctx.publish_artifact(publish_run_repro)),
+                        pipeline_timeout_sec,
+                        git_credential_helper: git_credential_helper.clone(),
+                        max_clone_retries: clone_retries,
+                        verify_signatures,
+                        gpg_keyring: gpg_keyring.clone(),
+                        dry_run: ReadVar::from_static(dry_run),
+                        done: ctx.new_done_handle(),
+                    },
+                )
+                .finish()
+        };
+
+        // Checks the artifacts the run jobs inject before they start
+        let add_verify_job = |pipeline: &mut Pipeline| {
+            let tmk_workspace = tmk_workspace.clone().unwrap_or_else(|| dir.join(TMK_DIR));
+            let elf_binaries = [
+                ("simple_tmk", simple_tmk_path(&tmk_workspace)),
+                (
+                    "tmk_vmm",
+                    tmk_vmm_path(&tmk_workspace, tmk_vmm_target.into()),
+                ),
+            ]
+            .into_iter()
+            .filter(|(name, _)| tmk_binary_selected(&tmk_binary, name))
+            .map(|(name, path)| (name.to_string(), path))
+            .collect();
+
+            pipeline
+                .new_job(
+                    FlowPlatform::host(backend_hint),
+                    FlowArch::host(backend_hint),
+                    "cca-fvp: verify artifacts",
+                )
+                .dep_on(
+                    |ctx| flowey_lib_hvlite::_jobs::local_verify_cca_artifacts::Params {
+                        kernel_image: kernel_image_path(
+                            &dir.join(HOST_KERNEL_DIR),
+                            KERNEL_ARCH,
+                            KERNEL_IMAGE_TARGET,
+                        ),
+                        elf_binaries,
+                        dry_run: ReadVar::from_static(dry_run),
+                        done: ctx.new_done_handle(),
+                    },
+                )
+                .finish()
+        };
+
+        if let Some(matrix) = matrix {
+            let configs = load_matrix(&matrix)?;
+            let matrix_dir = dir.join("matrix");
+            let config_state_dir = |name: &str| matrix_dir.join(name).join("shrinkwrap-state");
+            // Simple filenames are shrinkwrap configs, other relative paths
+            // are relative to the matrix file
+            let resolve = |p: PathBuf| {
+                if p.is_absolute() {
+                    p
+                } else if !p.to_string_lossy().contains('/') {
+                    shrinkwrap_config_dir.join(p)
+                } else {
+                    matrix.parent().unwrap_or(Path::new(".")).join(p)
+                }
+            };
 
-        // Shrinkwrap run job
-        let run_job = pipeline
+            // Every configuration builds with the same toolchain, kernel and
+            // TMK binaries, so those are installed and verified just once
+            let install_cca_config = !no_cca_config
+                && configs.iter().any(|config| {
+                    config.overlays.is_empty() || uses_planes_overlay(&config.overlays)
+                });
+            let install_job = add_install_job(&mut pipeline, install_cca_config);
+            if cleanup_before_build {
+                let platforms = configs
+                    .iter()
+                    .map(|config| {
+                        (
+                            resolve(config.platform.clone()),
+                            Some(config_state_dir(&config.name)),
+                        )
+                    })
+                    .collect();
+                let cleanup_job = add_cleanup_job(&mut pipeline, platforms);
+                pipeline.non_artifact_dep(&install_job, &cleanup_job);
+            }
+            let verify_job = (shrinkwrap_action == ShrinkwrapActionCli::Build).then(|| {
+                let verify_job = add_verify_job(&mut pipeline);
+                pipeline.non_artifact_dep(&verify_job, &install_job);
+                verify_job
+            });
+
+            let mut results = Vec::new();
+            let mut run_jobs = Vec::new();
+            for config in configs {
+                let config_dir = matrix_dir.join(&config.name);
+                let overlays: Vec<PathBuf> = if config.overlays.is_empty() {
+                    default_overlays()
+                } else {
+                    config.overlays
+                }
+                .into_iter()
+                .map(resolve)
+                .collect();
+                let platform = resolve(config.platform);
+                let shrinkwrap_state_dir = config_state_dir(&config.name);
+
+                // Each configuration injects into its own copy of the rootfs
+                let (rootfs, rootfs_download) = match &rootfs_download {
+                    Some(download) => {
+                        let file_name = rootfs.file_name().unwrap_or_default();
+                        (
+                            config_dir.join("rootfs").join(file_name),
+                            Some(download.clone()),
+                        )
+                    }
+                    None => (
+                        shrinkwrap_package_dir(Some(&shrinkwrap_state_dir), &platform)?
+                            .join("rootfs.ext2"),
+                        None,
+                    ),
+                };
+
+                let run_config = RunConfig {
+                    dir: config_dir.clone(),
+                    platform,
+                    overlays,
+                    btvars: if config.btvars.is_empty() {
+                        btvar.clone()
+                    } else {
+                        config.btvars
+                    },
+                    rtvars: rtvar.iter().cloned().chain(config.rtvars).collect(),
+                    rootfs,
+                    rootfs_download,
+                    shrinkwrap_state_dir: Some(shrinkwrap_state_dir),
+                    result_file: Some(config_dir.join("run-result.json")),
+                };
+
+                let job_label = format!("[{}]", config.name);
+                let artifact_suffix = format!("-{}", config.name);
+                let build_job =
+                    add_build_job(&mut pipeline, &job_label, &artifact_suffix, &run_config);
+                pipeline.non_artifact_dep(&build_job, &install_job);
+                let Some(verify_job) = &verify_job else {
+                    continue;
+                };
+                let run_job = add_run_job(&mut pipeline, &job_label, &artifact_suffix, &run_config);
+                pipeline.non_artifact_dep(&run_job, &build_job);
+                pipeline.non_artifact_dep(&run_job, verify_job);
+
+                results.push((config.name, config_dir.join("run-result.json")));
+                run_jobs.push(run_job);
+            }
+
+            if !run_jobs.is_empty() {
+                let report_job = pipeline
+                    .new_job(
+                        FlowPlatform::host(backend_hint),
+                        FlowArch::host(backend_hint),
+                        "cca-fvp: matrix report",
+                    )
+                    .dep_on(|ctx| {
+                        flowey_lib_hvlite::_jobs::local_shrinkwrap_matrix_report::Params {
+                            matrix_dir: matrix_dir.clone(),
+                            results,
+                            done: ctx.new_done_handle(),
+                        }
+                    })
+                    .finish();
+                for run_job in &run_jobs {
+                    pipeline.non_artifact_dep(&report_job, run_job);
+                }
+            }
+            return Ok(pipeline);
+        }
+
+        let run_config = RunConfig {
+            dir: dir.clone(),
+            platform,
+            overlays: overlay,
+            btvars: btvar.clone(),
+            rtvars: rtvar.clone(),
+            rootfs,
+            rootfs_download,
+            shrinkwrap_state_dir: None,
+            result_file: None,
+        };
+
+        // Create separate jobs to ensure proper ordering
+        let install_job = add_install_job(&mut pipeline, install_cca_config);
+        if cleanup_before_build {
+            let cleanup_job =
+                add_cleanup_job(&mut pipeline, vec![(run_config.platform.clone(), None)]);
+            pipeline.non_artifact_dep(&install_job, &cleanup_job);
+        }
+
+        if list_overlays {
+            let list_job = pipeline
+                .new_job(
+                    FlowPlatform::host(backend_hint),
+                    FlowArch::host(backend_hint),
+                    "cca-fvp: list overlays",
+                )
+                .dep_on(
+                    |ctx| flowey_lib_hvlite::_jobs::local_list_shrinkwrap_overlays::Params {
+                        shrinkwrap_dir: shrinkwrap_dir.clone(),
+                        done: ctx.new_done_handle(),
+                    },
+                )
+                .finish();
+            pipeline.non_artifact_dep(&list_job, &install_job);
+            return Ok(pipeline);
+        }
+
+        if interactive_shell {
+            let shell_job = pipeline
+                .new_job(
+                    FlowPlatform::host(backend_hint),
+                    FlowArch::host(backend_hint),
+                    "cca-fvp: interactive shell",
+                )
+                .dep_on(
+                    |ctx| flowey_lib_hvlite::_jobs::local_shrinkwrap_shell::Params {
+                        shrinkwrap_dir: shrinkwrap_dir.clone(),
+                        done: ctx.new_done_handle(),
+                    },
+                )
+                .finish();
+            pipeline.non_artifact_dep(&shell_job, &install_job);
+            return Ok(pipeline);
+        }
+
+        let build_job = add_build_job(&mut pipeline, "", "", &run_config);
+        pipeline.non_artifact_dep(&build_job, &install_job);
+
+        // Watch mode keeps rebuilding until interrupted, and other actions
+        // don't produce anything to run (or package)
+        if package.is_some() && shrinkwrap_action != ShrinkwrapActionCli::Build {
+            anyhow::bail!("--package requires --shrinkwrap-action build");
+        }
+        if watch || shrinkwrap_action != ShrinkwrapActionCli::Build {
+            return Ok(pipeline);
+        }
+
+        // Shrinkwrap run job, once its artifacts are verified
+        let verify_job = add_verify_job(&mut pipeline);
+        let run_job = add_run_job(&mut pipeline, "", "", &run_config);
+
+        // Explicitly declare job dependencies
+        pipeline.non_artifact_dep(&verify_job, &build_job);
+        pipeline.non_artifact_dep(&run_job, &verify_job);
+
+        // Summarize the test results the run printed
+        let report_job = pipeline
             .new_job(
                 FlowPlatform::host(backend_hint),
                 FlowArch::host(backend_hint),
-                "cca-fvp: shrinkwrap run",
+                "cca-fvp: test report",
+            )
+            .dep_on(
+                |ctx| flowey_lib_hvlite::_jobs::local_generate_cca_report::Params {
+                    log_path: dir.join("logs").join("shrinkwrap-run.log"),
+                    report_path: dir.join("cca-report.json"),
+                    fail_on_test_failure,
+                    dry_run: ReadVar::from_static(dry_run),
+                    done: ctx.new_done_handle(),
+                },
             )
-            .dep_on(|_| flowey_lib_hvlite::_jobs::cfg_versions::Request::Init)
-            .dep_on(|_| flowey_lib_hvlite::_jobs::cfg_hvlite_reposource::Params {
-                hvlite_repo_source: openvmm_repo.clone(),
-            })
-            .dep_on(|_| flowey_lib_hvlite::_jobs::cfg_common::Params {
-                local_only: Some(flowey_lib_hvlite::_jobs::cfg_common::LocalOnlyParams {
-                    interactive: true,
-                    auto_install: install_missing_deps,
-                    force_nuget_mono: false,
-                    external_nuget_auth: false,
-                    ignore_rust_version: true,
-                }),
-                verbose: ReadVar::from_static(verbose),
-                locked: false,
-                deny_warnings: false,
-            })
-            .dep_on(|ctx| flowey_lib_hvlite::_jobs::local_shrinkwrap_run::Params {
-                out_dir: dir.clone(),
-                shrinkwrap_dir: shrinkwrap_dir.clone(),
-                platform_yaml: platform.clone(),
-                rootfs_path: rootfs.clone(),
-                rtvars: rtvar.clone(),
-                done: ctx.new_done_handle(),
-            })
             .finish();
+        pipeline.non_artifact_dep(&report_job, &run_job);
 
-        // Explicitly declare job dependencies
-        pipeline.non_artifact_dep(&build_job, &install_job);
-        pipeline.non_artifact_dep(&run_job, &build_job);
+        // Package the build once the run job has injected into its rootfs
+        if let Some(package) = package {
+            let output_archive = std::path::absolute(&package)?;
+            let package_job = pipeline
+                .new_job(
+                    FlowPlatform::host(backend_hint),
+                    FlowArch::host(backend_hint),
+                    "cca-fvp: shrinkwrap package",
+                )
+                .dep_on(
+                    |ctx| flowey_lib_hvlite::_jobs::local_shrinkwrap_package::Params {
+                        out_dir: dir.clone(),
+                        shrinkwrap_dir: shrinkwrap_dir.clone(),
+                        platform_yaml: run_config.platform.clone(),
+                        output_archive,
+                        shrinkwrap_state_dir: None,
+                        force: force_package,
+                        dry_run: ReadVar::from_static(dry_run),
+                        done: ctx.new_done_handle(),
+                    },
+                )
+                .finish();
+            pipeline.non_artifact_dep(&package_job, &run_job);
+        }
         Ok(pipeline)
     }
 }
@@ -0,0 +1,248 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Inspect an existing CCA FVP working dir and report whether it is in a
+//! runnable state, without modifying anything.
+
+use crate::_jobs::local_install_shrinkwrap::LLVM_BINUTILS_CROSS_COMPILE;
+use crate::_jobs::local_install_shrinkwrap::LLVM_CLANG;
+use crate::_jobs::local_install_shrinkwrap::ToolchainFlavor;
+use crate::_jobs::shrinkwrap_util::CCA_CONFIG_DIR;
+use crate::_jobs::shrinkwrap_util::HOST_KERNEL_DIR;
+use crate::_jobs::shrinkwrap_util::KERNEL_ARCH;
+use crate::_jobs::shrinkwrap_util::KERNEL_IMAGE_TARGET;
+use crate::_jobs::shrinkwrap_util::SHRINKWRAP_PYTHON_PACKAGES;
+use crate::_jobs::shrinkwrap_util::TMK_DIR;
+use crate::_jobs::shrinkwrap_util::TmkVmmTarget;
+use crate::_jobs::shrinkwrap_util::installed_arm_gnu_toolchain_dir;
+use crate::_jobs::shrinkwrap_util::is_aarch64_elf;
+use crate::_jobs::shrinkwrap_util::is_arm64_kernel_image;
+use crate::_jobs::shrinkwrap_util::kernel_image_path;
+use crate::_jobs::shrinkwrap_util::simple_tmk_path;
+use crate::_jobs::shrinkwrap_util::tmk_binary_selected;
+use crate::_jobs::shrinkwrap_util::tmk_vmm_path;
+use flowey::node::prelude::*;
+use std::path::Path;
+
+flowey_request! {
+    pub struct Params {
+        /// Directory where the shrinkwrap repo is cloned (e.g. <out_dir>/shrinkwrap)
+        pub shrinkwrap_dir: PathBuf,
+        /// Cargo workspace the TMK binaries were built in, if not the
+        /// OpenVMM-TMK clone
+        pub tmk_cargo_workspace: Option<PathBuf>,
+        /// Rust target `tmk_vmm` was built for
+        pub tmk_vmm_target: TmkVmmTarget,
+        /// Compiler the host kernel is built with, which decides the
+        /// toolchain that has to be usable
+        pub toolchain_flavor: ToolchainFlavor,
+        /// TMK binaries to check for (`simple_tmk`, `tmk_vmm`). Checks all
+        /// of them when empty.
+        pub tmk_binaries: Vec<String>,
+        /// Whether the cca_config repo should be cloned
+        pub check_cca_config: bool,
+        /// Whether the OHCL Linux Kernel repo should be cloned (it isn't
+        /// when a prebuilt kernel Image is used)
+        pub check_kernel_repo: bool,
+        pub done: WriteVar<SideEffect>,
+    }
+}
+
+new_simple_flow_node!(struct Node);
+
+/// Report the commit a cloned repo is at, or record it as missing.
+fn check_repo(
+    rt: &RustRuntimeServices<'_>,
+    repo_dir: &Path,
+    repo_name: &str,
+    problems: &mut Vec<String>,
+) {
+    if !repo_dir.join(".git").exists() {
+        problems.push(format!(
+            "{} is not cloned at {}",
+            repo_name,
+            repo_dir.display()
+        ));
+        return;
+    }
+
+    match flowey::shell_cmd!(rt, "git -C {repo_dir} rev-parse HEAD")
+        .quiet()
+        .read()
+    {
+        Ok(commit) => log::info!(
+            "{} present at {} ({})",
+            repo_name,
+            repo_dir.display(),
+            commit
+        ),
+        Err(e) => problems.push(format!(
+            "{} at {} has no valid HEAD: {}",
+            repo_name,
+            repo_dir.display(),
+            e
+        )),
+    }
+}
+
+/// Check that `path` is an AArch64 ELF binary, recording any problem.
+fn check_aarch64_binary(path: &Path, name: &str, problems: &mut Vec<String>) {
+    match is_aarch64_elf(path) {
+        Ok(true) => log::info!("{} present at {}", name, path.display()),
+        Ok(false) => problems.push(format!(
+            "{} at {} is not an AArch64 ELF binary",
+            name,
+            path.display()
+        )),
+        Err(_) => problems.push(format!("{} is missing at {}", name, path.display())),
+    }
+}
+
+impl SimpleFlowNode for Node {
+    type Request = Params;
+
+    fn imports(_ctx: &mut ImportCtx<'_>) {}
+
+    fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Result<()> {
+        let Params {
+            shrinkwrap_dir,
+            tmk_cargo_workspace,
+            tmk_vmm_target,
+            toolchain_flavor,
+            tmk_binaries,
+            check_cca_config,
+            check_kernel_repo,
+            done,
+        } = request;
+
+        ctx.emit_rust_step("check cca fvp working dir", |ctx| {
+            done.claim(ctx);
+            move |rt| {
+                let toolchain_dir = shrinkwrap_dir
+                    .parent()
+                    .ok_or_else(|| anyhow::anyhow!("shrinkwrap_dir has no parent"))?;
+                let mut problems = Vec::new();
+
+                // 1) Repos
+                let host_kernel_dir = toolchain_dir.join(HOST_KERNEL_DIR);
+                let tmk_kernel_dir = toolchain_dir.join(TMK_DIR);
+                if check_kernel_repo {
+                    check_repo(rt, &host_kernel_dir, "OHCL Linux Kernel", &mut problems);
+                }
+                check_repo(rt, &tmk_kernel_dir, "OpenVMM TMK", &mut problems);
+                check_repo(rt, &shrinkwrap_dir, "Shrinkwrap", &mut problems);
+                if check_cca_config {
+                    check_repo(
+                        rt,
+                        &toolchain_dir.join(CCA_CONFIG_DIR),
+                        "cca_config",
+                        &mut problems,
+                    );
+                }
+
+                // 2) Kernel toolchain
+                let (toolchain, tools) = match toolchain_flavor {
+                    ToolchainFlavor::ArmGnu => (
+                        "ARM GNU toolchain",
+                        vec![
+                            installed_arm_gnu_toolchain_dir(toolchain_dir)
+                                .join("bin")
+                                .join("aarch64-none-elf-gcc"),
+                        ],
+                    ),
+                    // clang still needs the GNU binutils for the target
+                    ToolchainFlavor::LlvmClang => (
+                        "LLVM toolchain",
+                        vec![
+                            PathBuf::from(LLVM_CLANG),
+                            PathBuf::from(format!("{}ld", LLVM_BINUTILS_CROSS_COMPILE)),
+                        ],
+                    ),
+                };
+                for tool in tools {
+                    match flowey::shell_cmd!(rt, "{tool} --version").quiet().read() {
+                        Ok(version) => log::info!(
+                            "{}: {}",
+                            toolchain,
+                            version.lines().next().unwrap_or_default()
+                        ),
+                        Err(_) => problems.push(format!(
+                            "{} is not usable: {} --version failed",
+                            toolchain,
+                            tool.display()
+                        )),
+                    }
+                }
+
+                // 3) Kernel Image
+                let kernel_image =
+                    kernel_image_path(&host_kernel_dir, KERNEL_ARCH, KERNEL_IMAGE_TARGET);
+                match is_arm64_kernel_image(&kernel_image) {
+                    Ok(true) => log::info!("Kernel Image present at {}", kernel_image.display()),
+                    Ok(false) => problems.push(format!(
+                        "{} does not have an arm64 Image header",
+                        kernel_image.display()
+                    )),
+                    Err(_) => problems.push(format!(
+                        "Kernel Image is missing at {}",
+                        kernel_image.display()
+                    )),
+                }
+
+                // 4) TMK binaries
+                let tmk_workspace = tmk_cargo_workspace.as_deref().unwrap_or(&tmk_kernel_dir);
+                if tmk_binary_selected(&tmk_binaries, "simple_tmk") {
+                    check_aarch64_binary(
+                        &simple_tmk_path(tmk_workspace),
+                        "simple_tmk",
+                        &mut problems,
+                    );
+                }
+                if tmk_binary_selected(&tmk_binaries, "tmk_vmm") {
+                    check_aarch64_binary(
+                        &tmk_vmm_path(tmk_workspace, tmk_vmm_target),
+                        "tmk_vmm",
+                        &mut problems,
+                    );
+                }
+
+                // 5) Python venv
+                let pip_bin = shrinkwrap_dir.join("venv").join("bin").join("pip");
+                if !pip_bin.exists() {
+                    problems.push(format!(
+                        "shrinkwrap venv is missing at {}",
+                        shrinkwrap_dir.join("venv").display()
+                    ));
+                } else if flowey::shell_cmd!(rt, "{pip_bin} show {SHRINKWRAP_PYTHON_PACKAGES...}")
+                    .quiet()
+                    .ignore_stdout()
+                    .run()
+                    .is_err()
+                {
+                    problems.push(format!(
+                        "shrinkwrap venv is missing some of the required packages: {}",
+                        SHRINKWRAP_PYTHON_PACKAGES.join(" ")
+                    ));
+                } else {
+                    log::info!("shrinkwrap venv has all required packages");
+                }
+
+                if !problems.is_empty() {
+                    for problem in &problems {
+                        log::error!("{}", problem);
+                    }
+                    anyhow::bail!(
+                        "{} is not in a runnable state ({} problem(s) found)",
+                        toolchain_dir.display(),
+                        problems.len()
+                    );
+                }
+
+                log::info!("{} is in a runnable state", toolchain_dir.display());
+                Ok(())
+            }
+        });
+
+        Ok(())
+    }
+}
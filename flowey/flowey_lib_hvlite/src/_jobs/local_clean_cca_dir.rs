@@ -0,0 +1,120 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Wipe the CCA FVP pipeline working dir (the toolchain, kernel and
+//! shrinkwrap clones, builds and logs), to start over from a broken setup.
+
+use crate::_jobs::shrinkwrap_util::PIPELINE_LOCK_FILE;
+use crate::_jobs::shrinkwrap_util::acquire_pipeline_lock;
+use flowey::node::prelude::*;
+use std::io::IsTerminal;
+use std::io::Write;
+
+flowey_request! {
+    pub struct Params {
+        /// Working dir of the CCA FVP pipeline
+        pub dir: PathBuf,
+        /// Remove everything without asking for confirmation
+        pub yes: bool,
+        /// Only log what would be removed
+        pub dry_run: ReadVar<bool>,
+        pub done: WriteVar<SideEffect>,
+    }
+}
+
+new_simple_flow_node!(struct Node);
+
+impl SimpleFlowNode for Node {
+    type Request = Params;
+
+    fn imports(_ctx: &mut ImportCtx<'_>) {}
+
+    fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Result<()> {
+        let Params {
+            dir,
+            yes,
+            dry_run,
+            done,
+        } = request;
+
+        ctx.emit_rust_step("clean cca working dir", |ctx| {
+            done.claim(ctx);
+            let dry_run = dry_run.claim(ctx);
+            move |rt| {
+                let dry_run = rt.read(dry_run);
+                if !dir.exists() {
+                    log::info!("{} does not exist, nothing to clean", dir.display());
+                    return Ok(());
+                }
+
+                // Hold the lock from here on, so a concurrent run isn't wiped
+                // out from under it. The lock file itself is kept.
+                let lock_path = dir.join(PIPELINE_LOCK_FILE);
+                if !dry_run {
+                    acquire_pipeline_lock(&lock_path)?;
+                }
+
+                let mut paths = Vec::new();
+                for entry in fs_err::read_dir(&dir)? {
+                    let path = entry?.path();
+                    if path != lock_path {
+                        paths.push(path);
+                    }
+                }
+                paths.sort();
+                if paths.is_empty() {
+                    log::info!("{} is already empty", dir.display());
+                    return Ok(());
+                }
+
+                if dry_run {
+                    for path in &paths {
+                        log::info!("DRY-RUN: rm -rf {}", path.display());
+                    }
+                    return Ok(());
+                }
+
+                log::warn!("Cleaning {} removes:", dir.display());
+                for path in &paths {
+                    log::warn!("  {}", path.display());
+                }
+                if !yes {
+                    confirm_removal(&dir)?;
+                }
+
+                for path in &paths {
+                    log::info!("Removing {}", path.display());
+                    // Don't follow symlinks out of the working dir
+                    if fs_err::symlink_metadata(path)?.is_dir() {
+                        fs_err::remove_dir_all(path)?;
+                    } else {
+                        fs_err::remove_file(path)?;
+                    }
+                }
+                log::info!("Removed {} path(s) from {}", paths.len(), dir.display());
+                Ok(())
+            }
+        });
+
+        Ok(())
+    }
+}
+
+/// Ask on the terminal whether to go ahead with removing everything under
+/// `dir`, failing unless the answer is yes.
+fn confirm_removal(dir: &Path) -> anyhow::Result<()> {
+    if !std::io::stdin().is_terminal() {
+        anyhow::bail!(
+            "not removing the contents of {} without confirmation, pass --yes to confirm",
+            dir.display()
+        );
+    }
+    print!("Remove everything listed above? [y/N] ");
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    if !matches!(input.trim(), "y" | "Y" | "yes") {
+        anyhow::bail!("aborted, {} was left untouched", dir.display());
+    }
+    Ok(())
+}
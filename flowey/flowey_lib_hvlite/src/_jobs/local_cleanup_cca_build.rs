@@ -0,0 +1,155 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Remove the FVP binary artifacts (disk images, firmware and kernel
+//! `Image`s) left in the build output dirs by earlier builds.
+
+use crate::_jobs::shrinkwrap_util::shrinkwrap_build_dir;
+use crate::_jobs::shrinkwrap_util::shrinkwrap_package_dir;
+use flowey::node::prelude::*;
+
+flowey_request! {
+    pub struct Params {
+        /// Output dir of the CCA FVP pipeline
+        pub out_dir: PathBuf,
+        /// Platform configs whose shrinkwrap build and package dirs to
+        /// remove the FVP artifacts from, each with the shrinkwrap state dir
+        /// it's built in (`None` for shrinkwrap's default dirs). Only the
+        /// platform's own subdirs are touched, as the default dirs are shared
+        /// with every other shrinkwrap config the user builds.
+        pub platforms: Vec<(PathBuf, Option<PathBuf>)>,
+        /// Other build output dirs to remove the FVP artifacts from (e.g.
+        /// the TMK cargo target dir). Dirs outside `out_dir` are refused.
+        pub artifact_dirs: Vec<PathBuf>,
+        /// Leave `<out_dir>/logs` in place
+        pub keep_logs: bool,
+        /// Only log what would be removed
+        pub dry_run: ReadVar<bool>,
+        pub done: WriteVar<SideEffect>,
+    }
+}
+
+new_simple_flow_node!(struct Node);
+
+impl SimpleFlowNode for Node {
+    type Request = Params;
+
+    fn imports(_ctx: &mut ImportCtx<'_>) {}
+
+    fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Result<()> {
+        let Params {
+            out_dir,
+            platforms,
+            artifact_dirs,
+            keep_logs,
+            dry_run,
+            done,
+        } = request;
+
+        ctx.emit_rust_step("clean up cca build artifacts", |ctx| {
+            done.claim(ctx);
+            let dry_run = dry_run.claim(ctx);
+            move |rt| {
+                let dry_run = rt.read(dry_run);
+                let mut removed = 0;
+                let mut freed = 0;
+
+                let logs_dir = out_dir.join("logs");
+                if !keep_logs && logs_dir.is_dir() {
+                    freed += dir_size(&logs_dir)?;
+                    remove(&logs_dir, dry_run)?;
+                }
+
+                let mut roots = Vec::new();
+                for (platform_yaml, state_dir) in &platforms {
+                    roots.push(shrinkwrap_build_dir(state_dir.as_deref(), platform_yaml)?);
+                    roots.push(shrinkwrap_package_dir(state_dir.as_deref(), platform_yaml)?);
+                }
+                for dir in artifact_dirs {
+                    if dir.starts_with(&out_dir) {
+                        roots.push(dir);
+                    } else {
+                        log::warn!(
+                            "Not cleaning up {}, which is outside {}",
+                            dir.display(),
+                            out_dir.display()
+                        );
+                    }
+                }
+
+                let mut pending: Vec<PathBuf> =
+                    roots.into_iter().filter(|dir| dir.is_dir()).collect();
+                while let Some(dir) = pending.pop() {
+                    for entry in fs_err::read_dir(&dir)? {
+                        let path = entry?.path();
+                        // Don't follow symlinks out of the build output dirs
+                        let metadata = fs_err::symlink_metadata(&path)?;
+                        if metadata.is_dir() {
+                            pending.push(path);
+                        } else if metadata.is_file() && is_fvp_artifact(&path) {
+                            freed += metadata.len();
+                            removed += 1;
+                            remove(&path, dry_run)?;
+                        }
+                    }
+                }
+
+                log::info!(
+                    "{} {} FVP artifact(s){}, freeing {} MiB",
+                    if dry_run { "Would remove" } else { "Removed" },
+                    removed,
+                    if keep_logs { "" } else { " and the logs dir" },
+                    freed / (1024 * 1024)
+                );
+                Ok(())
+            }
+        });
+
+        Ok(())
+    }
+}
+
+/// Disk images, firmware volumes and kernel `Image`s (including compressed
+/// ones such as `Image.gz`).
+fn is_fvp_artifact(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+    name.starts_with("Image")
+        || matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("img" | "fd")
+        )
+}
+
+/// Total size of the files under `dir`.
+fn dir_size(dir: &Path) -> anyhow::Result<u64> {
+    let mut size = 0;
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs_err::read_dir(&dir)? {
+            let path = entry?.path();
+            let metadata = fs_err::symlink_metadata(&path)?;
+            if metadata.is_dir() {
+                pending.push(path);
+            } else {
+                size += metadata.len();
+            }
+        }
+    }
+    Ok(size)
+}
+
+fn remove(path: &Path, dry_run: bool) -> anyhow::Result<()> {
+    if dry_run {
+        log::info!("DRY-RUN: rm -rf {}", path.display());
+        return Ok(());
+    }
+    if path.is_dir() {
+        fs_err::remove_dir_all(path)?;
+    } else {
+        fs_err::remove_file(path)?;
+    }
+    log::debug!("Removed {}", path.display());
+    Ok(())
+}
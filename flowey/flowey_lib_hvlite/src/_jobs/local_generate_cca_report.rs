@@ -0,0 +1,132 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Summarize the test results a CCA FVP run printed to its log into a JSON
+//! report.
+
+use flowey::node::prelude::*;
+
+/// Statuses a test result line starts with, followed by the test name.
+const TEST_STATUSES: [&str; 3] = ["PASS", "FAIL", "ERROR"];
+
+flowey_request! {
+    pub struct Params {
+        /// Log to read the test results from
+        pub log_path: PathBuf,
+        /// Where to write the JSON report
+        pub report_path: PathBuf,
+        /// Fail if any test failed
+        pub fail_on_test_failure: bool,
+        /// Only log where the report would be written
+        pub dry_run: ReadVar<bool>,
+        pub done: WriteVar<SideEffect>,
+    }
+}
+
+new_simple_flow_node!(struct Node);
+
+impl SimpleFlowNode for Node {
+    type Request = Params;
+
+    fn imports(_ctx: &mut ImportCtx<'_>) {}
+
+    fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Result<()> {
+        let Params {
+            log_path,
+            report_path,
+            fail_on_test_failure,
+            dry_run,
+            done,
+        } = request;
+
+        ctx.emit_rust_step("generate cca test report", |ctx| {
+            done.claim(ctx);
+            let dry_run = dry_run.claim(ctx);
+            move |rt| {
+                if rt.read(dry_run) {
+                    log::info!(
+                        "DRY-RUN: summarize the test results in {} into {}",
+                        log_path.display(),
+                        report_path.display()
+                    );
+                    return Ok(());
+                }
+                // Smoke tests and boot benchmarks don't write the run log
+                if !log_path.exists() {
+                    log::warn!(
+                        "No run log at {}, not generating a test report",
+                        log_path.display()
+                    );
+                    return Ok(());
+                }
+
+                let log = fs_err::read_to_string(&log_path)?;
+                let results: Vec<(&str, &str)> =
+                    log.lines().filter_map(parse_test_result).collect();
+                let count = |status: &str| results.iter().filter(|(s, _)| *s == status).count();
+                let failed: Vec<&str> = results
+                    .iter()
+                    .filter(|(status, _)| *status == "FAIL")
+                    .map(|(_, name)| *name)
+                    .collect();
+
+                let report = serde_json::json!({
+                    "log": log_path,
+                    "total": results.len(),
+                    "passed": count("PASS"),
+                    "failed": failed.len(),
+                    "errors": count("ERROR"),
+                    "tests": results
+                        .iter()
+                        .map(|(status, name)| serde_json::json!({ "name": name, "status": status }))
+                        .collect::<Vec<_>>(),
+                });
+                if let Some(parent) = report_path.parent() {
+                    fs_err::create_dir_all(parent)?;
+                }
+                fs_err::write(&report_path, serde_json::to_string_pretty(&report)?)?;
+
+                println!(
+                    "CCA tests: {} passed, {} failed, {} errors",
+                    count("PASS"),
+                    failed.len(),
+                    count("ERROR")
+                );
+                for name in &failed {
+                    println!("  FAIL {}", name);
+                }
+                log::info!("Wrote test report to {}", report_path.display());
+
+                if results.is_empty() {
+                    log::warn!("No test results found in {}", log_path.display());
+                }
+                if fail_on_test_failure && !failed.is_empty() {
+                    anyhow::bail!(
+                        "{} of {} tests failed: {}",
+                        failed.len(),
+                        results.len(),
+                        failed.join(", ")
+                    );
+                }
+                Ok(())
+            }
+        });
+
+        Ok(())
+    }
+}
+
+/// Parse a `PASS: <name>`, `FAIL: <name>` or `ERROR: <name>` line (possibly
+/// after a console prefix such as a timestamp) into its status and test name.
+fn parse_test_result(line: &str) -> Option<(&'static str, &str)> {
+    TEST_STATUSES.into_iter().find_map(|status| {
+        let marker = format!("{}:", status);
+        let (prefix, rest) = line.split_once(&marker)?;
+        // Only match the status as a whole word (not e.g. `BYPASS:`)
+        if prefix.ends_with(|c: char| c.is_ascii_alphanumeric() || c == '_') {
+            return None;
+        }
+        let name = rest.trim();
+        (!name.is_empty()).then_some((status, name))
+    })
+}
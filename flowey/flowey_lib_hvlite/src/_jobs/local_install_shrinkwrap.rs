@@ -1,13 +1,56 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
-//! Install Shrinkwrap and its dependencies on Ubuntu.
+//! Install Shrinkwrap and its dependencies on Ubuntu/Debian, Fedora/RHEL or
+//! Arch Linux.
 
-use flowey::node::prelude::*;
+use crate::_jobs::shrinkwrap_util::CCA_CONFIG_DIR;
+use crate::_jobs::shrinkwrap_util::HOST_KERNEL_DIR;
+use crate::_jobs::shrinkwrap_util::KERNEL_ARCH;
+use crate::_jobs::shrinkwrap_util::KERNEL_COMPILE_COMMANDS;
+use crate::_jobs::shrinkwrap_util::KERNEL_IMAGE_TARGET;
+use crate::_jobs::shrinkwrap_util::PipelinePhase;
+use crate::_jobs::shrinkwrap_util::SHRINKWRAP_PYTHON_PACKAGES;
+use crate::_jobs::shrinkwrap_util::SIMPLE_TMK_CARGO_CONFIG;
+use crate::_jobs::shrinkwrap_util::SIMPLE_TMK_TARGET;
+use crate::_jobs::shrinkwrap_util::StageTimings;
+use crate::_jobs::shrinkwrap_util::TMK_DIR;
+use crate::_jobs::shrinkwrap_util::TmkVmmTarget;
+use crate::_jobs::shrinkwrap_util::ToolchainManifest;
+use crate::_jobs::shrinkwrap_util::acquire_pipeline_lock;
+use crate::_jobs::shrinkwrap_util::arm_gnu_cross_compile_prefix;
+use crate::_jobs::shrinkwrap_util::arm_gnu_toolchain_dir;
+use crate::_jobs::shrinkwrap_util::arm_gnu_toolchain_name;
+use crate::_jobs::shrinkwrap_util::arm_gnu_toolchain_url;
+use crate::_jobs::shrinkwrap_util::check_dir_writable;
+use crate::_jobs::shrinkwrap_util::command_line;
+use crate::_jobs::shrinkwrap_util::describe_exit_status;
+use crate::_jobs::shrinkwrap_util::download_file;
+use crate::_jobs::shrinkwrap_util::enter_pipeline_phase;
+use crate::_jobs::shrinkwrap_util::find_arm_gnu_toolchains;
+use crate::_jobs::shrinkwrap_util::interactive_output;
+use crate::_jobs::shrinkwrap_util::is_arm_gnu_toolchain;
+use crate::_jobs::shrinkwrap_util::is_arm64_kernel_image;
+use crate::_jobs::shrinkwrap_util::is_sha256_digest;
+use crate::_jobs::shrinkwrap_util::kernel_image_path;
+use crate::_jobs::shrinkwrap_util::report_invalid_params;
+use crate::_jobs::shrinkwrap_util::run_or_log;
+use crate::_jobs::shrinkwrap_util::sha256_file;
+use crate::_jobs::shrinkwrap_util::simple_tmk_path;
+use crate::_jobs::shrinkwrap_util::tee_command_output;
+use crate::_jobs::shrinkwrap_util::tmk_binary_selected;
+use crate::_jobs::shrinkwrap_util::tmk_target_dir;
+use crate::_jobs::shrinkwrap_util::tmk_vmm_path;
+use crate::_jobs::shrinkwrap_util::validate_tmk_binaries;
 use flowey::node::prelude::RustRuntimeServices;
+use flowey::node::prelude::*;
+use flowey::shell::FloweyCmd;
+use sha2::Digest;
+use sha2::Sha256;
+use std::collections::HashMap;
 use std::path::Path;
+use std::time::Instant;
 
-const ARM_GNU_TOOLCHAIN_URL: &str = "https://developer.arm.com/-/media/Files/downloads/gnu/14.3.rel1/binrel/arm-gnu-toolchain-14.3.rel1-x86_64-aarch64-none-elf.tar.xz";
 const OHCL_LINUX_KERNEL_REPO: &str = "https://github.com/weiding-msft/OHCL-Linux-Kernel.git";
 const OHCL_LINUX_KERNEL_PLANE0_BRANCH: &str = "with-arm-rebased-planes";
 const OPENVMM_TMK_REPO: &str = "https://github.com/Flgodd67/openvmm.git";
@@ -15,6 +58,86 @@
 const SHRINKWRAP_REPO: &str = "https://git.gitlab.arm.com/tooling/shrinkwrap.git";
 const CCA_CONFIG_REPO: &str = "https://github.com/weiding-msft/cca_config";
 
+/// Names of the repos the install node clones, which
+/// [`Params::repo_update_policies`] is keyed by.
+pub const KERNEL_REPO_NAME: &str = "OHCL Linux Kernel";
+pub const TMK_REPO_NAME: &str = "OpenVMM TMK";
+pub const SHRINKWRAP_REPO_NAME: &str = "Shrinkwrap";
+pub const CCA_CONFIG_REPO_NAME: &str = "cca_config";
+
+/// When [`clone_or_update_repo`] updates an existing clone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RepoUpdatePolicy {
+    /// Fetch and fast-forward (or reset) on every run
+    Always,
+    /// Leave an existing clone as it is
+    Never,
+    /// Fetch, and only update the clone if the upstream has moved
+    IfBehind,
+}
+
+/// Compiler the host kernel is built with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ToolchainFlavor {
+    /// The ARM GNU toolchain, downloaded from ARM
+    #[default]
+    ArmGnu,
+    /// The distro's clang, with `aarch64-none-elf-` binutils from `PATH`
+    LlvmClang,
+}
+
+/// Compiler the kernel is built with for [`ToolchainFlavor::LlvmClang`].
+pub const LLVM_CLANG: &str = "clang-18";
+/// `CROSS_COMPILE` prefix of the binutils used alongside [`LLVM_CLANG`].
+pub const LLVM_BINUTILS_CROSS_COMPILE: &str = "aarch64-none-elf-";
+
+/// A git repo to clone, and the branch to check out (the remote's default
+/// branch if unset).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RepoSpec {
+    pub url: String,
+    pub branch: Option<String>,
+}
+
+impl RepoSpec {
+    fn new(url: &str, branch: Option<&str>) -> Self {
+        Self {
+            url: url.to_string(),
+            branch: branch.map(str::to_string),
+        }
+    }
+}
+
+/// The repos the install node clones.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InstallRepos {
+    /// OHCL host kernel, with plane0 support
+    pub kernel: RepoSpec,
+    /// OpenVMM fork the TMK binaries are built from
+    pub tmk: RepoSpec,
+    pub shrinkwrap: RepoSpec,
+    /// Where `planes.yaml` comes from
+    pub cca_config: RepoSpec,
+}
+
+impl Default for InstallRepos {
+    fn default() -> Self {
+        Self {
+            kernel: RepoSpec::new(
+                OHCL_LINUX_KERNEL_REPO,
+                Some(OHCL_LINUX_KERNEL_PLANE0_BRANCH),
+            ),
+            tmk: RepoSpec::new(OPENVMM_TMK_REPO, Some(OPENVMM_TMK_BRANCH)),
+            shrinkwrap: RepoSpec::new(SHRINKWRAP_REPO, None),
+            cca_config: RepoSpec::new(CCA_CONFIG_REPO, None),
+        }
+    }
+}
+
+/// Suffix of the SHA-256 checksum file ARM publishes next to each toolchain
+/// archive (`<sha256>  <archive name>`).
+const ARM_GNU_TOOLCHAIN_SHA256_SUFFIX: &str = ".sha256asc";
+
 const CCA_CONFIGS: &[&str] = &["CONFIG_VIRT_DRIVERS", "CONFIG_ARM_CCA_GUEST"];
 const NINEP_CONFIGS: &[&str] = &[
     "CONFIG_NET_9P",
@@ -30,6 +153,18 @@
     "CONFIG_HYPERV_VTL_MODE",
 ];
 
+/// Oldest Python shrinkwrap supports, as `(major, minor)`.
+const SHRINKWRAP_MIN_PYTHON: (u32, u32) = (3, 9);
+
+/// Minimum host RAM per kernel build job before the OOM killer becomes likely.
+const MIN_RAM_PER_KERNEL_JOB: u64 = 2 * 1024 * 1024 * 1024;
+
+/// Log of the kernel's `make` output, under `<toolchain_dir>/logs`.
+const KERNEL_BUILD_LOG: &str = "kernel-build.log";
+
+/// File next to the kernel Image recording the commit it was built from.
+const LAST_BUILT_COMMIT: &str = ".last_built_commit";
+
 flowey_request! {
     pub struct Params {
         /// Directory where shrinkwrap repo will be cloned (e.g. <out_dir>/shrinkwrap)
@@ -37,91 +172,1381 @@ pub struct Params {
         /// If true, run apt-get and pip installs (requires sudo).
         /// If false, only clones repo and writes instructions.
         pub do_installs: bool,
+        /// Create the `docker` group and add the current user to it, unless
+        /// they already are a member
+        pub setup_docker_group: bool,
         /// If true, run `git pull --ff-only` if the repo already exists.
+        /// This is the update policy of repos not in `repo_update_policies`.
         pub update_repo: bool,
+        /// Update policy of individual repos, by repo name (e.g.
+        /// [`KERNEL_REPO_NAME`])
+        pub repo_update_policies: HashMap<String, RepoUpdatePolicy>,
+        /// Cargo workspace to build the TMK binaries in, instead of the
+        /// OpenVMM-TMK clone (e.g. a fork with a different layout).
+        pub tmk_cargo_workspace: Option<PathBuf>,
+        /// Cache compiled kernel Images here, keyed by kernel commit,
+        /// effective config and toolchain version.
+        pub kernel_cache_dir: Option<PathBuf>,
+        /// Use this prebuilt kernel Image instead of cloning and building
+        /// the OHCL Linux Kernel
+        pub prebuilt_kernel_image: Option<PathBuf>,
+        /// Rebuild the kernel Image even if one already exists
+        pub force_kernel_rebuild: bool,
+        /// Module signing key (PEM with private key and certificate) to use
+        /// if the kernel config enables `CONFIG_MODULE_SIG`.
+        pub module_sign_key: Option<PathBuf>,
+        /// Let the kernel build generate an ephemeral module signing key if
+        /// the kernel config enables `CONFIG_MODULE_SIG`.
+        pub ephemeral_module_sign_key: bool,
+        /// If there isn't enough RAM for the kernel build's `-j`, reduce it
+        /// instead of just warning.
+        pub auto_reduce_kernel_jobs: bool,
+        /// `-j` for the kernel build. Uses the host's CPU count when `None`.
+        pub kernel_build_jobs: Option<usize>,
+        /// TMK packages to build (`simple_tmk`, `tmk_vmm`). Builds all of
+        /// them when empty.
+        pub tmk_binaries: Vec<String>,
+        /// Rust target to build `tmk_vmm` for
+        pub tmk_vmm_target: TmkVmmTarget,
+        /// Build the TMK binaries concurrently rather than one after another
+        pub parallel_builds: bool,
+        /// Export the kernel build's `compile_commands.json` to
+        /// `kernel-compile_commands.json` under the pipeline working dir
+        pub gen_compile_commands: bool,
+        /// Clone the cca_config repo and copy its `planes.yaml` into the
+        /// shrinkwrap config dir. Only the planes overlay needs it.
+        pub install_cca_config: bool,
+        /// Python interpreter to create the shrinkwrap venv with, instead of
+        /// `python3` from `PATH`
+        pub python_interpreter: Option<PathBuf>,
+        /// Pipeline-wide timeout in seconds, counted from when the job starts
+        pub pipeline_timeout_sec: Option<u64>,
+        /// Lock file held for the rest of the pipeline run, so concurrent
+        /// runs sharing a working dir wait for each other
+        pub pipeline_lock: Option<PathBuf>,
+        /// Git credential helper used when cloning/pulling repos
+        pub git_credential_helper: Option<String>,
+        /// When updating a repo whose branch has diverged from upstream,
+        /// reset it to the upstream tip instead of failing
+        pub allow_reset: bool,
+        /// How many times a failed clone or fetch is retried, with
+        /// exponential backoff, before giving up
+        pub max_clone_retries: u32,
+        /// Clone and update the repos with only their latest commit, rather
+        /// than their full history
+        pub shallow_clones: bool,
+        /// Depth of the OHCL Linux Kernel clone, which only needs its latest
+        /// commit to be built. `None` clones its full history.
+        pub kernel_clone_depth: Option<u32>,
+        /// Existing OHCL Linux Kernel clone to add the kernel checkout to as
+        /// a `git worktree`, instead of cloning the kernel again
+        pub kernel_worktree_base: Option<PathBuf>,
+        /// After cloning/updating a repo checked out at a signed tag, verify
+        /// the tag's GPG signature
+        pub verify_signatures: bool,
+        /// GnuPG home dir holding the keyring trusted signers' keys are in,
+        /// instead of the user's default one
+        pub gpg_keyring: Option<PathBuf>,
+        /// Compiler to build the host kernel with
+        pub toolchain_flavor: ToolchainFlavor,
+        /// ARM GNU toolchain release to build the host kernel with (e.g.
+        /// `14.3.rel1`)
+        pub toolchain_version: String,
+        /// Where to download the toolchain archive from, instead of ARM's
+        /// download URL for `toolchain_version`
+        pub toolchain_url: Option<String>,
+        /// Expected SHA-256 of the toolchain archive, instead of the checksum
+        /// ARM publishes for it (e.g. for a `toolchain_url` mirror)
+        pub toolchain_sha256: Option<String>,
+        /// Kernel configs (e.g. `CONFIG_VIRTIO_MEM`) to enable on top of the
+        /// ones the stack needs
+        pub extra_kernel_configs: Vec<String>,
+        /// Kernel configs to disable, after the others are enabled
+        pub disable_kernel_configs: Vec<String>,
+        /// Where to clone each repo from
+        pub repos: InstallRepos,
+        /// Only log the commands that would change the system, after
+        /// checking the inputs
+        pub dry_run: ReadVar<bool>,
         pub done: WriteVar<SideEffect>,
     }
 }
 
 new_simple_flow_node!(struct Node);
 
-///clone or update a git repository
-fn clone_or_update_repo(
+impl Params {
+    /// Check the parameters up front, reporting every problem at once rather
+    /// than failing halfway through the install.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut problems = Vec::new();
+
+        if let Some(toolchain_dir) = self.shrinkwrap_dir.parent() {
+            check_dir_writable(toolchain_dir, &mut problems);
+        } else {
+            problems.push(format!(
+                "shrinkwrap dir {} has no parent",
+                self.shrinkwrap_dir.display()
+            ));
+        }
+
+        if let Err(e) = validate_tmk_binaries(&self.tmk_binaries) {
+            problems.push(e.to_string());
+        }
+
+        if self.kernel_build_jobs == Some(0) {
+            problems.push("kernel build jobs must be at least 1".to_string());
+        }
+
+        if let Some(sha256) = &self.toolchain_sha256
+            && !is_sha256_digest(sha256)
+        {
+            problems.push(format!("invalid toolchain sha256 '{}'", sha256));
+        }
+
+        if let Some(image) = &self.prebuilt_kernel_image {
+            if !image.is_file() {
+                problems.push(format!(
+                    "prebuilt kernel Image {} does not exist or is not a file",
+                    image.display()
+                ));
+            } else if !is_arm64_kernel_image(image).unwrap_or(false) {
+                problems.push(format!(
+                    "prebuilt kernel Image {} is not an arm64 kernel Image",
+                    image.display()
+                ));
+            }
+        }
+
+        for config in self
+            .extra_kernel_configs
+            .iter()
+            .chain(&self.disable_kernel_configs)
+        {
+            if let Err(e) = validate_kernel_config_name(config) {
+                problems.push(e.to_string());
+            }
+        }
+
+        if let Some(key) = &self.module_sign_key {
+            if self.ephemeral_module_sign_key {
+                problems.push(
+                    "module_sign_key and ephemeral_module_sign_key are mutually exclusive"
+                        .to_string(),
+                );
+            }
+            if !key.is_file() {
+                problems.push(format!(
+                    "module signing key {} does not exist",
+                    key.display()
+                ));
+            }
+        }
+
+        // A bare name is looked up on PATH when the venv is created
+        if let Some(python) = &self.python_interpreter
+            && python.components().count() > 1
+            && !python.is_file()
+        {
+            problems.push(format!(
+                "Python interpreter {} does not exist",
+                python.display()
+            ));
+        }
+        if let Some(workspace) = &self.tmk_cargo_workspace
+            && !workspace.is_dir()
+        {
+            problems.push(format!(
+                "TMK cargo workspace {} is not a directory",
+                workspace.display()
+            ));
+        }
+        if let Some(keyring) = &self.gpg_keyring
+            && !keyring.is_dir()
+        {
+            problems.push(format!(
+                "GnuPG home dir {} is not a directory",
+                keyring.display()
+            ));
+        }
+
+        report_invalid_params("install", problems)
+    }
+}
+
+/// Check that git credential helper `helper` (as passed to
+/// `credential.helper`) can be invoked.
+pub(crate) fn validate_credential_helper(
     rt: &RustRuntimeServices<'_>,
+    helper: &str,
+) -> anyhow::Result<()> {
+    let invocable = if let Some(shell_cmd) = helper.strip_prefix('!') {
+        // A shell snippet: check its program exists
+        shell_cmd
+            .split_whitespace()
+            .next()
+            .is_some_and(|program| which::which(program).is_ok())
+    } else {
+        let program = helper.split_whitespace().next().unwrap_or_default();
+        if Path::new(program).is_absolute() {
+            Path::new(program).exists()
+        } else {
+            // A helper name, run as `git credential-<name>` (e.g: `store`)
+            let helper_bin = format!("git-credential-{}", program);
+            let exec_path = flowey::shell_cmd!(rt, "git --exec-path").quiet().read()?;
+            which::which(&helper_bin).is_ok() || Path::new(&exec_path).join(&helper_bin).exists()
+        }
+    };
+
+    if !invocable {
+        anyhow::bail!("git credential helper is not invocable (check --git-credential-helper)");
+    }
+    Ok(())
+}
+
+/// How many times a failed clone or fetch is retried by default.
+pub const DEFAULT_CLONE_RETRIES: u32 = 3;
+/// Delay before the first retry of a failed clone or fetch, doubled for each
+/// subsequent retry.
+pub(crate) const DEFAULT_CLONE_BACKOFF_SECONDS: u64 = 2;
+
+/// How [`clone_or_update_repo`] authenticates, updates, verifies, and retries.
+#[derive(Clone, Copy)]
+pub(crate) struct CloneOptions<'a> {
+    /// git credential helper used for the clone and fetch
+    pub credential_helper: Option<&'a str>,
+    /// Reset a clone that diverged from its upstream branch to the upstream tip
+    pub allow_reset: bool,
+    /// Verify the GPG signature of the tag the clone is checked out at
+    pub verify_signatures: bool,
+    /// GnuPG home dir holding the keys signatures are verified with
+    pub gpg_keyring: Option<&'a Path>,
+    /// How many times a failed clone or fetch is retried
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for each subsequent one
+    pub backoff_seconds: u64,
+    /// Clone with `--depth n`, and update a shallow clone by fetching with
+    /// `--depth n --update-shallow` and resetting to the fetched tip
+    pub shallow_depth: Option<u32>,
+    /// Only log the commands that would change the clone
+    pub dry_run: bool,
+}
+
+impl Default for CloneOptions<'_> {
+    fn default() -> Self {
+        Self {
+            credential_helper: None,
+            allow_reset: false,
+            verify_signatures: false,
+            gpg_keyring: None,
+            max_retries: DEFAULT_CLONE_RETRIES,
+            backoff_seconds: DEFAULT_CLONE_BACKOFF_SECONDS,
+            shallow_depth: None,
+            dry_run: false,
+        }
+    }
+}
+
+/// A shell of its own for work run on separate threads (each
+/// [`clone_or_update_repo`], each TMK build), so it doesn't change another
+/// thread's current dir. Shaped like `rt` so it works with `flowey::shell_cmd!`.
+struct ThreadShell {
+    sh: flowey::shell::FloweyShell,
+}
+
+impl ThreadShell {
+    fn new() -> anyhow::Result<Self> {
+        Ok(Self {
+            sh: flowey::shell::FloweyShell::new()?,
+        })
+    }
+}
+
+/// Run the git command `cmd` for `repo_name` (or with `dry_run` only log
+/// it), logging its output with the repo name as a prefix so clones running
+/// in parallel stay readable.
+fn run_git(cmd: FloweyCmd<'_>, repo_name: &str, dry_run: bool) -> anyhow::Result<()> {
+    let command = cmd.to_string();
+    if dry_run {
+        log::info!("DRY-RUN: {}", command);
+        return Ok(());
+    }
+    log::info!("[{}] $ {}", repo_name, command);
+    let output = interactive_output(cmd)?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for line in stdout.lines().chain(stderr.lines()) {
+        log::info!("[{}] {}", repo_name, line);
+    }
+    if !output.status.success() {
+        anyhow::bail!("`{}` {}", command, describe_exit_status(output.status));
+    }
+    Ok(())
+}
+
+/// Run the network operation `op`, retrying it with exponential backoff if it
+/// fails.
+fn with_retries(
+    options: &CloneOptions<'_>,
+    what: &str,
+    mut op: impl FnMut() -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt < options.max_retries => {
+                let delay = options.backoff_seconds.saturating_mul(1 << attempt.min(16));
+                attempt += 1;
+                log::warn!(
+                    "{} failed ({:#}), retrying in {}s (attempt {}/{})",
+                    what,
+                    e,
+                    delay,
+                    attempt,
+                    options.max_retries
+                );
+                std::thread::sleep(std::time::Duration::from_secs(delay));
+            }
+            Err(e) => {
+                return Err(e.context(format!("{} failed after {} attempts", what, attempt + 1)));
+            }
+        }
+    }
+}
+
+/// The SHA-256 ARM publishes for the toolchain archive at `url`, or `None` if
+/// it can't be fetched (e.g. when re-running offline).
+fn published_toolchain_sha256(rt: &RustRuntimeServices<'_>, url: &str) -> Option<String> {
+    let checksum_url = format!("{}{}", url, ARM_GNU_TOOLCHAIN_SHA256_SUFFIX);
+    let checksum = match flowey::shell_cmd!(rt, "wget -q -O - {checksum_url}")
+        .quiet()
+        .read()
+    {
+        Ok(checksum) => checksum,
+        Err(e) => {
+            log::warn!(
+                "Failed to fetch {} ({:#}), not verifying the toolchain archive",
+                checksum_url,
+                e
+            );
+            return None;
+        }
+    };
+    match checksum.split_whitespace().next() {
+        Some(digest) if is_sha256_digest(digest) => Some(digest.to_ascii_lowercase()),
+        _ => {
+            log::warn!(
+                "{} doesn't contain a SHA-256 digest, not verifying the toolchain archive",
+                checksum_url
+            );
+            None
+        }
+    }
+}
+
+/// Clone or update a git repository.
+///
+/// If `worktree_base` is an existing clone of the same repo, `target_dir` is
+/// created as a `git worktree` of it rather than as a clone of its own, so
+/// checkouts of several branches share one object store.
+pub(crate) fn clone_or_update_repo(
     repo_url: &str,
     target_dir: &Path,
-    update_repo: bool,
+    worktree_base: Option<&Path>,
+    update_policy: RepoUpdatePolicy,
     branch: Option<&str>,
     repo_name: &str,
+    options: &CloneOptions<'_>,
 ) -> anyhow::Result<()> {
-    if !target_dir.exists() {
-        log::info!("Cloning {} to {}", repo_name, target_dir.display());
-        let mut cmd = flowey::shell_cmd!(rt, "git clone");
-        if let Some(b) = branch {
-            cmd = cmd.args(["--branch", b]);
+    // Let the helper supply credentials, rather than embedding them in URLs
+    let credential_args = match options.credential_helper {
+        Some(helper) => vec!["-c".to_string(), format!("credential.helper={}", helper)],
+        None => Vec::new(),
+    };
+    let shell = ThreadShell::new()?;
+    let worktree_base = worktree_base.filter(|base| {
+        let cloned = base.join(".git").is_dir();
+        if !cloned && !target_dir.exists() {
+            log::info!(
+                "No {} clone at {} to add a worktree to, cloning instead",
+                repo_name,
+                base.display()
+            );
         }
-        cmd.arg(repo_url).arg(target_dir).run()?;
+        cloned
+    });
+
+    if let Some(base) = worktree_base
+        && !target_dir.exists()
+    {
+        log::info!(
+            "Adding a worktree of {} at {} to {}",
+            repo_name,
+            base.display(),
+            target_dir.display()
+        );
+        let refspec = branch.unwrap_or("HEAD");
+        with_retries(options, &format!("Fetching {}", repo_name), || {
+            let credential_args = &credential_args;
+            run_git(
+                flowey::shell_cmd!(
+                    shell,
+                    "git -C {base} {credential_args...} fetch origin {refspec}"
+                ),
+                repo_name,
+                options.dry_run,
+            )
+        })?;
+        // Drop the registration of a worktree whose dir was deleted, which
+        // would otherwise keep its branch from being checked out again
+        run_git(
+            flowey::shell_cmd!(shell, "git -C {base} worktree prune"),
+            repo_name,
+            options.dry_run,
+        )?;
+        let cmd = match branch {
+            // Creates a local branch tracking origin/<branch> if needed
+            Some(b) => flowey::shell_cmd!(shell, "git -C {base} worktree add {target_dir} {b}"),
+            None => flowey::shell_cmd!(
+                shell,
+                "git -C {base} worktree add --detach {target_dir} FETCH_HEAD"
+            ),
+        };
+        run_git(cmd, repo_name, options.dry_run)?;
+        log::info!("{} worktree added successfully", repo_name);
+    } else if !target_dir.exists() {
+        log::info!("Cloning {} to {}", repo_name, target_dir.display());
+        with_retries(options, &format!("Cloning {}", repo_name), || {
+            // Don't let a partial clone from a failed attempt block the retry
+            if target_dir.exists() {
+                fs_err::remove_dir_all(target_dir)?;
+            }
+            let credential_args = &credential_args;
+            let mut cmd = flowey::shell_cmd!(shell, "git {credential_args...} clone");
+            if let Some(depth) = options.shallow_depth {
+                cmd = cmd.arg("--depth").arg(depth.to_string());
+            }
+            if let Some(b) = branch {
+                cmd = cmd.args(["--branch", b]);
+                if options.shallow_depth.is_some() {
+                    cmd = cmd.arg("--single-branch");
+                }
+            }
+            run_git(
+                cmd.arg(repo_url).arg(target_dir),
+                repo_name,
+                options.dry_run,
+            )
+        })?;
         log::info!("{} cloned successfully", repo_name);
-    } else if update_repo {
+    } else if update_policy != RepoUpdatePolicy::Never {
+        // A worktree has a `.git` file pointing at its base repo's git dir
+        if target_dir.join(".git").is_file() {
+            log::info!(
+                "{} at {} is a git worktree, updating it in place",
+                repo_name,
+                target_dir.display()
+            );
+        }
         log::info!("Updating {} repo...", repo_name);
-        rt.sh.change_dir(target_dir);
-        flowey::shell_cmd!(rt, "git pull --ff-only").run()?;
+        shell.sh.change_dir(target_dir);
+        // Leave the history of an existing full clone alone
+        let shallow_depth = options.shallow_depth.filter(|_| {
+            flowey::shell_cmd!(shell, "git rev-parse --is-shallow-repository")
+                .quiet()
+                .read()
+                .is_ok_and(|shallow| shallow == "true")
+        });
+        let shallow = shallow_depth.is_some();
+        with_retries(options, &format!("Fetching {}", repo_name), || {
+            let credential_args = &credential_args;
+            let cmd = if let Some(depth) = shallow_depth {
+                let depth = depth.to_string();
+                let refspec = branch.unwrap_or("HEAD");
+                flowey::shell_cmd!(
+                    shell,
+                    "git {credential_args...} fetch --depth {depth} --update-shallow origin {refspec}"
+                )
+            } else {
+                flowey::shell_cmd!(shell, "git {credential_args...} fetch origin")
+            };
+            run_git(cmd, repo_name, options.dry_run)
+        })?;
+
+        // A dry run didn't fetch, so FETCH_HEAD is stale if it exists at
+        // all. Log the update that would be made instead.
+        let up_to_date = !options.dry_run
+            && (update_policy == RepoUpdatePolicy::IfBehind || shallow)
+            && flowey::shell_cmd!(shell, "git rev-parse HEAD")
+                .quiet()
+                .read()?
+                == flowey::shell_cmd!(shell, "git rev-parse FETCH_HEAD")
+                    .quiet()
+                    .read()?;
+
+        // A rebased upstream branch (or local commits) can't be fast-forwarded
+        let upstream = "@{u}";
+        let can_fast_forward = !shallow
+            && flowey::shell_cmd!(shell, "git merge-base --is-ancestor HEAD {upstream}")
+                .quiet()
+                .run()
+                .is_ok();
+        if up_to_date {
+            log::info!("{} is already at the upstream tip", repo_name);
+        } else if shallow {
+            // Without the history there's nothing to fast-forward along, so
+            // jump straight to the fetched tip
+            stash_uncommitted_changes(&shell, target_dir, repo_name, options)?;
+            run_git(
+                flowey::shell_cmd!(shell, "git reset --hard FETCH_HEAD"),
+                repo_name,
+                options.dry_run,
+            )?;
+        } else if can_fast_forward {
+            run_git(
+                flowey::shell_cmd!(shell, "git merge --ff-only {upstream}"),
+                repo_name,
+                options.dry_run,
+            )?;
+        } else if options.allow_reset {
+            let discarded = flowey::shell_cmd!(shell, "git log --oneline")
+                .arg(format!("{}..HEAD", upstream))
+                .quiet()
+                .read()?;
+            log::warn!(
+                "{} has diverged from its upstream branch, resetting to the upstream tip. Discarding local commits:\n{}",
+                repo_name,
+                discarded
+            );
+            stash_uncommitted_changes(&shell, target_dir, repo_name, options)?;
+            run_git(
+                flowey::shell_cmd!(shell, "git reset --hard {upstream}"),
+                repo_name,
+                options.dry_run,
+            )?;
+        } else {
+            anyhow::bail!(
+                "{} at {} has diverged from its upstream branch (it was likely rebased upstream, or has local commits), so it can't be fast-forwarded. \
+                 Resolve it manually (e.g. `git -C {} reset --hard @{{u}}`), or pass --allow-reset to reset to the upstream tip",
+                repo_name,
+                target_dir.display(),
+                target_dir.display()
+            );
+        }
         log::info!("{} updated successfully", repo_name);
     } else {
         log::info!("{} already exists at {}", repo_name, target_dir.display());
     }
+
+    if options.verify_signatures {
+        if options.dry_run {
+            log::info!("DRY-RUN: verify the GPG signature of the {} tag", repo_name);
+        } else {
+            verify_tag_signature(&shell, target_dir, repo_name, options.gpg_keyring)?;
+        }
+    }
+    Ok(())
+}
+
+/// Stash any uncommitted changes in the current dir's repo (`target_dir`)
+/// before it's hard reset, so they can be recovered.
+fn stash_uncommitted_changes(
+    shell: &ThreadShell,
+    target_dir: &Path,
+    repo_name: &str,
+    options: &CloneOptions<'_>,
+) -> anyhow::Result<()> {
+    let dirty = !flowey::shell_cmd!(shell, "git status --porcelain")
+        .quiet()
+        .read()?
+        .is_empty();
+    if dirty {
+        run_git(
+            flowey::shell_cmd!(shell, "git stash push --include-untracked -m")
+                .arg("cca-fvp: uncommitted changes before reset"),
+            repo_name,
+            options.dry_run,
+        )?;
+        log::warn!(
+            "Stashed uncommitted changes in {} (see `git stash list`)",
+            target_dir.display()
+        );
+    }
     Ok(())
 }
 
-fn enable_kernel_configs(rt: &RustRuntimeServices<'_>, group: &str, configs: &[&str]) -> anyhow::Result<()> {
-    // Enable each config one at a time to avoid shell argument parsing issues
-    for config in configs {
-        flowey::shell_cmd!(rt, "./scripts/config --file .config --enable {config}")
-            .run()
-            .with_context(|| format!("Failed to enable {} kernel config {}", group, config))?;
+/// Verify the GPG signatures of the tags `repo_dir` is checked out at, with
+/// the keyring in the GnuPG home dir `gpg_keyring` (or the user's default
+/// one). Branches and unsigned tags have nothing to verify, so they're only
+/// warned about.
+fn verify_tag_signature(
+    shell: &ThreadShell,
+    repo_dir: &Path,
+    repo_name: &str,
+    gpg_keyring: Option<&Path>,
+) -> anyhow::Result<()> {
+    let tags = flowey::shell_cmd!(shell, "git -C {repo_dir} tag --points-at HEAD")
+        .quiet()
+        .read()?;
+    let mut verified = false;
+    for tag in tags.lines() {
+        // Lightweight tags are plain refs, and annotated tags needn't be signed
+        let tag_object = flowey::shell_cmd!(shell, "git -C {repo_dir} cat-file -p")
+            .arg(format!("refs/tags/{}", tag))
+            .quiet()
+            .read()?;
+        if !tag_object.contains("-----BEGIN PGP SIGNATURE-----") {
+            continue;
+        }
+
+        let mut cmd = flowey::shell_cmd!(shell, "git -C {repo_dir} tag -v {tag}");
+        if let Some(keyring) = gpg_keyring {
+            cmd = cmd.env("GNUPGHOME", keyring);
+        }
+        cmd.run().with_context(|| {
+            format!(
+                "GPG signature verification of {} tag {} failed",
+                repo_name, tag
+            )
+        })?;
+        log::info!("Verified GPG signature of {} tag {}", repo_name, tag);
+        verified = true;
     }
 
+    if !verified {
+        log::warn!(
+            "{} is not checked out at a signed tag, skipping GPG signature verification",
+            repo_name
+        );
+    }
     Ok(())
 }
 
-/// Build a Rust binary if it doesn't already exist
-fn build_rust_binary(
+fn apply_kernel_configs(
     rt: &RustRuntimeServices<'_>,
+    group: &str,
+    enable: &[&str],
+    disable: &[&str],
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    // Apply each config one at a time to avoid shell argument parsing issues
+    let configs = enable
+        .iter()
+        .map(|config| ("enable", config))
+        .chain(disable.iter().map(|config| ("disable", config)));
+    for (action, config) in configs {
+        let flag = format!("--{}", action);
+        run_or_log(
+            flowey::shell_cmd!(rt, "./scripts/config --file .config {flag} {config}"),
+            dry_run,
+        )
+        .with_context(|| format!("Failed to {} {} kernel config {}", action, group, config))?;
+    }
+
+    Ok(())
+}
+
+/// Check that `config` looks like a kernel config name, e.g. `CONFIG_VIRTIO_BLK`.
+fn validate_kernel_config_name(config: &str) -> anyhow::Result<()> {
+    let valid = config.strip_prefix("CONFIG_").is_some_and(|name| {
+        !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    });
+    if !valid {
+        anyhow::bail!(
+            "invalid kernel config '{}': expected a config name like CONFIG_VIRTIO_BLK (without =y)",
+            config
+        );
+    }
+    Ok(())
+}
+
+/// Returns true if `config` is set to `y` in the `.config` of `kernel_dir`.
+fn kernel_config_enabled(kernel_dir: &Path, config: &str) -> anyhow::Result<bool> {
+    let contents = fs_err::read_to_string(kernel_dir.join(".config"))?;
+    let enabled = format!("{}=y", config);
+    Ok(contents.lines().any(|line| line.trim() == enabled))
+}
+
+/// Point the kernel build at a module signing key if the resolved config
+/// enables module signing. Fails if signing is enabled but no key source was
+/// chosen.
+fn configure_module_signing(
+    rt: &RustRuntimeServices<'_>,
+    kernel_dir: &Path,
+    module_sign_key: Option<&Path>,
+    ephemeral: bool,
+) -> anyhow::Result<bool> {
+    if !kernel_config_enabled(kernel_dir, "CONFIG_MODULE_SIG")? {
+        return Ok(false);
+    }
+
+    let key = match module_sign_key {
+        Some(key) => {
+            if !key.exists() {
+                anyhow::bail!("module signing key not found at {}", key.display());
+            }
+            log::info!(
+                "CONFIG_MODULE_SIG is enabled, signing modules with {}",
+                key.display()
+            );
+            key.to_path_buf()
+        }
+        None if ephemeral => {
+            // kbuild generates a fresh key at this path if it doesn't exist
+            log::info!("CONFIG_MODULE_SIG is enabled, using an ephemeral signing key");
+            PathBuf::from("certs/signing_key.pem")
+        }
+        None => anyhow::bail!(
+            "the kernel config enables CONFIG_MODULE_SIG, but no module signing key was provided \
+            (pass --module-sign-key <path> or --ephemeral-module-sign-key)"
+        ),
+    };
+
+    flowey::shell_cmd!(
+        rt,
+        "./scripts/config --file .config --set-str CONFIG_MODULE_SIG_KEY {key}"
+    )
+    .run()
+    .context("Failed to set CONFIG_MODULE_SIG_KEY")?;
+    Ok(true)
+}
+
+/// Total host RAM in bytes, as reported by `/proc/meminfo`.
+fn total_system_ram() -> Option<u64> {
+    let meminfo = fs_err::read_to_string("/proc/meminfo").ok()?;
+    let kib = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kib * 1024)
+}
+
+/// Check that the host has enough RAM for `jobs` parallel kernel build jobs.
+/// Returns the `-j` to use: `jobs`, or the recommended value if
+/// `auto_reduce` is set.
+fn check_kernel_build_ram(jobs: usize, auto_reduce: bool) -> usize {
+    let Some(total_ram) = total_system_ram() else {
+        log::warn!("Could not determine total system RAM, skipping kernel build RAM check");
+        return jobs;
+    };
+
+    let recommended = ((total_ram / MIN_RAM_PER_KERNEL_JOB) as usize).max(1);
+    if jobs <= recommended {
+        return jobs;
+    }
+
+    log::warn!(
+        "Only {} MiB of RAM for {} kernel build jobs ({} MiB per job); the build may be OOM-killed",
+        total_ram / (1024 * 1024),
+        jobs,
+        total_ram / (1024 * 1024) / jobs as u64
+    );
+    log::warn!(
+        "Recommended kernel build jobs for this host: -j{}",
+        recommended
+    );
+    if auto_reduce {
+        log::info!("Reducing kernel build jobs to -j{}", recommended);
+        recommended
+    } else {
+        jobs
+    }
+}
+
+/// Pick the kernel build's `-j`: `requested` if given, otherwise the host's
+/// CPU count, reduced to fit the host's RAM if `auto_reduce` is set.
+fn resolve_kernel_build_jobs(requested: Option<usize>, auto_reduce: bool) -> usize {
+    let jobs = match requested {
+        Some(jobs) => {
+            log::info!("Kernel build parallelism: -j{} (requested)", jobs);
+            jobs
+        }
+        None => {
+            let jobs = std::thread::available_parallelism().map_or(1, |n| n.get());
+            log::info!("Kernel build parallelism: -j{} (one per CPU)", jobs);
+            jobs
+        }
+    };
+    // An explicit -j is only warned about, never reduced
+    check_kernel_build_ram(jobs, auto_reduce && requested.is_none())
+}
+
+/// Check that `dir` is the root of a cargo workspace
+fn check_cargo_workspace(dir: &Path) -> anyhow::Result<()> {
+    let manifest = dir.join("Cargo.toml");
+    let contents = fs_err::read_to_string(&manifest)
+        .with_context(|| format!("TMK workspace {} has no Cargo.toml", dir.display()))?;
+    if !contents.lines().any(|line| line.trim() == "[workspace]") {
+        anyhow::bail!(
+            "{} does not contain a [workspace] section; --tmk-workspace must point at a cargo workspace root",
+            manifest.display()
+        );
+    }
+    Ok(())
+}
+
+/// A TMK package to build with [`build_rust_binary`], and the binary it
+/// produces in its own target dir (see [`tmk_target_dir`]).
+struct TmkBuild {
+    package: &'static str,
+    binary_path: PathBuf,
+    build_args: Vec<String>,
+    target: &'static str,
+}
+
+/// Build `package` in cargo workspace `workspace`, unless `binary_path`
+/// already exists. Each package is built from a shell of its own, into its
+/// own target dir, so builds can run in parallel.
+fn build_rust_binary(
+    workspace: &Path,
     binary_path: &Path,
     package: &str,
-    build_args: &[&str],
+    build_args: &[String],
+    dry_run: bool,
 ) -> anyhow::Result<()> {
     if binary_path.exists() {
-        log::info!("{} binary already exists at {}", package, binary_path.display());
+        log::info!(
+            "{} binary already exists at {}",
+            package,
+            binary_path.display()
+        );
         return Ok(());
     }
 
     log::info!("Building {}...", package);
-    let mut command = flowey::shell_cmd!(rt, "cargo build -p {package}");
+    let shell = ThreadShell::new()?;
+    shell.sh.change_dir(workspace);
+    let target_dir = tmk_target_dir(workspace, package);
+    let mut command =
+        flowey::shell_cmd!(shell, "cargo build -p {package} --target-dir {target_dir}");
 
     // Add additional build arguments
     for arg in build_args {
         command = command.arg(arg);
     }
 
-    command
+    let command = command
         .env("RUSTC_BOOTSTRAP", "1")
         .env_remove("ARCH")
-        .env_remove("CROSS_COMPILE")
-        .run()
+        .env_remove("CROSS_COMPILE");
+    run_or_log(command, dry_run)
         .map_err(|e| anyhow::anyhow!("Failed to build {}: {}", package, e))?;
+    if dry_run {
+        return Ok(());
+    }
 
-    log::info!("{} built successfully at: {}", package, binary_path.display());
+    log::info!(
+        "{} built successfully at: {}",
+        package,
+        binary_path.display()
+    );
     Ok(())
 }
 
-fn make_target(rt: &RustRuntimeServices<'_>, arch: &str, cross_compile: &str, target: &str, jobs: &str) -> anyhow::Result<()> {
-    flowey::shell_cmd!(
-        rt,
-        "make ARCH={arch} CROSS_COMPILE={cross_compile} {target} -j{jobs}"
+/// Check that `{cross_compile}gcc` exists and runs, so a broken toolchain
+/// fails here rather than deep inside the kernel build.
+fn check_cross_compiler(rt: &RustRuntimeServices<'_>, cross_compile: &str) -> anyhow::Result<()> {
+    let gcc = PathBuf::from(format!("{}gcc", cross_compile));
+    let hint = "delete the toolchain dir and run again to re-extract it";
+    if !gcc.is_file() {
+        anyhow::bail!("cross-compiler {} does not exist, {}", gcc.display(), hint);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if fs_err::metadata(&gcc)?.permissions().mode() & 0o111 == 0 {
+            anyhow::bail!(
+                "cross-compiler {} is not executable, {}",
+                gcc.display(),
+                hint
+            );
+        }
+    }
+    let version = flowey::shell_cmd!(rt, "{gcc} --version")
+        .quiet()
+        .read()
+        .with_context(|| format!("cross-compiler {} failed to run, {}", gcc.display(), hint))?;
+    log::info!(
+        "Cross-compiler: {}",
+        version.lines().next().unwrap_or_default()
+    );
+    Ok(())
+}
+
+/// Check that `clang` runs and can target AArch64, and that the
+/// `{cross_compile}ld` binutils the kernel build uses alongside it are on
+/// `PATH`.
+fn check_clang(
+    rt: &RustRuntimeServices<'_>,
+    clang: &str,
+    cross_compile: &str,
+) -> anyhow::Result<()> {
+    let targets = flowey::shell_cmd!(rt, "{clang} --print-targets")
+        .quiet()
+        .read()
+        .with_context(|| format!("{} failed to run, is it installed?", clang))?;
+    if !targets
+        .lines()
+        .any(|line| line.trim_start().starts_with("aarch64"))
+    {
+        anyhow::bail!("{} was built without the AArch64 target", clang);
+    }
+    let ld = format!("{}ld", cross_compile);
+    if which::which(&ld).is_err() {
+        anyhow::bail!(
+            "{} is not on PATH, install aarch64-none-elf binutils or build with the ARM GNU toolchain",
+            ld
+        );
+    }
+    let version = flowey::shell_cmd!(rt, "{clang} --version").quiet().read()?;
+    log::info!(
+        "Cross-compiler: {}",
+        version.lines().next().unwrap_or_default()
+    );
+    Ok(())
+}
+
+/// Run `make <target>` in the current dir, appending its output to
+/// `log_path` as well as echoing it. `cc` overrides the `{cross_compile}gcc`
+/// compiler (e.g. with clang).
+fn make_target(
+    rt: &RustRuntimeServices<'_>,
+    arch: &str,
+    cross_compile: &str,
+    cc: Option<&str>,
+    target: &str,
+    jobs: &str,
+    log_path: &Path,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    let cc_arg = cc.map(|cc| format!(" CC={}", cc)).unwrap_or_default();
+    if dry_run {
+        log::info!(
+            "DRY-RUN: cd {} && make ARCH={} CROSS_COMPILE={}{} {} -j{}",
+            rt.sh.current_dir().display(),
+            arch,
+            cross_compile,
+            cc_arg,
+            target,
+            jobs
+        );
+        return Ok(());
+    }
+
+    // Run make directly (rather than through the shell) so a build killed by
+    // a signal, e.g. by the OOM killer, can be told apart from a build error
+    log::info!(
+        "$ make ARCH={} CROSS_COMPILE={}{} {} -j{}",
+        arch,
+        cross_compile,
+        cc_arg,
+        target,
+        jobs
+    );
+    let mut cmd = std::process::Command::new("make");
+    cmd.arg(format!("ARCH={}", arch))
+        .arg(format!("CROSS_COMPILE={}", cross_compile));
+    if let Some(cc) = cc {
+        cmd.arg(format!("CC={}", cc));
+    }
+    cmd.arg(target)
+        .arg(format!("-j{}", jobs))
+        .current_dir(rt.sh.current_dir());
+    let line = command_line(&cmd);
+    let status =
+        tee_command_output(cmd, log_path).with_context(|| format!("Failed to run `{}`", line))?;
+    if !status.success() {
+        anyhow::bail!(
+            "`{}` {} (see {})",
+            line,
+            describe_exit_status(status),
+            log_path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Family of the host distro, which decides the package manager and package
+/// names the system dependencies are installed with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum HostDistro {
+    Debian,
+    Fedora,
+    Arch,
+}
+
+/// System packages the pipeline needs, by Ubuntu name. Listed for the user to
+/// install when the distro isn't recognized.
+const DEBIAN_PACKAGES: &[&str] = &[
+    "build-essential",
+    "flex",
+    "bison",
+    "libssl-dev",
+    "libelf-dev",
+    "bc",
+    "git",
+    "netcat-openbsd",
+    "python3",
+    "python3-pip",
+    "python3-venv",
+    "telnet",
+    "docker.io",
+    "unzip",
+];
+/// [`DEBIAN_PACKAGES`] on Fedora/RHEL, where the venv module ships with
+/// `python3`.
+const FEDORA_PACKAGES: &[&str] = &[
+    "@development-tools",
+    "flex",
+    "bison",
+    "openssl-devel",
+    "elfutils-libelf-devel",
+    "bc",
+    "git",
+    "nmap-ncat",
+    "python3",
+    "python3-pip",
+    "telnet",
+    "moby-engine",
+    "unzip",
+];
+/// [`DEBIAN_PACKAGES`] on Arch Linux.
+const ARCH_PACKAGES: &[&str] = &[
+    "base-devel",
+    "flex",
+    "bison",
+    "openssl",
+    "libelf",
+    "bc",
+    "git",
+    "openbsd-netcat",
+    "python",
+    "python-pip",
+    "inetutils",
+    "docker",
+    "unzip",
+];
+
+impl HostDistro {
+    /// Detect the host distro from the `ID` and `ID_LIKE` fields of
+    /// `/etc/os-release`, or `None` if it isn't one of the supported ones.
+    fn detect() -> anyhow::Result<Option<Self>> {
+        let os_release = fs_err::read_to_string("/etc/os-release")?;
+        let field = |key: &str| {
+            os_release
+                .lines()
+                .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+                .map(|value| value.trim().trim_matches('"').to_string())
+                .unwrap_or_default()
+        };
+        let ids = format!("{} {}", field("ID"), field("ID_LIKE"));
+        Ok(ids.split_whitespace().find_map(|id| match id {
+            "ubuntu" | "debian" => Some(HostDistro::Debian),
+            "fedora" | "rhel" | "centos" => Some(HostDistro::Fedora),
+            "arch" => Some(HostDistro::Arch),
+            _ => None,
+        }))
+    }
+
+    fn packages(self) -> &'static [&'static str] {
+        match self {
+            HostDistro::Debian => DEBIAN_PACKAGES,
+            HostDistro::Fedora => FEDORA_PACKAGES,
+            HostDistro::Arch => ARCH_PACKAGES,
+        }
+    }
+
+    /// Package with the `aarch64-linux-musl-gcc` cross linker, where the
+    /// distro packages one.
+    fn musl_cross_package(self) -> Option<&'static str> {
+        match self {
+            HostDistro::Debian => Some("musl-cross"),
+            HostDistro::Fedora | HostDistro::Arch => None,
+        }
+    }
+
+    /// Packages with [`LLVM_CLANG`] and the LLVM tools.
+    fn clang_packages(self) -> &'static [&'static str] {
+        match self {
+            HostDistro::Debian => &["clang-18", "llvm-18"],
+            HostDistro::Fedora | HostDistro::Arch => &["clang", "llvm"],
+        }
+    }
+
+    /// Whether `package` (or, on Fedora, `@group`) is fully installed.
+    fn is_installed(self, rt: &RustRuntimeServices<'_>, package: &str) -> bool {
+        match self {
+            // A removed package whose config files are left still has a status
+            HostDistro::Debian => flowey::shell_cmd!(rt, "dpkg -s {package}")
+                .quiet()
+                .ignore_stderr()
+                .read()
+                .is_ok_and(|status| {
+                    status
+                        .lines()
+                        .any(|line| line == "Status: install ok installed")
+                }),
+            HostDistro::Fedora => match package.strip_prefix('@') {
+                Some(group) => dnf_group_installed(rt, group),
+                None => flowey::shell_cmd!(rt, "rpm -q {package}")
+                    .quiet()
+                    .ignore_stdout()
+                    .ignore_stderr()
+                    .run()
+                    .is_ok(),
+            },
+            HostDistro::Arch => flowey::shell_cmd!(rt, "pacman -Q {package}")
+                .quiet()
+                .ignore_stdout()
+                .ignore_stderr()
+                .run()
+                .is_ok(),
+        }
+    }
+
+    /// Install whichever of `packages` aren't installed yet with the
+    /// distro's package manager, without touching the package index if
+    /// they all are.
+    fn install(
+        self,
+        rt: &RustRuntimeServices<'_>,
+        packages: &[&str],
+        dry_run: bool,
+    ) -> anyhow::Result<()> {
+        let (present, missing): (Vec<&str>, Vec<&str>) = packages
+            .iter()
+            .partition(|package| self.is_installed(rt, package));
+        if !present.is_empty() {
+            log::info!("Already installed: {}", present.join(" "));
+        }
+        if missing.is_empty() {
+            log::info!("All system dependencies are already installed");
+            return Ok(());
+        }
+        let packages = &missing;
+        match self {
+            HostDistro::Debian => {
+                run_or_log(flowey::shell_cmd!(rt, "sudo apt-get update"), dry_run)?;
+                run_or_log(
+                    flowey::shell_cmd!(rt, "sudo apt-get install -y {packages...}"),
+                    dry_run,
+                )
+            }
+            HostDistro::Fedora => run_or_log(
+                flowey::shell_cmd!(rt, "sudo dnf install -y {packages...}"),
+                dry_run,
+            ),
+            HostDistro::Arch => run_or_log(
+                flowey::shell_cmd!(rt, "sudo pacman -S --needed --noconfirm {packages...}"),
+                dry_run,
+            ),
+        }?;
+        log::info!(
+            "{}: {}",
+            if dry_run {
+                "Would install"
+            } else {
+                "Newly installed"
+            },
+            missing.join(" ")
+        );
+        Ok(())
+    }
+}
+
+/// Whether the dnf package group `group` (e.g. `development-tools`) is
+/// installed. `rpm` knows nothing about groups, so this asks dnf: dnf5 lists
+/// installed group ids in the first column, while dnf4 only shows them (as
+/// `Name (id)`) with `--ids`, which dnf5 rejects.
+fn dnf_group_installed(rt: &RustRuntimeServices<'_>, group: &str) -> bool {
+    flowey::shell_cmd!(rt, "dnf group list --installed --ids")
+        .quiet()
+        .ignore_stderr()
+        .read()
+        .or_else(|_| {
+            flowey::shell_cmd!(rt, "dnf group list --installed")
+                .quiet()
+                .ignore_stderr()
+                .read()
+        })
+        .is_ok_and(|list| {
+            let dnf4_id = format!("({group})");
+            list.lines().map(str::trim).any(|line| {
+                line.split_whitespace().next() == Some(group) || line.ends_with(&dnf4_id)
+            })
+        })
+}
+
+/// Groups `username` is a member of, from the output of `id -nG <username>`.
+fn user_groups(rt: &RustRuntimeServices<'_>, username: &str) -> anyhow::Result<Vec<String>> {
+    let output = flowey::shell_cmd!(rt, "id -nG {username}")
+        .quiet()
+        .read()
+        .with_context(|| format!("failed to list the groups of {}", username))?;
+    Ok(output.split_whitespace().map(str::to_string).collect())
+}
+
+/// Make sure the `docker` group exists and `username` is in it. Returns true
+/// if `username` was added to it (or would be, in a dry run), and false if it
+/// already was a member.
+fn ensure_docker_group(
+    rt: &RustRuntimeServices<'_>,
+    username: &str,
+    dry_run: bool,
+) -> anyhow::Result<bool> {
+    if user_groups(rt, username)?
+        .iter()
+        .any(|group| group == "docker")
+    {
+        return Ok(false);
+    }
+
+    // -f succeeds if the group already exists
+    run_or_log(flowey::shell_cmd!(rt, "sudo groupadd -f docker"), dry_run)
+        .context("failed to create the docker group")?;
+    run_or_log(
+        flowey::shell_cmd!(rt, "sudo usermod -aG docker {username}"),
+        dry_run,
     )
-    .run()
-    .with_context(|| format!("Failed to run `make {}`", target))?;
+    .with_context(|| format!("failed to add {} to the docker group", username))?;
+
+    if !dry_run
+        && !user_groups(rt, username)?
+            .iter()
+            .any(|group| group == "docker")
+    {
+        anyhow::bail!(
+            "usermod succeeded, but {} is still not in the docker group",
+            username
+        );
+    }
+    Ok(true)
+}
+
+/// Version of Python interpreter `python`, checked against
+/// [`SHRINKWRAP_MIN_PYTHON`].
+fn check_python_version(rt: &RustRuntimeServices<'_>, python: &Path) -> anyhow::Result<String> {
+    let version = flowey::shell_cmd!(rt, "{python} -c")
+        .arg("import sys; print('%d.%d.%d' % sys.version_info[:3])")
+        .quiet()
+        .read()
+        .with_context(|| format!("failed to run Python interpreter {}", python.display()))?;
+
+    let mut parts = version
+        .split('.')
+        .map(|part| part.parse::<u32>().unwrap_or(0));
+    let major_minor = (parts.next().unwrap_or(0), parts.next().unwrap_or(0));
+    if major_minor < SHRINKWRAP_MIN_PYTHON {
+        anyhow::bail!(
+            "{} is Python {}, but shrinkwrap needs Python {}.{} or newer (use --python to pick another interpreter)",
+            python.display(),
+            version,
+            SHRINKWRAP_MIN_PYTHON.0,
+            SHRINKWRAP_MIN_PYTHON.1
+        );
+    }
+    Ok(version)
+}
+
+/// Generate `compile_commands.json` for the kernel in `kernel_dir` from its
+/// last build, and copy it to [`KERNEL_COMPILE_COMMANDS`] under `out_dir`.
+fn export_compile_commands(
+    rt: &RustRuntimeServices<'_>,
+    kernel_dir: &Path,
+    arch: &str,
+    cross_compile: &str,
+    cc: Option<&str>,
+    out_dir: &Path,
+    log_path: &Path,
+) -> anyhow::Result<()> {
+    rt.sh.change_dir(kernel_dir);
+    make_target(
+        rt,
+        arch,
+        cross_compile,
+        cc,
+        "compile_commands.json",
+        "1",
+        log_path,
+        false,
+    )?;
+
+    let generated = kernel_dir.join("compile_commands.json");
+    let exported = out_dir.join(KERNEL_COMPILE_COMMANDS);
+    fs_err::copy(&generated, &exported)?;
+
+    // It's generated from the build's .cmd files, which a kernel restored
+    // from the cache doesn't have
+    if fs_err::read_to_string(&exported)?.trim() == "[]" {
+        log::warn!(
+            "compile_commands.json is empty, delete {} and run again to rebuild the kernel",
+            kernel_image_path(kernel_dir, arch, KERNEL_IMAGE_TARGET).display()
+        );
+    }
+    log::info!(
+        "Exported kernel compile_commands.json to {}",
+        exported.display()
+    );
+    Ok(())
+}
+
+/// Compute the kernel cache key for the configured kernel tree in
+/// `kernel_dir`: `sha256(kernel commit + .config + toolchain version)`. The
+/// compiler is `cc`, or `{cross_compile}gcc` if unset.
+fn kernel_cache_key(
+    rt: &RustRuntimeServices<'_>,
+    kernel_dir: &Path,
+    cross_compile: &str,
+    cc: Option<&str>,
+) -> anyhow::Result<String> {
+    let commit = flowey::shell_cmd!(rt, "git -C {kernel_dir} rev-parse HEAD").read()?;
+    let config = fs_err::read(kernel_dir.join(".config"))?;
+    let compiler = cc.map_or_else(|| format!("{}gcc", cross_compile), str::to_string);
+    let toolchain_version = flowey::shell_cmd!(rt, "{compiler} --version").read()?;
+
+    let mut hasher = Sha256::new();
+    hasher.update(commit.as_bytes());
+    hasher.update(&config);
+    hasher.update(toolchain_version.as_bytes());
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Restore a cached kernel Image from `entry` to `kernel_image`. Returns false
+/// if there is no usable cache entry.
+fn restore_cached_kernel(entry: &Path, kernel_image: &Path) -> anyhow::Result<bool> {
+    let cached_image = entry.join(KERNEL_IMAGE_TARGET);
+    let digest_file = entry.join(format!("{}.sha256", KERNEL_IMAGE_TARGET));
+    // The digest is written last, so its presence marks a complete entry
+    if !digest_file.exists() {
+        return Ok(false);
+    }
+
+    let expected = fs_err::read_to_string(&digest_file)?;
+    if sha256_file(&cached_image)? != expected.trim() || !is_arm64_kernel_image(&cached_image)? {
+        log::warn!("Ignoring corrupt kernel cache entry at {}", entry.display());
+        return Ok(false);
+    }
+
+    if let Some(parent) = kernel_image.parent() {
+        fs_err::create_dir_all(parent)?;
+    }
+    fs_err::copy(&cached_image, kernel_image)?;
+    Ok(true)
+}
+
+/// Store the kernel Image and `.config` from `kernel_dir` in cache `entry`.
+fn store_cached_kernel(entry: &Path, kernel_dir: &Path, kernel_image: &Path) -> anyhow::Result<()> {
+    fs_err::create_dir_all(entry)?;
+    fs_err::copy(kernel_image, entry.join(KERNEL_IMAGE_TARGET))?;
+    fs_err::copy(kernel_dir.join(".config"), entry.join(".config"))?;
+    fs_err::write(
+        entry.join(format!("{}.sha256", KERNEL_IMAGE_TARGET)),
+        sha256_file(kernel_image)?,
+    )?;
     Ok(())
 }
 
@@ -131,233 +1556,587 @@ impl SimpleFlowNode for Node {
     fn imports(_ctx: &mut ImportCtx<'_>) {}
 
     fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Result<()> {
+        request.validate()?;
+
         let Params {
             shrinkwrap_dir,
             do_installs,
+            setup_docker_group,
             update_repo,
+            repo_update_policies,
+            tmk_cargo_workspace,
+            kernel_cache_dir,
+            prebuilt_kernel_image,
+            force_kernel_rebuild,
+            module_sign_key,
+            ephemeral_module_sign_key,
+            auto_reduce_kernel_jobs,
+            kernel_build_jobs,
+            tmk_binaries,
+            tmk_vmm_target,
+            parallel_builds,
+            gen_compile_commands,
+            install_cca_config,
+            python_interpreter,
+            pipeline_timeout_sec,
+            pipeline_lock,
+            git_credential_helper,
+            allow_reset,
+            max_clone_retries,
+            shallow_clones,
+            kernel_clone_depth,
+            kernel_worktree_base,
+            verify_signatures,
+            gpg_keyring,
+            toolchain_flavor,
+            toolchain_version,
+            toolchain_url,
+            toolchain_sha256,
+            extra_kernel_configs,
+            disable_kernel_configs,
+            repos,
+            dry_run,
             done,
         } = request;
 
         ctx.emit_rust_step("install shrinkwrap", |ctx| {
             done.claim(ctx);
+            let dry_run = dry_run.claim(ctx);
             move |rt| {
+                let dry_run = rt.read(dry_run);
+                if let Some(lock_path) = &pipeline_lock {
+                    if dry_run {
+                        log::info!("DRY-RUN: would lock {}", lock_path.display());
+                    } else {
+                        acquire_pipeline_lock(lock_path)?;
+                    }
+                }
+                enter_pipeline_phase(pipeline_timeout_sec, PipelinePhase::Install)?;
 
-                // 0) Create parent dir
-                if let Some(parent) = shrinkwrap_dir.parent() {
-                    fs_err::create_dir_all(parent)?;
+                if let Some(helper) = &git_credential_helper {
+                    validate_credential_helper(rt, helper)?;
+                    log::info!("Using the configured git credential helper for clones");
                 }
 
-                // 1) System deps (Ubuntu)
-                if do_installs {
-                    log::info!("Installing system dependencies...");
-                    flowey::shell_cmd!(rt, "sudo apt-get update").run()?;
-                    flowey::shell_cmd!(rt, "sudo apt-get install -y build-essential flex bison libssl-dev libelf-dev bc git netcat-openbsd python3 python3-pip python3-venv telnet docker.io unzip").run()?;
+                // 0) Create parent dir
+                let toolchain_dir = shrinkwrap_dir.parent()
+                    .ok_or_else(|| anyhow::anyhow!("shrinkwrap_dir has no parent"))?;
+                fs_err::create_dir_all(toolchain_dir)?;
+                let mut timings = StageTimings::new("install", toolchain_dir.join("logs"));
 
-                    // Setup Docker group and add current user
+                // 1) System deps
+                let t0 = Instant::now();
+                if do_installs {
+                    let Some(distro) = HostDistro::detect()? else {
+                        anyhow::bail!(
+                            "unsupported host distro (expected Ubuntu/Debian, Fedora/RHEL or Arch Linux), install the equivalent of these Ubuntu packages manually and rerun without --install-missing-deps: {}",
+                            DEBIAN_PACKAGES.join(" ")
+                        );
+                    };
+                    log::info!("Installing system dependencies ({:?} packages)...", distro);
+                    let mut packages = distro.packages().to_vec();
+                    // Cross linker for the musl target
+                    if tmk_vmm_target == TmkVmmTarget::MuslLinux && tmk_binary_selected(&tmk_binaries, "tmk_vmm") {
+                        match distro.musl_cross_package() {
+                            Some(package) => packages.push(package),
+                            None => log::warn!(
+                                "{:?} doesn't package an aarch64 musl cross toolchain, install one providing aarch64-linux-musl-gcc manually",
+                                distro
+                            ),
+                        }
+                    }
+                    if toolchain_flavor == ToolchainFlavor::LlvmClang {
+                        packages.extend(distro.clang_packages());
+                    }
+                    distro.install(rt, &packages, dry_run)?;
+                }
+                if setup_docker_group {
                     log::info!("Setting up Docker group...");
                     let username = std::env::var("USER").unwrap_or_else(|_| "vscode".to_string());
-
-                    // Create docker group (ignore error if it already exists)
-                    let _ = flowey::shell_cmd!(rt, "sudo groupadd docker").run();
-
-                    // Add user to docker group
-                    flowey::shell_cmd!(rt, "sudo usermod -aG docker {username}").run()?;
-
-                    log::warn!("Docker group membership updated. You may need to log out and log back in for docker permissions to take effect.");
-                    log::warn!("Alternatively, run: newgrp docker");
+                    if ensure_docker_group(rt, &username, dry_run)? {
+                        log::warn!("Docker group membership updated. You may need to log out and log back in for docker permissions to take effect.");
+                        log::warn!("Alternatively, run: newgrp docker");
+                    } else {
+                        log::info!("{} is already in the docker group", username);
+                    }
                 }
+                timings.record("system dependencies", t0);
 
                 // 2) Download and extract ARM GNU toolchain for Host linux kernel compilation
-                let toolchain_dir = shrinkwrap_dir.parent()
-                    .ok_or_else(|| anyhow::anyhow!("shrinkwrap_dir has no parent"))?;
-                let toolchain_archive = toolchain_dir.join("arm-gnu-toolchain-14.3.rel1-x86_64-aarch64-none-elf.tar.xz");
-                let toolchain_extracted_dir = toolchain_dir.join("arm-gnu-toolchain-14.3.rel1-x86_64-aarch64-none-elf");
-
-                // Download toolchain if not present
-                if !toolchain_archive.exists() {
-                    log::info!("Downloading ARM GNU toolchain to {}", toolchain_archive.display());
-                    flowey::shell_cmd!(rt, "wget -O").arg(&toolchain_archive).arg(ARM_GNU_TOOLCHAIN_URL).run()?;
-                    log::info!("ARM GNU toolchain downloaded successfully");
-                } else {
-                    log::info!("ARM GNU toolchain already exists at {}", toolchain_archive.display());
-                }
+                let t0 = Instant::now();
+                let toolchain_name = arm_gnu_toolchain_name(&toolchain_version);
+                let toolchain_archive = toolchain_dir.join(format!("{}.tar.xz", toolchain_name));
+                // An earlier install of this version may have extracted to a
+                // differently named dir, which it recorded
+                let mut toolchain_extracted_dir = ToolchainManifest::read(toolchain_dir)?
+                    .filter(|toolchains| toolchains.arm_gnu_toolchain_version.as_deref() == Some(&toolchain_version))
+                    .and_then(|toolchains| toolchains.arm_gnu_toolchain_dir)
+                    .filter(|dir| is_arm_gnu_toolchain(dir))
+                    .unwrap_or_else(|| arm_gnu_toolchain_dir(toolchain_dir, &toolchain_version));
 
-                // Extract toolchain if not already extracted
-                if !toolchain_extracted_dir.exists() {
-                    log::info!("Extracting ARM GNU toolchain to {}", toolchain_dir.display());
-                    rt.sh.change_dir(toolchain_dir);
-                    flowey::shell_cmd!(rt, "tar -xvf").arg(&toolchain_archive).run()?;
-                    log::info!("ARM GNU toolchain extracted successfully");
+                if toolchain_flavor == ToolchainFlavor::LlvmClang {
+                    log::info!("Building the kernel with {}, skipping the ARM GNU toolchain", LLVM_CLANG);
                 } else {
-                    log::info!("ARM GNU toolchain already extracted at {}", toolchain_extracted_dir.display());
+                    // Download toolchain if not present (or if it doesn't match its
+                    // checksum, e.g. after a truncated download)
+                    if dry_run {
+                        let url = toolchain_url.unwrap_or_else(|| arm_gnu_toolchain_url(&toolchain_version));
+                        log::info!("DRY-RUN: download {} to {}", url, toolchain_archive.display());
+                    } else {
+                        let toolchain_sha256 = match (toolchain_sha256, &toolchain_url) {
+                            (Some(sha256), _) => Some(sha256),
+                            (None, None) => published_toolchain_sha256(rt, &arm_gnu_toolchain_url(&toolchain_version)),
+                            (None, Some(url)) => {
+                                log::warn!(
+                                    "No checksum is published for {}, not verifying the toolchain archive (pass --toolchain-sha256)",
+                                    url
+                                );
+                                None
+                            }
+                        };
+                        let toolchain_url = toolchain_url.unwrap_or_else(|| arm_gnu_toolchain_url(&toolchain_version));
+                        download_file(&toolchain_url, &toolchain_archive, toolchain_sha256.as_deref())?;
+                    }
+
+                    // Extract toolchain if not already extracted
+                    if is_arm_gnu_toolchain(&toolchain_extracted_dir) {
+                        log::info!("ARM GNU toolchain already extracted at {}", toolchain_extracted_dir.display());
+                    } else if dry_run {
+                        log::info!("DRY-RUN: cd {} && tar -xvf {}", toolchain_dir.display(), toolchain_archive.display());
+                    } else {
+                        log::info!("Extracting ARM GNU toolchain to {}", toolchain_dir.display());
+                        let existing = find_arm_gnu_toolchains(toolchain_dir)?;
+                        rt.sh.change_dir(toolchain_dir);
+                        run_or_log(flowey::shell_cmd!(rt, "tar -xvf").arg(&toolchain_archive), false)?;
+
+                        // The archive's top-level dir may not match the expected
+                        // name, so look for whatever it extracted to
+                        if !is_arm_gnu_toolchain(&toolchain_extracted_dir) {
+                            toolchain_extracted_dir = find_arm_gnu_toolchains(toolchain_dir)?
+                                .into_iter()
+                                .find(|dir| !existing.contains(dir))
+                                .ok_or_else(|| {
+                                    anyhow::anyhow!(
+                                        "extracting {} did not produce a dir containing bin/aarch64-none-elf-gcc",
+                                        toolchain_archive.display()
+                                    )
+                                })?;
+                            log::info!(
+                                "ARM GNU toolchain extracted to {} (expected {}), using it",
+                                toolchain_extracted_dir.display(),
+                                toolchain_name
+                            );
+                        }
+                        log::info!("ARM GNU toolchain extracted successfully");
+                    }
                 }
+                timings.record("ARM GNU toolchain", t0);
 
                 // Document the cross-compilation environment variables needed
-                let cross_compile_path = toolchain_extracted_dir.join("bin").join("aarch64-none-elf-");
-                log::info!("ARM GNU toolchain bin path: {}", cross_compile_path.display());
-
-                // 3) Clone OHCL Linux Kernel (Host Linux Kernel)
-                let host_kernel_dir = toolchain_dir.join("OHCL-Linux-Kernel");
-                clone_or_update_repo(
-                    &rt,
-                    OHCL_LINUX_KERNEL_REPO,
-                    &host_kernel_dir,
-                    update_repo,
-                    Some(OHCL_LINUX_KERNEL_PLANE0_BRANCH),
-                    "OHCL Linux Kernel",
-                )?;
+                let (cross_compile_path, kernel_cc) = match toolchain_flavor {
+                    ToolchainFlavor::ArmGnu => {
+                        let cross_compile_path = arm_gnu_cross_compile_prefix(&toolchain_extracted_dir);
+                        log::info!("ARM GNU toolchain bin path: {}", cross_compile_path.display());
+                        (cross_compile_path, None)
+                    }
+                    ToolchainFlavor::LlvmClang => (PathBuf::from(LLVM_BINUTILS_CROSS_COMPILE), Some(LLVM_CLANG)),
+                };
+
+                let update_policy = |repo_name: &str| {
+                    repo_update_policies.get(repo_name).copied().unwrap_or(if update_repo {
+                        RepoUpdatePolicy::Always
+                    } else {
+                        RepoUpdatePolicy::Never
+                    })
+                };
+                let clone_options = CloneOptions {
+                    credential_helper: git_credential_helper.as_deref(),
+                    allow_reset,
+                    verify_signatures,
+                    gpg_keyring: gpg_keyring.as_deref(),
+                    max_retries: max_clone_retries,
+                    backoff_seconds: DEFAULT_CLONE_BACKOFF_SECONDS,
+                    shallow_depth: shallow_clones.then_some(1),
+                    dry_run,
+                };
+
+                // 3) Clone the OHCL Linux Kernel, TMK, shrinkwrap and cca_config
+                // repos. They don't depend on each other, so clone them in
+                // parallel, each with its own shell.
+                let host_kernel_dir = toolchain_dir.join(HOST_KERNEL_DIR);
+                let tmk_kernel_dir = toolchain_dir.join(TMK_DIR);
+                let cca_config_dir = toolchain_dir.join(CCA_CONFIG_DIR);
+                let mut clones = vec![
+                    (TMK_REPO_NAME, &repos.tmk, tmk_kernel_dir.as_path()),
+                    (SHRINKWRAP_REPO_NAME, &repos.shrinkwrap, shrinkwrap_dir.as_path()),
+                ];
+                if prebuilt_kernel_image.is_none() {
+                    clones.push((KERNEL_REPO_NAME, &repos.kernel, host_kernel_dir.as_path()));
+                }
+                if install_cca_config {
+                    clones.push((CCA_CONFIG_REPO_NAME, &repos.cca_config, cca_config_dir.as_path()));
+                }
+                let t0 = Instant::now();
+                let results = std::thread::scope(|s| {
+                    let threads = clones
+                        .iter()
+                        .map(|&(name, repo, dir)| {
+                            let policy = update_policy(name);
+                            let options = if name == KERNEL_REPO_NAME {
+                                CloneOptions {
+                                    shallow_depth: kernel_clone_depth,
+                                    ..clone_options
+                                }
+                            } else {
+                                clone_options
+                            };
+                            let worktree_base = kernel_worktree_base.as_deref().filter(|_| name == KERNEL_REPO_NAME);
+                            s.spawn(move || {
+                                clone_or_update_repo(
+                                    &repo.url,
+                                    dir,
+                                    worktree_base,
+                                    policy,
+                                    repo.branch.as_deref(),
+                                    name,
+                                    &options,
+                                )
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    threads
+                        .into_iter()
+                        .zip(&clones)
+                        .map(|(thread, (name, _, _))| {
+                            thread
+                                .join()
+                                .unwrap_or_else(|_| Err(anyhow::anyhow!("cloning {} panicked", name)))
+                        })
+                        .collect::<Vec<_>>()
+                });
+                timings.record("clone repos", t0);
+                let mut first_error = None;
+                for result in results {
+                    if let Err(e) = result {
+                        if first_error.is_none() {
+                            first_error = Some(e);
+                        } else {
+                            log::error!("{:#}", e);
+                        }
+                    }
+                }
+                if let Some(e) = first_error {
+                    return Err(e);
+                }
+
+                // Record what the kernel and TMK binaries get built with
+                let mut toolchains = ToolchainManifest::read(toolchain_dir)?.unwrap_or_default();
+                let arm_gnu = toolchain_flavor == ToolchainFlavor::ArmGnu;
+                toolchains.arm_gnu_toolchain_version = arm_gnu.then(|| toolchain_version.clone());
+                toolchains.arm_gnu_toolchain_dir = arm_gnu.then(|| toolchain_extracted_dir.clone());
 
                 // 4) Compile OHCL Linux Kernel with ARM GNU toolchain
-                let kernel_image = host_kernel_dir.join("arch").join("arm64").join("boot").join("Image");
-                if !kernel_image.exists() {
+                let kernel_image = kernel_image_path(&host_kernel_dir, KERNEL_ARCH, KERNEL_IMAGE_TARGET);
+                let last_built_commit = kernel_image.with_file_name(LAST_BUILT_COMMIT);
+                let kernel_log = toolchain_dir.join("logs").join(KERNEL_BUILD_LOG);
+                // A dry run may not have cloned the kernel to have a HEAD
+                let kernel_head = if prebuilt_kernel_image.is_none() && host_kernel_dir.join(".git").exists() {
+                    Some(flowey::shell_cmd!(rt, "git -C {host_kernel_dir} rev-parse HEAD").quiet().read()?)
+                } else {
+                    None
+                };
+                let rebuild_kernel = if !kernel_image.exists() {
+                    false
+                } else if force_kernel_rebuild {
+                    log::info!("Rebuilding the existing kernel Image as requested");
+                    true
+                } else if let Some(head) = &kernel_head
+                    && let Some(built) = fs_err::read_to_string(&last_built_commit).ok()
+                    && built.trim() != head
+                {
+                    log::info!(
+                        "Kernel Image was built from {}, but the kernel repo is now at {}, rebuilding",
+                        built.trim(),
+                        head
+                    );
+                    true
+                } else {
+                    false
+                };
+
+                let t0 = Instant::now();
+                if let Some(prebuilt) = &prebuilt_kernel_image {
+                    // Put it where the run job looks for the kernel
+                    if dry_run {
+                        log::info!("DRY-RUN: cp {} {}", prebuilt.display(), kernel_image.display());
+                    } else {
+                        if let Some(parent) = kernel_image.parent() {
+                            fs_err::create_dir_all(parent)?;
+                        }
+                        fs_err::copy(prebuilt, &kernel_image)?;
+                        // The Image no longer matches any kernel commit
+                        if last_built_commit.exists() {
+                            fs_err::remove_file(&last_built_commit)?;
+                        }
+                    }
+                    toolchains.kernel_arch = Some(KERNEL_ARCH.to_string());
+                    toolchains.kernel_cross_compile = None;
+                    log::info!("Using prebuilt kernel Image {}, skipping the kernel build", prebuilt.display());
+                } else if !kernel_image.exists() || rebuild_kernel {
                     log::info!("Compiling OHCL Linux Kernel...");
                     rt.sh.change_dir(&host_kernel_dir);
+                    if !dry_run {
+                        // Start a fresh log for this build
+                        fs_err::create_dir_all(toolchain_dir.join("logs"))?;
+                        fs_err::File::create(&kernel_log)?;
+                        log::info!("Kernel build output will be saved to: {}", kernel_log.display());
+                    }
 
                     // Set environment variables for cross-compilation
-                    let arch = "arm64";
+                    let arch = KERNEL_ARCH;
                     let cross_compile = cross_compile_path.to_str()
                         .ok_or_else(|| anyhow::anyhow!("Invalid cross_compile path"))?;
+                    // A dry run doesn't extract the toolchain to check
+                    if !dry_run {
+                        match kernel_cc {
+                            Some(cc) => check_clang(rt, cc, cross_compile)?,
+                            None => check_cross_compiler(rt, cross_compile)?,
+                        }
+                    }
 
                     // Run make defconfig
                     log::info!("Running make defconfig...");
-                    make_target(&rt, arch, cross_compile, "defconfig", "1")?;
+                    make_target(rt, arch, cross_compile, kernel_cc, "defconfig", "1", &kernel_log, dry_run)?;
 
                     // Enable required kernel configs in groups
                     log::info!("Enabling required kernel configurations...");
-                    enable_kernel_configs(&rt, "CCA", CCA_CONFIGS)?;
-                    enable_kernel_configs(&rt, "9P", NINEP_CONFIGS)?;
-                    enable_kernel_configs(&rt, "Hyper-V", HYPERV_CONFIGS)?;
+                    apply_kernel_configs(rt, "CCA", CCA_CONFIGS, &[], dry_run)?;
+                    apply_kernel_configs(rt, "9P", NINEP_CONFIGS, &[], dry_run)?;
+                    apply_kernel_configs(rt, "Hyper-V", HYPERV_CONFIGS, &[], dry_run)?;
+                    let user_enable: Vec<&str> = extra_kernel_configs.iter().map(String::as_str).collect();
+                    let user_disable: Vec<&str> = disable_kernel_configs.iter().map(String::as_str).collect();
+                    apply_kernel_configs(rt, "user", &user_enable, &user_disable, dry_run)?;
 
                     // Run make olddefconfig
                     log::info!("Running make olddefconfig...");
-                    make_target(&rt, arch, cross_compile, "olddefconfig", "1")?;
-
-                    // Build kernel Image
-                    log::info!("Building kernel Image (this may take several minutes)...");
-                    let nproc = std::thread::available_parallelism()
-                        .map(|n| n.get().to_string())
-                        .unwrap_or_else(|_| "1".to_string());
-                    make_target(&rt, arch, cross_compile, "Image", &nproc)?;
-
-                    // Verify kernel Image was created
-                    if !kernel_image.exists() {
-                        anyhow::bail!("Kernel compilation appeared to succeed but Image file was not created at {}", kernel_image.display());
+                    make_target(rt, arch, cross_compile, kernel_cc, "olddefconfig", "1", &kernel_log, dry_run)?;
+
+                    // Set up module signing if the resolved config needs it
+                    // (there's no resolved config to check in a dry run)
+                    if !dry_run
+                        && configure_module_signing(rt, &host_kernel_dir, module_sign_key.as_deref(), ephemeral_module_sign_key)?
+                    {
+                        make_target(rt, arch, cross_compile, kernel_cc, "olddefconfig", "1", &kernel_log, false)?;
+                    }
+
+                    let cache_entry = match &kernel_cache_dir {
+                        Some(cache_dir) if !dry_run => {
+                            Some(cache_dir.join(kernel_cache_key(rt, &host_kernel_dir, cross_compile, kernel_cc)?))
+                        }
+                        _ => None,
+                    };
+
+                    if dry_run {
+                        let nproc = resolve_kernel_build_jobs(kernel_build_jobs, auto_reduce_kernel_jobs).to_string();
+                        make_target(rt, arch, cross_compile, kernel_cc, KERNEL_IMAGE_TARGET, &nproc, &kernel_log, true)?;
+                    } else if let Some(entry) = &cache_entry
+                        && !force_kernel_rebuild
+                        && restore_cached_kernel(entry, &kernel_image)?
+                    {
+                        log::info!("Restored kernel Image from cache {}", entry.display());
+                    } else {
+                        // Build kernel Image
+                        log::info!("Building kernel Image (this may take several minutes)...");
+                        let nproc = resolve_kernel_build_jobs(kernel_build_jobs, auto_reduce_kernel_jobs).to_string();
+                        make_target(rt, arch, cross_compile, kernel_cc, KERNEL_IMAGE_TARGET, &nproc, &kernel_log, false)?;
+
+                        // Verify kernel Image was created
+                        if !kernel_image.exists() {
+                            anyhow::bail!("Kernel compilation appeared to succeed but Image file was not created at {}", kernel_image.display());
+                        }
+
+                        log::info!("OHCL Linux Kernel compiled successfully");
+
+                        if let Some(entry) = &cache_entry {
+                            store_cached_kernel(entry, &host_kernel_dir, &kernel_image)?;
+                            log::info!("Stored kernel Image in cache {}", entry.display());
+                        }
+                    }
+
+                    if !dry_run && let Some(head) = &kernel_head {
+                        fs_err::write(&last_built_commit, head)?;
                     }
+                    toolchains.kernel_arch = Some(arch.to_string());
+                    toolchains.kernel_cross_compile = Some(cross_compile.to_string());
 
-                    log::info!("OHCL Linux Kernel compiled successfully");
                     log::info!("Kernel Image at: {}", kernel_image.display());
                 } else {
                     log::info!("OHCL Linux Kernel Image already exists at {}", kernel_image.display());
-                    log::info!("To rebuild, delete the Image file and run again");
+                    log::info!("To rebuild, pass --rebuild-kernel");
                 }
+                timings.record("kernel", t0);
 
-                // 4.5) Clone OpenVMM TMK branch with plane0 support and build TMK components
-                let tmk_kernel_dir = toolchain_dir.join("OpenVMM-TMK");
-                clone_or_update_repo(
-                    &rt,
-                    OPENVMM_TMK_REPO,
-                    &tmk_kernel_dir,
-                    update_repo,
-                    Some(OPENVMM_TMK_BRANCH),
-                    "OpenVMM TMK",
-                )?;
+                if gen_compile_commands && prebuilt_kernel_image.is_some() {
+                    log::warn!("Not exporting compile_commands.json, since the kernel wasn't built here");
+                } else if gen_compile_commands && dry_run {
+                    log::info!("DRY-RUN: export the kernel's compile_commands.json to {}", toolchain_dir.display());
+                } else if gen_compile_commands {
+                    let cross_compile = cross_compile_path.to_str()
+                        .ok_or_else(|| anyhow::anyhow!("Invalid cross_compile path"))?;
+                    let t0 = Instant::now();
+                    export_compile_commands(
+                        rt,
+                        &host_kernel_dir,
+                        KERNEL_ARCH,
+                        cross_compile,
+                        kernel_cc,
+                        toolchain_dir,
+                        &kernel_log,
+                    )?;
+                    timings.record("compile_commands.json", t0);
+                }
+
+                // 4.5) Build the TMK components from the OpenVMM TMK branch with
+                // plane0 support
+                let tmk_workspace = tmk_cargo_workspace.clone().unwrap_or_else(|| tmk_kernel_dir.clone());
 
                 // Install Rust targets and build TMK components if do_installs is true
+                let t0 = Instant::now();
                 if do_installs {
                     log::info!("Installing Rust cross-compilation targets...");
-                    flowey::shell_cmd!(rt, "rustup target add aarch64-unknown-linux-gnu").run()?;
-                    flowey::shell_cmd!(rt, "rustup target add aarch64-unknown-none").run()?;
-
-                    // Change to the TMK kernel directory (which should be the openvmm repo root)
-                    rt.sh.change_dir(&tmk_kernel_dir);
-
-                    log::info!("Building TMK components...");
-
-                    // Build simple_tmk
-                    let simple_tmk_binary = tmk_kernel_dir
-                        .join("target")
-                        .join("aarch64-minimal_rt-none")
-                        .join("debug")
-                        .join("simple_tmk");
-                    build_rust_binary(
-                        &rt,
-                        &simple_tmk_binary,
-                        "simple_tmk",
-                        &["--config", "openhcl/minimal_rt/aarch64-config.toml"],
-                    )?;
+                    let tmk_vmm_triple = tmk_vmm_target.triple();
+                    run_or_log(flowey::shell_cmd!(rt, "rustup target add {tmk_vmm_triple}"), dry_run)?;
+                    run_or_log(flowey::shell_cmd!(rt, "rustup target add aarch64-unknown-none"), dry_run)?;
 
-                    // Build tmk_vmm
-                    let tmk_vmm_binary = tmk_kernel_dir
-                        .join("target")
-                        .join("aarch64-unknown-linux-gnu")
-                        .join("debug")
-                        .join("tmk_vmm");
-                    build_rust_binary(
-                        &rt,
-                        &tmk_vmm_binary,
-                        "tmk_vmm",
-                        &["--target", "aarch64-unknown-linux-gnu"],
-                    )?;
+                    // Build in the TMK workspace (by default the openvmm repo root of the TMK clone)
+                    if tmk_cargo_workspace.is_some() {
+                        check_cargo_workspace(&tmk_workspace)?;
+                        log::info!("Using custom TMK cargo workspace at {}", tmk_workspace.display());
+                    }
+
+                    let mut builds = Vec::new();
+                    if tmk_binary_selected(&tmk_binaries, "simple_tmk") {
+                        let simple_tmk_config = tmk_workspace.join(SIMPLE_TMK_CARGO_CONFIG);
+                        let simple_tmk_config = simple_tmk_config.to_str()
+                            .ok_or_else(|| anyhow::anyhow!("Invalid simple_tmk cargo config path"))?;
+                        builds.push(TmkBuild {
+                            package: "simple_tmk",
+                            binary_path: simple_tmk_path(&tmk_workspace),
+                            build_args: vec!["--config".into(), simple_tmk_config.into()],
+                            target: SIMPLE_TMK_TARGET,
+                        });
+                    } else {
+                        log::info!("Skipping simple_tmk build (not selected)");
+                    }
+                    if tmk_binary_selected(&tmk_binaries, "tmk_vmm") {
+                        builds.push(TmkBuild {
+                            package: "tmk_vmm",
+                            binary_path: tmk_vmm_path(&tmk_workspace, tmk_vmm_target),
+                            build_args: tmk_vmm_target.cargo_args(),
+                            target: tmk_vmm_target.triple(),
+                        });
+                    } else {
+                        log::info!("Skipping tmk_vmm build (not selected)");
+                    }
+
+                    // They target different triples and have target dirs of
+                    // their own, so they don't depend on each other
+                    let build = |tmk: &TmkBuild| {
+                        build_rust_binary(&tmk_workspace, &tmk.binary_path, tmk.package, &tmk.build_args, dry_run)
+                    };
+                    let results = if parallel_builds && builds.len() > 1 {
+                        log::info!("Building TMK components in parallel...");
+                        let mut results: Vec<anyhow::Result<()>> = builds.iter().map(|_| Ok(())).collect();
+                        rayon::scope(|s| {
+                            for (tmk, result) in builds.iter().zip(&mut results) {
+                                s.spawn(move |_| *result = build(tmk));
+                            }
+                        });
+                        results
+                    } else {
+                        log::info!("Building TMK components...");
+                        builds.iter().map(build).collect()
+                    };
 
-                    // Return to parent directory
-                    rt.sh.change_dir(shrinkwrap_dir.parent().unwrap());
+                    let errors: Vec<_> = results.into_iter().filter_map(Result::err).collect();
+                    match errors.len() {
+                        0 => {}
+                        1 => return Err(errors.into_iter().next().unwrap()),
+                        _ => anyhow::bail!(
+                            "failed to build the TMK binaries:\n  {}",
+                            errors.iter().map(|e| format!("{:#}", e)).collect::<Vec<_>>().join("\n  ")
+                        ),
+                    }
+                    for tmk in &builds {
+                        toolchains.tmk_targets.insert(tmk.package.into(), tmk.target.into());
+                    }
                 } else {
                     log::info!("Skipping TMK builds (do_installs=false). Run with --install-missing-deps to build.");
                 }
+                timings.record("TMK binaries", t0);
+                if !dry_run {
+                    toolchains.write(toolchain_dir)?;
+                }
 
-                // 5) Clone shrinkwrap repo first (need it for venv location)
-                clone_or_update_repo(
-                    &rt,
-                    SHRINKWRAP_REPO,
-                    &shrinkwrap_dir,
-                    update_repo,
-                    None,
-                    "Shrinkwrap",
-                )?;
-
-                // 5.5) Clone cca_config repo and copy planes.yaml
-                let cca_config_dir = toolchain_dir.join("cca_config");
-                clone_or_update_repo(
-                    &rt,
-                    CCA_CONFIG_REPO,
-                    &cca_config_dir,
-                    update_repo,
-                    None,
-                    "cca_config",
-                )?;
-
-                // Copy planes.yaml to shrinkwrap config directory, cca-3world.yaml configuration does not bring
-                // in the right versions of all the components, this builds a planes-enabled stack
-                let planes_yaml_src = cca_config_dir.join("planes.yaml");
-                let shrinkwrap_config_dir = shrinkwrap_dir.join("config");
-                fs_err::create_dir_all(&shrinkwrap_config_dir)?;
-                let planes_yaml_dest = shrinkwrap_config_dir.join("planes.yaml");
-
-                if planes_yaml_src.exists() {
-                    log::info!("Copying planes.yaml from {} to {}",
-                        planes_yaml_src.display(),
-                        planes_yaml_dest.display());
-                    fs_err::copy(&planes_yaml_src, &planes_yaml_dest)?;
+                // 5) Copy planes.yaml from the cca_config repo
+                if install_cca_config {
+                    // Copy planes.yaml to shrinkwrap config directory, cca-3world.yaml configuration does not bring
+                    // in the right versions of all the components, this builds a planes-enabled stack
+                    let planes_yaml_src = cca_config_dir.join("planes.yaml");
+                    let shrinkwrap_config_dir = shrinkwrap_dir.join("config");
+                    let planes_yaml_dest = shrinkwrap_config_dir.join("planes.yaml");
+
+                    if dry_run {
+                        log::info!("DRY-RUN: cp {} {}", planes_yaml_src.display(), planes_yaml_dest.display());
+                    } else if planes_yaml_src.exists() {
+                        fs_err::create_dir_all(&shrinkwrap_config_dir)?;
+                        log::info!("Copying planes.yaml from {} to {}",
+                            planes_yaml_src.display(),
+                            planes_yaml_dest.display());
+                        fs_err::copy(&planes_yaml_src, &planes_yaml_dest)?;
+                    } else {
+                        log::warn!("planes.yaml not found in cca_config repo at {}", planes_yaml_src.display());
+                    }
                 } else {
-                    log::warn!("planes.yaml not found in cca_config repo at {}", planes_yaml_src.display());
+                    log::info!("Skipping cca_config clone and planes.yaml copy");
                 }
 
                 // 6) Create Python virtual environment and install deps
                 let venv_dir = shrinkwrap_dir.join("venv");
+                let t0 = Instant::now();
                 if do_installs {
+                    let python = python_interpreter.clone().unwrap_or_else(|| PathBuf::from("python3"));
                     if !venv_dir.exists() {
-                        log::info!("Creating Python virtual environment at {}", venv_dir.display());
-                        flowey::shell_cmd!(rt, "python3 -m venv").arg(&venv_dir).run()?;
+                        let version = check_python_version(rt, &python)?;
+                        log::info!(
+                            "Creating Python virtual environment at {} with {} (Python {})",
+                            venv_dir.display(),
+                            python.display(),
+                            version
+                        );
+                        run_or_log(flowey::shell_cmd!(rt, "{python} -m venv").arg(&venv_dir), dry_run)?;
+                    } else if python_interpreter.is_some() {
+                        check_python_version(rt, &python)?;
+                        log::warn!(
+                            "Virtual environment already exists at {}, so it keeps its existing interpreter. Delete it to recreate it with {}",
+                            venv_dir.display(),
+                            python.display()
+                        );
                     }
 
                     log::info!("Installing Python dependencies in virtual environment...");
                     let pip_bin = venv_dir.join("bin").join("pip");
-                    flowey::shell_cmd!(rt, "{pip_bin} install --upgrade pip").run()?;
-                    flowey::shell_cmd!(rt, "{pip_bin} install pyyaml termcolor tuxmake").run()?;
+                    run_or_log(flowey::shell_cmd!(rt, "{pip_bin} install --upgrade pip"), dry_run)?;
+                    run_or_log(flowey::shell_cmd!(rt, "{pip_bin} install {SHRINKWRAP_PYTHON_PACKAGES...}"), dry_run)?;
                 }
+                timings.record("python venv", t0);
 
                 // 7) Validate shrinkwrap entrypoint exists
                 let shrinkwrap_bin_dir = shrinkwrap_dir.join("shrinkwrap");
+                if dry_run {
+                    log::info!("DRY-RUN: done, nothing was changed");
+                    return Ok(());
+                }
                 if !shrinkwrap_bin_dir.exists() {
                     anyhow::bail!(
                         "expected shrinkwrap directory at {}, but it does not exist",
@@ -370,13 +2149,16 @@ fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Res
                 log::info!("");
                 log::info!("Shrinkwrap repo ready at: {}", shrinkwrap_dir.display());
                 log::info!("Virtual environment at: {}", venv_dir.display());
-                log::info!("ARM GNU toolchain ready at: {}", toolchain_extracted_dir.display());
+                match kernel_cc {
+                    Some(cc) => log::info!("Kernel compiler: {}", cc),
+                    None => log::info!("ARM GNU toolchain ready at: {}", toolchain_extracted_dir.display()),
+                }
                 log::info!("OHCL Linux Kernel ready at: {}", host_kernel_dir.display());
                 log::info!("Kernel Image at: {}", kernel_image.display());
 
                 // Check if TMK binaries exist and report their status
-                let simple_tmk_binary = tmk_kernel_dir.join("target").join("aarch64-minimal_rt-none").join("debug").join("simple_tmk");
-                let tmk_vmm_binary = tmk_kernel_dir.join("target").join("aarch64-unknown-linux-gnu").join("debug").join("tmk_vmm");
+                let simple_tmk_binary = simple_tmk_path(&tmk_workspace);
+                let tmk_vmm_binary = tmk_vmm_path(&tmk_workspace, tmk_vmm_target);
 
                 if simple_tmk_binary.exists() {
                     log::info!("simple_tmk binary at: {}", simple_tmk_binary.display());
@@ -393,8 +2175,14 @@ fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Res
                 log::info!("For kernel compilation, set these environment variables:");
                 log::info!("  export ARCH=arm64");
                 log::info!("  export CROSS_COMPILE={}", cross_compile_path.display());
+                if let Some(cc) = kernel_cc {
+                    log::info!("and pass CC={} to make", cc);
+                }
                 log::info!("");
-                log::info!("For TMK builds, Rust targets are installed (aarch64-unknown-linux-gnu, aarch64-unknown-none)");
+                log::info!(
+                    "For TMK builds, Rust targets are installed ({}, aarch64-unknown-none)",
+                    tmk_vmm_target.triple()
+                );
                 log::info!("Or the pipeline will invoke it directly using the venv Python.");
 
                 Ok(())
@@ -0,0 +1,136 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! List the overlay YAMLs available in the shrinkwrap config dir.
+
+use crate::_jobs::shrinkwrap_util::CCA_CONFIG_DIR;
+use flowey::node::prelude::*;
+use std::path::Path;
+
+flowey_request! {
+    pub struct Params {
+        /// Directory where the shrinkwrap repo is cloned (e.g. <out_dir>/shrinkwrap)
+        pub shrinkwrap_dir: PathBuf,
+        pub done: WriteVar<SideEffect>,
+    }
+}
+
+new_simple_flow_node!(struct Node);
+
+/// Description of shrinkwrap config `contents`: its leading comment (minus
+/// copyright/license lines), falling back to its `description` key.
+fn config_description(contents: &str) -> Option<String> {
+    let comment = contents
+        .lines()
+        .map_while(|line| line.trim_start().strip_prefix('#'))
+        .map(str::trim)
+        .filter(|line| {
+            !line.is_empty()
+                && !line.starts_with("Copyright")
+                && !line.starts_with("SPDX-License-Identifier")
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    if !comment.is_empty() {
+        return Some(comment);
+    }
+
+    let config: serde_yaml::Value = serde_yaml::from_str(contents).ok()?;
+    let description = config.get("description")?.as_str()?;
+    Some(description.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+/// Whether shrinkwrap config `contents` is an overlay, i.e. it isn't marked
+/// `concrete` (buildable on its own, like a platform).
+fn is_overlay(contents: &str) -> bool {
+    serde_yaml::from_str::<serde_yaml::Value>(contents)
+        .ok()
+        .and_then(|config| config.get("concrete")?.as_bool())
+        != Some(true)
+}
+
+/// YAML files directly under `dir`, sorted by name.
+fn yaml_files(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    if dir.exists() {
+        for entry in fs_err::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "yaml") {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+impl SimpleFlowNode for Node {
+    type Request = Params;
+
+    fn imports(_ctx: &mut ImportCtx<'_>) {}
+
+    fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Result<()> {
+        let Params {
+            shrinkwrap_dir,
+            done,
+        } = request;
+
+        ctx.emit_rust_step("list shrinkwrap overlays", |ctx| {
+            done.claim(ctx);
+            move |_rt| {
+                let toolchain_dir = shrinkwrap_dir
+                    .parent()
+                    .ok_or_else(|| anyhow::anyhow!("shrinkwrap_dir has no parent"))?;
+                let config_dir = shrinkwrap_dir.join("config");
+
+                // Overlays the install node copied in from cca_config
+                let cca_config_names: Vec<_> = yaml_files(&toolchain_dir.join(CCA_CONFIG_DIR))?
+                    .into_iter()
+                    .filter_map(|path| path.file_name().map(|name| name.to_owned()))
+                    .collect();
+
+                let mut native = Vec::new();
+                let mut from_cca_config = Vec::new();
+                for path in yaml_files(&config_dir)? {
+                    let contents = fs_err::read_to_string(&path)?;
+                    if !is_overlay(&contents) {
+                        continue;
+                    }
+                    let Some(name) = path.file_name() else {
+                        continue;
+                    };
+                    let entry = (
+                        name.to_string_lossy().into_owned(),
+                        config_description(&contents).unwrap_or_default(),
+                    );
+                    if cca_config_names.iter().any(|cca_name| cca_name == name) {
+                        from_cca_config.push(entry);
+                    } else {
+                        native.push(entry);
+                    }
+                }
+
+                println!(
+                    "Overlays in {} (use with --overlay <name>):",
+                    config_dir.display()
+                );
+                for (heading, overlays) in
+                    [("shrinkwrap", &native), ("cca_config", &from_cca_config)]
+                {
+                    println!();
+                    println!("{} overlays:", heading);
+                    if overlays.is_empty() {
+                        println!("  (none)");
+                    }
+                    for (name, description) in overlays {
+                        println!("  {:<32} {}", name, description);
+                    }
+                }
+
+                Ok(())
+            }
+        });
+
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Clone and build the RMM (Realm Management Monitor) from source, for use
+//! in place of the RMM built by shrinkwrap.
+
+use crate::_jobs::local_install_shrinkwrap::CloneOptions;
+use crate::_jobs::local_install_shrinkwrap::RepoUpdatePolicy;
+use crate::_jobs::local_install_shrinkwrap::clone_or_update_repo;
+use crate::_jobs::local_install_shrinkwrap::validate_credential_helper;
+use crate::_jobs::shrinkwrap_util::ToolchainManifest;
+use crate::_jobs::shrinkwrap_util::arm_gnu_cross_compile_prefix;
+use crate::_jobs::shrinkwrap_util::installed_arm_gnu_toolchain_dir;
+use crate::_jobs::shrinkwrap_util::run_or_log;
+use flowey::node::prelude::*;
+
+flowey_request! {
+    pub struct Params {
+        /// Directory to clone the RMM repo into
+        pub rmm_dir: PathBuf,
+        /// RMM git repo to clone
+        pub repo_url: String,
+        /// Branch of the RMM repo to build
+        pub branch: String,
+        /// Build script to run, relative to the RMM repo root
+        pub build_script: PathBuf,
+        /// Binary produced by the build script, relative to the RMM repo root
+        pub output_binary: PathBuf,
+        /// Dir the install node set up the toolchains in. The RMM is built
+        /// with the ARM GNU toolchain recorded there, if there is one.
+        pub toolchain_dir: PathBuf,
+        /// Git credential helper used when cloning/pulling the RMM repo
+        pub git_credential_helper: Option<String>,
+        /// How many times a failed clone or fetch of the RMM repo is retried
+        pub max_clone_retries: u32,
+        /// Verify the GPG signature of the RMM tag, if `branch` is one
+        pub verify_signatures: bool,
+        /// GnuPG home dir holding the keyring to verify signatures with
+        pub gpg_keyring: Option<PathBuf>,
+        /// Only log the clone and build commands
+        pub dry_run: ReadVar<bool>,
+        /// Path of the built RMM binary
+        pub rmm_binary: WriteVar<PathBuf>,
+    }
+}
+
+new_simple_flow_node!(struct Node);
+
+impl SimpleFlowNode for Node {
+    type Request = Params;
+
+    fn imports(_ctx: &mut ImportCtx<'_>) {}
+
+    fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Result<()> {
+        let Params {
+            rmm_dir,
+            repo_url,
+            branch,
+            build_script,
+            output_binary,
+            toolchain_dir,
+            git_credential_helper,
+            max_clone_retries,
+            verify_signatures,
+            gpg_keyring,
+            dry_run,
+            rmm_binary,
+        } = request;
+
+        ctx.emit_rust_step("build rmm from source", |ctx| {
+            let rmm_binary = rmm_binary.claim(ctx);
+            let dry_run = dry_run.claim(ctx);
+            move |rt| {
+                let dry_run = rt.read(dry_run);
+                if let Some(helper) = &git_credential_helper {
+                    validate_credential_helper(rt, helper)?;
+                }
+                clone_or_update_repo(
+                    &repo_url,
+                    &rmm_dir,
+                    None,
+                    RepoUpdatePolicy::Always,
+                    Some(&branch),
+                    "RMM",
+                    &CloneOptions {
+                        credential_helper: git_credential_helper.as_deref(),
+                        verify_signatures,
+                        gpg_keyring: gpg_keyring.as_deref(),
+                        max_retries: max_clone_retries,
+                        dry_run,
+                        ..Default::default()
+                    },
+                )?;
+
+                let build_script = rmm_dir.join(build_script);
+                // A dry run doesn't clone, so there may be nothing to check yet
+                if !build_script.exists() && (!dry_run || rmm_dir.exists()) {
+                    anyhow::bail!("RMM build script not found at {}", build_script.display());
+                }
+
+                // Resolved only now that the install node has run, since the
+                // toolchain it sets up changes with --toolchain-version and
+                // there is none with --toolchain-flavor llvm-clang
+                let cross_compile = ToolchainManifest::read(&toolchain_dir)?
+                    .filter(|toolchains| toolchains.arm_gnu_toolchain_version.is_some())
+                    .map(|_| {
+                        arm_gnu_cross_compile_prefix(&installed_arm_gnu_toolchain_dir(
+                            &toolchain_dir,
+                        ))
+                    });
+
+                log::info!("Building RMM with {}...", build_script.display());
+                rt.sh.change_dir(&rmm_dir);
+                let mut cmd = flowey::shell_cmd!(rt, "{build_script}");
+                match &cross_compile {
+                    Some(cross_compile) => {
+                        log::info!("CROSS_COMPILE={}", cross_compile.display());
+                        cmd = cmd.env("CROSS_COMPILE", cross_compile);
+                    }
+                    None => log::info!(
+                        "No ARM GNU toolchain is installed in {}, leaving CROSS_COMPILE to the RMM build script",
+                        toolchain_dir.display()
+                    ),
+                }
+                run_or_log(cmd, dry_run).with_context(|| {
+                    format!("Failed to run RMM build script {}", build_script.display())
+                })?;
+
+                let output_binary = rmm_dir.join(output_binary);
+                if !dry_run && !output_binary.exists() {
+                    anyhow::bail!(
+                        "RMM build appeared to succeed but {} was not created",
+                        output_binary.display()
+                    );
+                }
+
+                log::info!("RMM built at: {}", output_binary.display());
+                rt.write(rmm_binary, &output_binary);
+                Ok(())
+            }
+        });
+
+        Ok(())
+    }
+}
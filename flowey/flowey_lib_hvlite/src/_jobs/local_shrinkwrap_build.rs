@@ -3,11 +3,107 @@
 
 //! Run shrinkwrap build command to build FVP artifacts.
 
+use crate::_jobs::shrinkwrap_util::BUILD_ID_FILE;
+use crate::_jobs::shrinkwrap_util::KERNEL_COMPILE_COMMANDS;
+use crate::_jobs::shrinkwrap_util::PipelinePhase;
+use crate::_jobs::shrinkwrap_util::StageTimings;
+use crate::_jobs::shrinkwrap_util::ToolchainManifest;
+use crate::_jobs::shrinkwrap_util::check_dir_writable;
+use crate::_jobs::shrinkwrap_util::describe_exit_status;
+use crate::_jobs::shrinkwrap_util::enter_pipeline_phase;
+use crate::_jobs::shrinkwrap_util::forward_lines;
+use crate::_jobs::shrinkwrap_util::kill_process_group;
+use crate::_jobs::shrinkwrap_util::log_dry_run;
+use crate::_jobs::shrinkwrap_util::next_build_id;
+use crate::_jobs::shrinkwrap_util::report_invalid_params;
+use crate::_jobs::shrinkwrap_util::set_shrinkwrap_state_dir;
+use crate::_jobs::shrinkwrap_util::sha256_file;
+use crate::_jobs::shrinkwrap_util::shrinkwrap_package_dir;
+use crate::_jobs::shrinkwrap_util::spawn_in_process_group;
+use crate::_jobs::shrinkwrap_util::tee_command_output;
+use crate::_jobs::shrinkwrap_util::wait_with_timeout;
+use crate::_jobs::shrinkwrap_util::write_line;
+use crate::_jobs::shrinkwrap_util::write_repro_script;
+use crate::_jobs::shrinkwrap_util::write_run_result;
 use flowey::node::prelude::*;
-use std::io::{BufRead, BufReader, Write};
+use notify_debouncer_mini::DebounceEventResult;
+use notify_debouncer_mini::Debouncer;
+use notify_debouncer_mini::new_debouncer;
+use notify_debouncer_mini::notify::RecommendedWatcher;
+use notify_debouncer_mini::notify::RecursiveMode;
+use redis::AsyncCommands;
+use sha2::Digest;
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+use std::process::ExitStatus;
 use std::process::Stdio;
-use std::sync::{Arc, Mutex};
-use std::thread;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// How often watch mode checks for Ctrl-C while waiting for file events.
+const WATCH_INTERRUPT_INTERVAL: Duration = Duration::from_millis(200);
+/// Changes within this long of the first one are folded into a single rebuild.
+const WATCH_DEBOUNCE: Duration = Duration::from_secs(1);
+
+/// How long cached builds are kept in the Redis build cache.
+const REDIS_CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Index of the files a successful build produced, under `<out_dir>/logs`,
+/// for CI to publish them from.
+const ARTIFACT_INDEX: &str = "artifact-index.json";
+
+/// Inputs of the last successful build, under `<out_dir>/logs`.
+const BUILD_MANIFEST: &str = "build-manifest.json";
+
+/// Redis server used to share build artifacts between machines.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RedisCacheConfig {
+    /// Redis server URL (e.g. `redis://cache.example.com:6379`)
+    pub url: String,
+    /// Prefix of the cache keys, so a server can be shared
+    pub key_prefix: String,
+}
+
+/// How `shrinkwrap build` output is written to the console and build log.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OutputMode {
+    /// Echo each line as it is produced
+    #[default]
+    Streaming,
+    /// Collect all output and write it once the build exits. Faster, but
+    /// there is no live view of the build.
+    Buffered,
+}
+
+/// Shrinkwrap subcommand run by the build node.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ShrinkwrapAction {
+    /// `shrinkwrap build`
+    #[default]
+    Build,
+    /// `shrinkwrap clean`: clear shrinkwrap's build state for the platform
+    Clean,
+}
+
+impl ShrinkwrapAction {
+    fn subcommand(self) -> &'static str {
+        match self {
+            ShrinkwrapAction::Build => "build",
+            ShrinkwrapAction::Clean => "clean",
+        }
+    }
+}
 
 flowey_request! {
     pub struct Params {
@@ -16,126 +112,1332 @@ pub struct Params {
         pub platform_yaml: PathBuf,
         pub overlays: Vec<PathBuf>,
         pub btvars: Vec<String>,      // "KEY=VALUE"
+        /// Extra arguments appended to `shrinkwrap build`, after the btvars
+        pub extra_args: Vec<String>,
+        /// Shrinkwrap subcommand to run with the platform/overlays
+        pub action: ShrinkwrapAction,
+        /// On failure, rebuild just the component that failed, then resume
+        /// the full build
+        pub retry_failed_components: bool,
+        /// How build output is written to the console and build log
+        pub output_mode: OutputMode,
+        /// Kill the build if a component produces no output for this many
+        /// seconds
+        pub component_build_timeout_sec: Option<u64>,
+        /// Write a script reproducing the resolved `shrinkwrap build`
+        /// invocation into this dir (e.g. a published artifact)
+        pub repro_dir: Option<ReadVar<PathBuf>>,
+        /// After the first build, keep rebuilding whenever the platform or
+        /// overlay YAMLs change, until Ctrl-C
+        pub watch: bool,
+        /// Before building, check that every config the platform and overlay
+        /// YAMLs `include` exists in the shrinkwrap config dir
+        pub check_includes: bool,
+        /// Validate the platform and overlay YAMLs before building, with
+        /// `shrinkwrap validate` if available
+        pub pre_validate: bool,
+        /// Pipeline-wide timeout in seconds, counted from when the job starts
+        pub pipeline_timeout_sec: Option<u64>,
+        /// Counter file used to assign each build a unique, increasing
+        /// build number (written to `<out_dir>/build-id.json`)
+        pub build_id_file: Option<PathBuf>,
+        /// Fetch the build artifacts from this Redis cache instead of
+        /// building, if they're cached, and cache them after building
+        pub redis_cache: Option<RedisCacheConfig>,
+        /// Keep shrinkwrap's build and package dirs under this dir, instead
+        /// of the user-wide ones under `~/.shrinkwrap`
+        pub shrinkwrap_state_dir: Option<PathBuf>,
+        /// Build even if the inputs match those of the last successful build
+        /// (see `<out_dir>/logs/build-manifest.json`)
+        pub force: bool,
+        /// After a successful build, write an index of its artifacts to
+        /// `<out_dir>/logs/artifact-index.json`
+        pub emit_artifact_index: bool,
+        /// Don't check, before running shrinkwrap, that the platform and
+        /// overlay YAMLs exist and parse, and that every btvar they reference
+        /// is set
+        pub skip_preflight: bool,
+        /// Record a failed build in this result file (as read by the matrix
+        /// report) instead of failing the job
+        pub result_file: Option<PathBuf>,
+        /// Only log the shrinkwrap command, after checking the configs
+        pub dry_run: ReadVar<bool>,
         pub done: WriteVar<SideEffect>,
     }
 }
 
 new_simple_flow_node!(struct Node);
 
+impl Params {
+    /// Check the parameters up front, reporting every problem at once rather
+    /// than leaving shrinkwrap to fail on them.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut problems = Vec::new();
+
+        check_dir_writable(&self.out_dir, &mut problems);
+        if let Some(toolchain_dir) = self.shrinkwrap_dir.parent() {
+            check_dir_writable(toolchain_dir, &mut problems);
+        } else {
+            problems.push(format!(
+                "shrinkwrap dir {} has no parent",
+                self.shrinkwrap_dir.display()
+            ));
+        }
+
+        // Configs are looked up in the shrinkwrap config dir too, which only
+        // exists once the install step has cloned shrinkwrap (not in a dry run)
+        let config_dir = self.shrinkwrap_dir.join("config");
+        if config_dir.is_dir() {
+            for config in std::iter::once(&self.platform_yaml).chain(&self.overlays) {
+                if !(config.exists() || config.is_relative() && config_dir.join(config).exists()) {
+                    problems.push(format!(
+                        "config {} not found (nor in {})",
+                        config.display(),
+                        config_dir.display()
+                    ));
+                }
+            }
+        }
+
+        for btvar in &self.btvars {
+            if btvar.split_once('=').is_none_or(|(key, _)| key.is_empty()) {
+                problems.push(format!("invalid btvar '{}', expected KEY=VALUE", btvar));
+            }
+        }
+
+        if self.watch && self.action != ShrinkwrapAction::Build {
+            problems.push("watch mode is only supported for shrinkwrap build".to_string());
+        }
+        if self.watch && self.redis_cache.is_some() {
+            problems.push("the redis build cache is not supported in watch mode".to_string());
+        }
+        if self.component_build_timeout_sec == Some(0) {
+            problems.push("component build timeout must be at least 1 second".to_string());
+        }
+
+        report_invalid_params("build", problems)
+    }
+}
+
+/// Arguments shared by every shrinkwrap invocation of a step.
+struct ShrinkwrapCommand<'a> {
+    action: ShrinkwrapAction,
+    out_dir: &'a Path,
+    shrinkwrap_dir: &'a Path,
+    platform_yaml: &'a Path,
+    overlays: &'a [PathBuf],
+    btvars: &'a [String],
+    extra_args: &'a [String],
+    output_mode: OutputMode,
+    component_timeout: Option<Duration>,
+    state_dir: Option<&'a Path>,
+}
+
+impl ShrinkwrapCommand<'_> {
+    /// The bare shrinkwrap command, set up to run from the venv.
+    fn base_command(&self) -> Command {
+        // Build command line - use shrinkwrap wrapper script with venv activated
+        let shrinkwrap_exe = self.shrinkwrap_dir.join("shrinkwrap").join("shrinkwrap");
+        let venv_dir = self.shrinkwrap_dir.join("venv");
+        let venv_bin = venv_dir.join("bin");
+
+        let mut cmd = Command::new(&shrinkwrap_exe);
+        cmd.current_dir(self.out_dir); // keep build outputs contained
+
+        // Set environment to use venv Python
+        cmd.env("VIRTUAL_ENV", &venv_dir);
+        cmd.env(
+            "PATH",
+            format!(
+                "{}:{}",
+                venv_bin.display(),
+                std::env::var("PATH").unwrap_or_default()
+            ),
+        );
+        if let Some(state_dir) = self.state_dir {
+            set_shrinkwrap_state_dir(&mut cmd, state_dir);
+        }
+
+        cmd
+    }
+
+    /// Returns true if this shrinkwrap version has the `validate` subcommand.
+    fn has_validate(&self) -> anyhow::Result<bool> {
+        let output = self.base_command().arg("--help").output()?;
+        Ok(String::from_utf8_lossy(&output.stdout).contains("validate"))
+    }
+
+    /// Run `shrinkwrap validate` on the platform and overlays.
+    fn validate(&self) -> anyhow::Result<()> {
+        let status = self
+            .base_command()
+            .arg("validate")
+            .arg(self.platform_yaml)
+            .args(self.overlays)
+            .status()?;
+        if !status.success() {
+            anyhow::bail!("shrinkwrap validate {}", describe_exit_status(status));
+        }
+        Ok(())
+    }
+
+    /// Build the shrinkwrap command, optionally limited to a single
+    /// component.
+    fn command(&self, component: Option<&str>) -> Command {
+        let mut cmd = self.base_command();
+
+        cmd.arg(self.action.subcommand());
+        cmd.arg(self.platform_yaml);
+
+        for ov in self.overlays {
+            cmd.arg("--overlay").arg(ov);
+        }
+
+        for bt in self.btvars {
+            cmd.arg("--btvar").arg(bt);
+        }
+
+        if self.action == ShrinkwrapAction::Build {
+            cmd.args(self.extra_args);
+        }
+
+        if let Some(component) = component {
+            cmd.arg("--component").arg(component);
+        }
+
+        cmd
+    }
+
+    /// Run the shrinkwrap command (optionally limited to a single component),
+    /// writing its stdout/stderr to the console and to `log_path` according
+    /// to the configured output mode.
+    fn run(
+        &self,
+        component: Option<&str>,
+        log_path: &Path,
+        append: bool,
+    ) -> anyhow::Result<ExitStatus> {
+        let mut cmd = self.command(component);
+        if !append {
+            fs_err::File::create(log_path)?;
+        }
+
+        // Without a watchdog or buffering, this is a plain tee
+        if self.component_timeout.is_none() && self.output_mode == OutputMode::Streaming {
+            return tee_command_output(cmd, log_path);
+        }
+
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::piped());
+
+        let mut log_file = BufWriter::new(
+            fs_err::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(log_path)?,
+        );
+
+        // So the watchdogs can kill everything the build spawned
+        let mut child = spawn_in_process_group(&mut cmd)?;
+
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("failed to capture stdout"))?;
+        let stderr = child
+            .stderr
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("failed to capture stderr"))?;
+
+        // Funnel both streams through a single channel, so lines are written
+        // in the order they were read without sharing the file
+        let (tx, rx) = mpsc::channel();
+        let stdout_thread = forward_lines(stdout, false, tx.clone());
+        let stderr_thread = forward_lines(stderr, true, tx);
+
+        let mut buffered: Vec<(bool, String)> = Vec::new();
+        let mut current_component = None;
+        loop {
+            // Acts as a watchdog: reset on every line of output
+            let next = match self.component_timeout {
+                Some(timeout) => rx.recv_timeout(timeout),
+                None => rx.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+            };
+            let (is_stderr, line) = match next {
+                Ok(line) => line,
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    kill_process_group(&mut child);
+                    for (is_stderr, line) in buffered.drain(..) {
+                        write_line(&mut log_file, is_stderr, &line)?;
+                    }
+                    log_file.flush()?;
+                    anyhow::bail!(
+                        "shrinkwrap build produced no output for {}s while building component '{}', killed it",
+                        self.component_timeout.unwrap_or_default().as_secs(),
+                        current_component.as_deref().unwrap_or("<unknown>")
+                    );
+                }
+            };
+
+            if let Some(component) = line_component(&line) {
+                current_component = Some(component.to_string());
+            }
+
+            match self.output_mode {
+                OutputMode::Streaming => write_line(&mut log_file, is_stderr, &line)?,
+                OutputMode::Buffered => buffered.push((is_stderr, line)),
+            }
+        }
+
+        for (is_stderr, line) in buffered {
+            write_line(&mut log_file, is_stderr, &line)?;
+        }
+
+        // Wait for threads to finish
+        let _ = stdout_thread.join();
+        let _ = stderr_thread.join();
+
+        log_file.flush()?;
+
+        // Wait for child process
+        wait_with_timeout(&mut child, None)
+    }
+}
+
+/// Returns the component a shrinkwrap output line belongs to, from its
+/// `[<component>]` prefix.
+fn line_component(line: &str) -> Option<&str> {
+    let (component, _) = line.strip_prefix('[')?.split_once(']')?;
+    (!component.is_empty() && !component.contains(char::is_whitespace)).then_some(component)
+}
+
+/// Find the last component reported as failed (`ERROR: component <name>
+/// failed`) in a shrinkwrap build log.
+fn failed_component(log_path: &Path) -> anyhow::Result<Option<String>> {
+    let log = fs_err::read_to_string(log_path)?;
+    Ok(log.lines().rev().find_map(|line| {
+        let rest = &line[line.find("ERROR: component ")? + "ERROR: component ".len()..];
+        let name = rest
+            .strip_suffix(" failed")
+            .or_else(|| rest.split_once(" failed").map(|(name, _)| name))?;
+        (!name.is_empty()).then(|| name.to_string())
+    }))
+}
+
+/// SHA-256 of every packaged artifact, keyed by path relative to the package
+/// dir. Written to `<out_dir>/artifacts.json` after each successful build.
+#[derive(Default, Serialize, Deserialize)]
+struct ArtifactManifest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    build_id: Option<String>,
+    /// The exported kernel `compile_commands.json`, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kernel_compile_commands: Option<PathBuf>,
+    /// Interpreter the shrinkwrap venv was created with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    python_interpreter: Option<String>,
+    /// Version of `python_interpreter`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    python_version: Option<String>,
+    artifacts: BTreeMap<String, String>,
+}
+
+impl ArtifactManifest {
+    /// Hash every file under `package_dir`.
+    fn from_dir(package_dir: &Path, build_id: Option<&str>) -> anyhow::Result<Self> {
+        let mut artifacts = BTreeMap::new();
+        let mut pending = vec![package_dir.to_path_buf()];
+        while let Some(dir) = pending.pop() {
+            for entry in fs_err::read_dir(&dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    pending.push(path);
+                } else {
+                    let name = path
+                        .strip_prefix(package_dir)?
+                        .to_string_lossy()
+                        .into_owned();
+                    artifacts.insert(name, sha256_file(&path)?);
+                }
+            }
+        }
+        Ok(Self {
+            build_id: build_id.map(str::to_string),
+            kernel_compile_commands: None,
+            python_interpreter: None,
+            python_version: None,
+            artifacts,
+        })
+    }
+}
+
+/// A file in the artifact index.
+#[derive(Serialize)]
+struct ArtifactEntry {
+    /// Path relative to the package dir
+    path: String,
+    size_bytes: u64,
+    sha256: String,
+    /// Last modification time, as RFC 3339
+    modified_at: String,
+}
+
+/// Write an index of every file under `package_dir` to
+/// `<out_dir>/logs/artifact-index.json`.
+fn write_artifact_index(out_dir: &Path, package_dir: &Path) -> anyhow::Result<()> {
+    let mut entries = Vec::new();
+    let mut pending = vec![package_dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs_err::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            let metadata = fs_err::metadata(&path)?;
+            entries.push(ArtifactEntry {
+                path: path
+                    .strip_prefix(package_dir)?
+                    .to_string_lossy()
+                    .into_owned(),
+                size_bytes: metadata.len(),
+                sha256: sha256_file(&path)?,
+                modified_at: time::OffsetDateTime::from(metadata.modified()?)
+                    .format(&time::format_description::well_known::Rfc3339)?,
+            });
+        }
+    }
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let index_path = out_dir.join("logs").join(ARTIFACT_INDEX);
+    fs_err::write(&index_path, serde_json::to_string_pretty(&entries)?)?;
+    log::info!(
+        "Wrote index of {} artifacts to {}",
+        entries.len(),
+        index_path.display()
+    );
+    Ok(())
+}
+
+/// Inputs of a successful build, so a re-run of an interrupted pipeline can
+/// skip a build that already finished.
+#[derive(PartialEq, Eq, Serialize, Deserialize)]
+struct BuildManifest {
+    /// Commit the shrinkwrap clone was at
+    #[serde(default)]
+    shrinkwrap_revision: String,
+    /// ARM GNU toolchain version the install node recorded
+    #[serde(default)]
+    arm_gnu_toolchain_version: Option<String>,
+    platform_yaml_sha256: String,
+    /// Path and SHA-256 of each overlay, in the order they're applied
+    overlays: Vec<(PathBuf, String)>,
+    btvars: Vec<String>,
+    #[serde(default)]
+    extra_args: Vec<String>,
+}
+
+impl BuildManifest {
+    fn new(shrinkwrap: &ShrinkwrapCommand<'_>) -> anyhow::Result<Self> {
+        let toolchains = match shrinkwrap.shrinkwrap_dir.parent() {
+            Some(toolchain_dir) => ToolchainManifest::read(toolchain_dir)?,
+            None => None,
+        };
+        Ok(Self {
+            shrinkwrap_revision: shrinkwrap_revision(shrinkwrap.shrinkwrap_dir)?,
+            arm_gnu_toolchain_version: toolchains
+                .and_then(|toolchains| toolchains.arm_gnu_toolchain_version),
+            platform_yaml_sha256: sha256_file(shrinkwrap.platform_yaml)?,
+            overlays: shrinkwrap
+                .overlays
+                .iter()
+                .map(|ov| Ok((ov.clone(), sha256_file(ov)?)))
+                .collect::<anyhow::Result<_>>()?,
+            btvars: shrinkwrap.btvars.to_vec(),
+            extra_args: shrinkwrap.extra_args.to_vec(),
+        })
+    }
+
+    fn path(out_dir: &Path) -> PathBuf {
+        out_dir.join("logs").join(BUILD_MANIFEST)
+    }
+
+    /// Whether the last successful build in `out_dir` had the same inputs.
+    fn is_current(shrinkwrap: &ShrinkwrapCommand<'_>) -> anyhow::Result<bool> {
+        let path = Self::path(shrinkwrap.out_dir);
+        if !path.exists() {
+            return Ok(false);
+        }
+        let last: Self = match serde_json::from_str(&fs_err::read_to_string(&path)?) {
+            Ok(last) => last,
+            Err(e) => {
+                log::warn!(
+                    "Ignoring unreadable build manifest {}: {}",
+                    path.display(),
+                    e
+                );
+                return Ok(false);
+            }
+        };
+        Ok(last == Self::new(shrinkwrap)?)
+    }
+
+    fn write(&self, out_dir: &Path) -> anyhow::Result<()> {
+        fs_err::write(Self::path(out_dir), serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Interpreter and version the venv at `venv_dir` was created with, from
+/// its `pyvenv.cfg`.
+fn venv_python(venv_dir: &Path) -> (Option<String>, Option<String>) {
+    let Ok(cfg) = fs_err::read_to_string(venv_dir.join("pyvenv.cfg")) else {
+        return (None, None);
+    };
+    let value = |key: &str| {
+        cfg.lines()
+            .filter_map(|line| line.split_once('='))
+            .find(|(k, _)| k.trim() == key)
+            .map(|(_, v)| v.trim().to_string())
+    };
+    (
+        value("executable").or_else(|| value("home")),
+        value("version").or_else(|| value("version_info")),
+    )
+}
+
+/// Record the artifact manifest for this build, and write a
+/// `build-diff.txt` comparing it to the previous build's manifest.
+fn write_build_diff(
+    out_dir: &Path,
+    package_dir: &Path,
+    venv_dir: &Path,
+    build_id: Option<&str>,
+) -> anyhow::Result<()> {
+    let manifest_path = out_dir.join("artifacts.json");
+    let prev_manifest_path = out_dir.join(".prev-artifacts.json");
+    let diff_path = out_dir.join("build-diff.txt");
+
+    if manifest_path.exists() {
+        fs_err::rename(&manifest_path, &prev_manifest_path)?;
+    }
+
+    let mut current = ArtifactManifest::from_dir(package_dir, build_id)?;
+    let compile_commands = out_dir.join(KERNEL_COMPILE_COMMANDS);
+    if compile_commands.exists() {
+        current.kernel_compile_commands = Some(compile_commands);
+    }
+    (current.python_interpreter, current.python_version) = venv_python(venv_dir);
+    fs_err::write(&manifest_path, serde_json::to_string_pretty(&current)?)?;
+
+    let previous: ArtifactManifest = if prev_manifest_path.exists() {
+        serde_json::from_str(&fs_err::read_to_string(&prev_manifest_path)?)?
+    } else {
+        log::info!("No previous artifact manifest, treating all artifacts as new");
+        ArtifactManifest::default()
+    };
+
+    let mut new = Vec::new();
+    let mut changed = Vec::new();
+    for (name, digest) in &current.artifacts {
+        match previous.artifacts.get(name) {
+            None => new.push(name),
+            Some(prev_digest) if prev_digest != digest => changed.push(name),
+            Some(_) => {}
+        }
+    }
+    let removed: Vec<_> = previous
+        .artifacts
+        .keys()
+        .filter(|name| !current.artifacts.contains_key(*name))
+        .collect();
+
+    let mut diff = String::new();
+    for (heading, names) in [("New", &new), ("Removed", &removed), ("Changed", &changed)] {
+        diff.push_str(&format!("{} artifacts ({}):\n", heading, names.len()));
+        for name in names {
+            diff.push_str(&format!("  {}\n", name));
+        }
+    }
+    fs_err::write(&diff_path, diff)?;
+
+    log::info!(
+        "{} artifacts changed, {} new, {} removed since last build (see {})",
+        changed.len(),
+        new.len(),
+        removed.len(),
+        diff_path.display()
+    );
+    Ok(())
+}
+
+/// Commit the shrinkwrap clone at `shrinkwrap_dir` is at.
+fn shrinkwrap_revision(shrinkwrap_dir: &Path) -> anyhow::Result<String> {
+    let revision = Command::new("git")
+        .arg("-C")
+        .arg(shrinkwrap_dir)
+        .args(["rev-parse", "HEAD"])
+        .output()?;
+    if !revision.status.success() {
+        anyhow::bail!("failed to get shrinkwrap revision");
+    }
+    Ok(String::from_utf8_lossy(&revision.stdout).trim().to_string())
+}
+
+/// Fingerprint of everything that determines the build's artifacts: the
+/// shrinkwrap revision, the platform and overlay configs, the btvars and any
+/// extra arguments.
+fn build_fingerprint(shrinkwrap: &ShrinkwrapCommand<'_>) -> anyhow::Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(shrinkwrap_revision(shrinkwrap.shrinkwrap_dir)?);
+    let configs = std::iter::once(shrinkwrap.platform_yaml)
+        .chain(shrinkwrap.overlays.iter().map(|ov| ov.as_path()));
+    for config in configs {
+        hasher.update(fs_err::read(config)?);
+    }
+    for arg in shrinkwrap.btvars.iter().chain(shrinkwrap.extra_args) {
+        hasher.update(arg.as_bytes());
+        hasher.update([0]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Download the build archive cached under `key`, or `None` on a cache miss.
+async fn download_cached_build(
+    cache: &RedisCacheConfig,
+    key: &str,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    let client = redis::Client::open(cache.url.as_str())?;
+    let mut con = client.get_multiplexed_async_connection().await?;
+    Ok(con.get(key).await?)
+}
+
+/// Upload the build `archive` to the cache under `key`, expiring at
+/// `expire_at` (seconds since the Unix epoch).
+async fn upload_cached_build(
+    cache: &RedisCacheConfig,
+    key: &str,
+    archive: Vec<u8>,
+    expire_at: i64,
+) -> anyhow::Result<()> {
+    let client = redis::Client::open(cache.url.as_str())?;
+    let mut con = client.get_multiplexed_async_connection().await?;
+    redis::pipe()
+        .atomic()
+        .set(key, archive)
+        .ignore()
+        .expire_at(key, expire_at)
+        .ignore()
+        .query_async::<()>(&mut con)
+        .await?;
+    Ok(())
+}
+
+/// Extract the artifacts cached under `key` into `package_dir`. Returns false
+/// on a cache miss.
+fn fetch_cached_build(
+    cache: &RedisCacheConfig,
+    key: &str,
+    package_dir: &Path,
+    out_dir: &Path,
+) -> anyhow::Result<bool> {
+    let Some(data) = tokio::runtime::Runtime::new()?.block_on(download_cached_build(cache, key))?
+    else {
+        return Ok(false);
+    };
+
+    let archive = out_dir.join("build-cache.tar.gz");
+    fs_err::write(&archive, data)?;
+
+    let parent = package_dir
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("package dir has no parent"))?;
+    fs_err::create_dir_all(parent)?;
+    if package_dir.exists() {
+        fs_err::remove_dir_all(package_dir)?;
+    }
+    let status = Command::new("tar")
+        .arg("-xzf")
+        .arg(&archive)
+        .arg("-C")
+        .arg(parent)
+        .status()?;
+    fs_err::remove_file(&archive)?;
+    if !status.success() {
+        anyhow::bail!(
+            "failed to extract cached build: tar {}",
+            describe_exit_status(status)
+        );
+    }
+    Ok(true)
+}
+
+/// Upload the artifacts in `package_dir` to the cache under `key`, expiring
+/// after [`REDIS_CACHE_TTL`].
+fn store_cached_build(
+    cache: &RedisCacheConfig,
+    key: &str,
+    package_dir: &Path,
+    out_dir: &Path,
+) -> anyhow::Result<()> {
+    let (parent, name) = package_dir
+        .parent()
+        .zip(package_dir.file_name())
+        .ok_or_else(|| anyhow::anyhow!("invalid package dir {}", package_dir.display()))?;
+
+    let archive = out_dir.join("build-cache.tar.gz");
+    let status = Command::new("tar")
+        .arg("-czf")
+        .arg(&archive)
+        .arg("-C")
+        .arg(parent)
+        .arg(name)
+        .status()?;
+    if !status.success() {
+        anyhow::bail!(
+            "failed to archive build artifacts: tar {}",
+            describe_exit_status(status)
+        );
+    }
+    let data = fs_err::read(&archive);
+    fs_err::remove_file(&archive)?;
+
+    let expire_at = (SystemTime::now() + REDIS_CACHE_TTL)
+        .duration_since(UNIX_EPOCH)?
+        .as_secs()
+        .try_into()?;
+    tokio::runtime::Runtime::new()?.block_on(upload_cached_build(cache, key, data?, expire_at))
+}
+
+/// Parse shrinkwrap config `yaml`.
+fn parse_config(yaml: &Path) -> anyhow::Result<serde_yaml::Value> {
+    // Skip the `%YAML 1.2` directive shrinkwrap configs start with
+    let contents: String = fs_err::read_to_string(yaml)?
+        .lines()
+        .filter(|line| !line.starts_with('%'))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    let config: serde_yaml::Value = serde_yaml::from_str(&contents)
+        .with_context(|| format!("failed to parse {}", yaml.display()))?;
+    if !config.is_mapping() && !config.is_null() {
+        anyhow::bail!(
+            "{} is not a shrinkwrap config (top level is not a mapping)",
+            yaml.display()
+        );
+    }
+    Ok(config)
+}
+
+/// Configs listed under the top-level `include` key of shrinkwrap config
+/// `yaml`.
+fn config_includes(yaml: &Path) -> anyhow::Result<Vec<String>> {
+    let config = parse_config(yaml)?;
+    Ok(config
+        .get("include")
+        .and_then(|include| include.as_sequence())
+        .map(|include| {
+            include
+                .iter()
+                .filter_map(|entry| entry.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+/// Check that every config transitively included by `configs` exists in
+/// `config_dir`, reporting all missing includes at once.
+fn check_config_includes(config_dir: &Path, configs: &[&Path]) -> anyhow::Result<()> {
+    let mut pending: Vec<PathBuf> = configs.iter().map(|c| c.to_path_buf()).collect();
+    let mut visited = BTreeSet::new();
+    let mut missing = Vec::new();
+
+    while let Some(config) = pending.pop() {
+        if !visited.insert(config.clone()) || !config.exists() {
+            continue;
+        }
+        for include in config_includes(&config)? {
+            let path = config_dir.join(&include);
+            if path.exists() {
+                pending.push(path);
+            } else {
+                missing.push(format!("{} (included by {})", include, config.display()));
+            }
+        }
+    }
+
+    if !missing.is_empty() {
+        for include in &missing {
+            log::error!("Missing config: {}", include);
+        }
+        anyhow::bail!(
+            "{} included config(s) missing from {}",
+            missing.len(),
+            config_dir.display()
+        );
+    }
+    Ok(())
+}
+
+/// A `${btvar:NAME}` reference in a shrinkwrap config.
+struct BtvarRef {
+    name: String,
+    /// Dotted path of the key whose value holds the reference
+    key: String,
+    config: PathBuf,
+}
+
+/// Collect the btvars referenced by `value` (found at `key`) into `refs`.
+fn collect_btvar_refs(
+    value: &serde_yaml::Value,
+    key: &str,
+    config: &Path,
+    refs: &mut Vec<BtvarRef>,
+) {
+    match value {
+        serde_yaml::Value::String(s) => {
+            let mut rest = s.as_str();
+            while let Some(start) = rest.find("${btvar:") {
+                rest = &rest[start + "${btvar:".len()..];
+                let Some(end) = rest.find('}') else { break };
+                refs.push(BtvarRef {
+                    name: rest[..end].to_string(),
+                    key: key.to_string(),
+                    config: config.to_path_buf(),
+                });
+                rest = &rest[end..];
+            }
+        }
+        serde_yaml::Value::Sequence(seq) => {
+            for (i, item) in seq.iter().enumerate() {
+                collect_btvar_refs(item, &format!("{}[{}]", key, i), config, refs);
+            }
+        }
+        serde_yaml::Value::Mapping(map) => {
+            for (k, v) in map {
+                let k = match k {
+                    serde_yaml::Value::String(k) => k.clone(),
+                    k => serde_yaml::to_string(k)
+                        .unwrap_or_default()
+                        .trim()
+                        .to_string(),
+                };
+                let key = if key.is_empty() {
+                    k
+                } else {
+                    format!("{}.{}", key, k)
+                };
+                collect_btvar_refs(v, &key, config, refs);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Check the platform and overlay YAMLs before handing them to shrinkwrap,
+/// which otherwise only reports these problems with a Python traceback,
+/// possibly after downloading several GB of sources:
+///
+/// - every config exists, either as given or in `config_dir`
+/// - every config, and every config it includes, parses as YAML
+/// - every `${btvar:NAME}` reference is satisfied by `btvars`, or by a
+///   default declared under `buildex.btvars` by one of the configs
+fn preflight_configs(
+    config_dir: &Path,
+    configs: &[&Path],
+    btvars: &[String],
+) -> anyhow::Result<()> {
+    let mut problems = Vec::new();
+    let mut pending = Vec::new();
+    for config in configs {
+        if config.exists() {
+            pending.push(config.to_path_buf());
+        } else if config.is_relative() && config_dir.join(config).exists() {
+            pending.push(config_dir.join(config));
+        } else {
+            problems.push(format!(
+                "config {} not found (nor in {})",
+                config.display(),
+                config_dir.display()
+            ));
+        }
+    }
+
+    let mut visited = BTreeSet::new();
+    let mut defaults = BTreeSet::new();
+    let mut refs = Vec::new();
+    while let Some(config) = pending.pop() {
+        if !visited.insert(config.clone()) {
+            continue;
+        }
+        let parsed = match parse_config(&config) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                problems.push(format!("{:#}", e));
+                continue;
+            }
+        };
+        // Missing includes are reported by check_config_includes
+        for include in config_includes(&config)? {
+            let path = config_dir.join(include);
+            if path.exists() {
+                pending.push(path);
+            }
+        }
+        if let Some(declared) = parsed
+            .get("buildex")
+            .and_then(|buildex| buildex.get("btvars"))
+            .and_then(|btvars| btvars.as_mapping())
+        {
+            for (name, btvar) in declared {
+                if let (Some(name), Some(value)) = (name.as_str(), btvar.get("value"))
+                    && !value.is_null()
+                {
+                    defaults.insert(name.to_string());
+                }
+            }
+        }
+        collect_btvar_refs(&parsed, "", &config, &mut refs);
+    }
+
+    let set: BTreeSet<&str> = btvars
+        .iter()
+        .map(|bt| bt.split_once('=').map_or(bt.as_str(), |(name, _)| name))
+        .collect();
+    for r in refs {
+        if !set.contains(r.name.as_str()) && !defaults.contains(&r.name) {
+            problems.push(format!(
+                "`{}` in {} references ${{btvar:{}}}, which is not set (pass --btvar {}=...)",
+                r.key,
+                r.config.display(),
+                r.name,
+                r.name
+            ));
+        }
+    }
+
+    if !problems.is_empty() {
+        anyhow::bail!(
+            "{} problem(s) found in the shrinkwrap configs:\n  {}",
+            problems.len(),
+            problems.join("\n  ")
+        );
+    }
+    Ok(())
+}
+
+/// Watch the parent dirs of `files`, so editors that save by renaming a temp
+/// file over the original are still seen.
+fn watch_files(
+    files: &[PathBuf],
+) -> anyhow::Result<(
+    Debouncer<RecommendedWatcher>,
+    mpsc::Receiver<DebounceEventResult>,
+)> {
+    let (tx, rx) = mpsc::channel();
+    let mut debouncer =
+        new_debouncer(WATCH_DEBOUNCE, tx).context("failed to create file watcher")?;
+    let dirs: BTreeSet<&Path> = files.iter().filter_map(|file| file.parent()).collect();
+    for dir in dirs {
+        debouncer
+            .watcher()
+            .watch(dir, RecursiveMode::NonRecursive)
+            .with_context(|| format!("failed to watch {}", dir.display()))?;
+    }
+    Ok((debouncer, rx))
+}
+
+/// Block until one of `files` is modified. Returns false if `interrupted` is
+/// set (i.e: Ctrl-C) first.
+fn wait_for_change(
+    events: &mpsc::Receiver<DebounceEventResult>,
+    files: &[PathBuf],
+    interrupted: &AtomicBool,
+) -> anyhow::Result<bool> {
+    while !interrupted.load(Ordering::SeqCst) {
+        match events.recv_timeout(WATCH_INTERRUPT_INTERVAL) {
+            Ok(Ok(events)) => {
+                if events.iter().any(|event| files.contains(&event.path)) {
+                    return Ok(!interrupted.load(Ordering::SeqCst));
+                }
+            }
+            Ok(Err(e)) => log::warn!("file watcher error: {:#}", e),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                anyhow::bail!("file watcher stopped unexpectedly")
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Take the next build ID from `build_id_file`, if given, and record it in
+/// the output dir.
+fn start_build(
+    shrinkwrap: &ShrinkwrapCommand<'_>,
+    build_id_file: Option<&Path>,
+) -> anyhow::Result<Option<String>> {
+    let build_id = build_id_file.map(next_build_id).transpose()?;
+    if let Some(build_id) = &build_id {
+        fs_err::write(
+            shrinkwrap.out_dir.join(BUILD_ID_FILE),
+            serde_json::to_string_pretty(&serde_json::json!({ "build_id": build_id }))?,
+        )?;
+        log::info!("Build ID: {}", build_id);
+    }
+    Ok(build_id)
+}
+
+/// Record a successful build: its build diff (and optionally the artifact
+/// index) and the [`BuildManifest`] of its inputs.
+fn finish_build(
+    shrinkwrap: &ShrinkwrapCommand<'_>,
+    build_id: Option<&str>,
+    emit_artifact_index: bool,
+) -> anyhow::Result<()> {
+    let package_dir = shrinkwrap_package_dir(shrinkwrap.state_dir, shrinkwrap.platform_yaml)?;
+    if package_dir.exists() {
+        write_build_diff(
+            shrinkwrap.out_dir,
+            &package_dir,
+            &shrinkwrap.shrinkwrap_dir.join("venv"),
+            build_id,
+        )?;
+        if emit_artifact_index {
+            write_artifact_index(shrinkwrap.out_dir, &package_dir)?;
+        }
+    } else {
+        log::warn!(
+            "Package dir {} not found, skipping build diff",
+            package_dir.display()
+        );
+    }
+
+    BuildManifest::new(shrinkwrap)?.write(shrinkwrap.out_dir)
+}
+
+/// Run a full build (retrying a failed component if requested), and record
+/// it on success (see [`finish_build`]).
+fn build_once(
+    shrinkwrap: &ShrinkwrapCommand<'_>,
+    log_path: &Path,
+    retry_failed_components: bool,
+    build_id_file: Option<&Path>,
+    emit_artifact_index: bool,
+) -> anyhow::Result<()> {
+    let build_id = start_build(shrinkwrap, build_id_file)?;
+
+    let mut status = shrinkwrap.run(None, log_path, false)?;
+
+    if !status.success() && retry_failed_components {
+        if let Some(component) = failed_component(log_path)? {
+            log::warn!(
+                "Component '{}' failed, retrying it on its own...",
+                component
+            );
+            status = shrinkwrap.run(Some(&component), log_path, true)?;
+            if status.success() {
+                log::info!(
+                    "Retry of component '{}' succeeded, resuming full build...",
+                    component
+                );
+                status = shrinkwrap.run(None, log_path, true)?;
+            } else {
+                log::error!("Retry of component '{}' failed", component);
+            }
+        } else {
+            log::warn!(
+                "Could not identify a failed component in {}, not retrying",
+                log_path.display()
+            );
+        }
+    }
+
+    if !status.success() {
+        anyhow::bail!(
+            "shrinkwrap build {} (see {})",
+            describe_exit_status(status),
+            log_path.display()
+        );
+    }
+
+    finish_build(shrinkwrap, build_id.as_deref(), emit_artifact_index)
+}
+
 impl SimpleFlowNode for Node {
     type Request = Params;
 
     fn imports(_ctx: &mut ImportCtx<'_>) {}
 
     fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Result<()> {
+        request.validate()?;
+
         let Params {
             out_dir,
             shrinkwrap_dir,
             platform_yaml,
             overlays,
             btvars,
+            extra_args,
+            action,
+            retry_failed_components,
+            output_mode,
+            component_build_timeout_sec,
+            repro_dir,
+            watch,
+            check_includes,
+            pre_validate,
+            pipeline_timeout_sec,
+            build_id_file,
+            redis_cache,
+            shrinkwrap_state_dir,
+            force,
+            emit_artifact_index,
+            skip_preflight,
+            result_file,
+            dry_run,
             done,
         } = request;
 
-        ctx.emit_rust_step("run shrinkwrap build", |ctx| {
+        ctx.emit_rust_step(format!("run shrinkwrap {}", action.subcommand()), |ctx| {
             done.claim(ctx);
-            move |_rt| {
-                fs_err::create_dir_all(&out_dir)?;
-                let log_dir = out_dir.join("logs");
-                fs_err::create_dir_all(&log_dir)?;
-                let log_path = log_dir.join("shrinkwrap-build.log");
-
-                // Build command line - use shrinkwrap wrapper script with venv activated
-                let shrinkwrap_exe = shrinkwrap_dir.join("shrinkwrap").join("shrinkwrap");
-                let venv_dir = shrinkwrap_dir.join("venv");
-                let venv_bin = venv_dir.join("bin");
-
-                let mut cmd = std::process::Command::new(&shrinkwrap_exe);
-                cmd.current_dir(&out_dir); // keep build outputs contained
-
-                // Set environment to use venv Python
-                cmd.env("VIRTUAL_ENV", &venv_dir);
-                cmd.env("PATH", format!("{}:{}",
-                    venv_bin.display(),
-                    std::env::var("PATH").unwrap_or_default()
-                ));
-
-                cmd.arg("build");
-                cmd.arg(&platform_yaml);
-
-                for ov in &overlays {
-                    cmd.arg("--overlay").arg(ov);
+            let repro_dir = repro_dir.claim(ctx);
+            let dry_run = dry_run.claim(ctx);
+            move |rt| {
+                let dry_run = rt.read(dry_run);
+                let repro_dir = rt.read(repro_dir);
+                // The run node records its result here too, so don't leave a
+                // stale one behind from an earlier build
+                if let Some(result_file) = &result_file
+                    && !dry_run
+                    && result_file.exists()
+                {
+                    fs_err::remove_file(result_file)?;
                 }
 
-                for bt in &btvars {
-                    cmd.arg("--btvar").arg(bt);
-                }
+                let result = (|| -> anyhow::Result<()> {
+                    enter_pipeline_phase(pipeline_timeout_sec, PipelinePhase::Build)?;
 
-                // Stream output to both console and log file
-                log::info!("Running shrinkwrap build...");
-                log::info!("Output will be saved to: {}", log_path.display());
-
-                cmd.stdout(Stdio::piped());
-                cmd.stderr(Stdio::piped());
-
-                let mut child = cmd.spawn()?;
-
-                let stdout = child.stdout.take()
-                    .ok_or_else(|| anyhow::anyhow!("failed to capture stdout"))?;
-                let stderr = child.stderr.take()
-                    .ok_or_else(|| anyhow::anyhow!("failed to capture stderr"))?;
-
-                // Open log file
-                let log_file = Arc::new(Mutex::new(
-                    std::fs::OpenOptions::new()
-                        .create(true)
-                        .truncate(true)
-                        .write(true)
-                        .open(&log_path)?
-                ));
-
-                // Spawn threads to tee output to both console and log file
-                let log_file_clone = log_file.clone();
-                let stdout_thread = thread::spawn(move || {
-                    let reader = BufReader::new(stdout);
-                    for line in reader.lines() {
-                        if let Ok(line) = line {
-                            println!("{}", line);
-                            if let Ok(mut file) = log_file_clone.lock() {
-                                let _ = writeln!(file, "{}", line);
-                            }
+                    fs_err::create_dir_all(&out_dir)?;
+                    let log_dir = out_dir.join("logs");
+                    fs_err::create_dir_all(&log_dir)?;
+                    let log_path = log_dir.join(format!("shrinkwrap-{}.log", action.subcommand()));
+                    let mut timings = StageTimings::new("build", log_dir.clone());
+
+                    let shrinkwrap = ShrinkwrapCommand {
+                        action,
+                        out_dir: &out_dir,
+                        shrinkwrap_dir: &shrinkwrap_dir,
+                        platform_yaml: &platform_yaml,
+                        overlays: &overlays,
+                        btvars: &btvars,
+                        extra_args: &extra_args,
+                        output_mode,
+                        component_timeout: component_build_timeout_sec.map(Duration::from_secs),
+                        state_dir: shrinkwrap_state_dir.as_deref(),
+                    };
+
+                    if let Some(repro_dir) = repro_dir {
+                        let script = write_repro_script(
+                            &repro_dir,
+                            &format!("shrinkwrap-{}", action.subcommand()),
+                            &shrinkwrap.command(None),
+                        )?;
+                        log::info!("Wrote repro script to {}", script.display());
+                    }
+
+                    // Stream output to both console and log file
+                    log::info!("Running shrinkwrap {}...", action.subcommand());
+                    log::info!("Output will be saved to: {}", log_path.display());
+
+                    let configs: Vec<&Path> = std::iter::once(platform_yaml.as_path())
+                        .chain(overlays.iter().map(|ov| ov.as_path()))
+                        .collect();
+
+                    let config_dir = shrinkwrap_dir.join("config");
+                    if skip_preflight {
+                        log::info!("Skipping the preflight checks of the configs");
+                    } else if dry_run && !config_dir.is_dir() {
+                        log::info!("{} does not exist yet, skipping the preflight checks", config_dir.display());
+                    } else {
+                        let t0 = Instant::now();
+                        preflight_configs(&config_dir, &configs, &btvars)?;
+                        log::info!("Preflight checks of the configs passed");
+                        timings.record("preflight", t0);
+                    }
+
+                    if dry_run {
+                        // shrinkwrap itself may not be installed yet, so only
+                        // check what can be checked without it
+                        if (pre_validate || check_includes) && config_dir.is_dir() {
+                            check_config_includes(&config_dir, &configs)?;
+                            log::info!("All included configs are present");
                         }
+                        log_dry_run(&shrinkwrap.command(None));
+                        return Ok(());
                     }
-                });
 
-                let log_file_clone = log_file.clone();
-                let stderr_thread = thread::spawn(move || {
-                    let reader = BufReader::new(stderr);
-                    for line in reader.lines() {
-                        if let Ok(line) = line {
-                            eprintln!("{}", line);
-                            if let Ok(mut file) = log_file_clone.lock() {
-                                let _ = writeln!(file, "STDERR: {}", line);
+                    // Resume an interrupted pipeline without rebuilding, as long
+                    // as the packaged build is still there
+                    if action == ShrinkwrapAction::Build && !watch {
+                        let package_dir = shrinkwrap_package_dir(shrinkwrap_state_dir.as_deref(), &platform_yaml)?;
+                        if force {
+                            log::info!("Forcing a rebuild, ignoring {}", BuildManifest::path(&out_dir).display());
+                        } else if package_dir.exists() && BuildManifest::is_current(&shrinkwrap)? {
+                            log::info!(
+                                "Inputs unchanged since the last successful build (see {}), skipping shrinkwrap build",
+                                BuildManifest::path(&out_dir).display()
+                            );
+                            return Ok(());
+                        }
+                    }
+
+                    let t0 = Instant::now();
+                    if pre_validate {
+                        if shrinkwrap.has_validate()? {
+                            log::info!("Validating configs with shrinkwrap validate...");
+                            shrinkwrap.validate().context("config validation failed, skipping build")?;
+                        } else {
+                            // Fall back to checking that every config parses and
+                            // its includes exist
+                            log::info!("shrinkwrap validate is not available, checking configs directly...");
+                            check_config_includes(&config_dir, &configs)
+                                .context("config validation failed, skipping build")?;
+                        }
+                        log::info!("Configs validated successfully");
+                        timings.record("validate configs", t0);
+                    } else if check_includes {
+                        check_config_includes(&config_dir, &configs)?;
+                        log::info!("All included configs are present");
+                        timings.record("check includes", t0);
+                    }
+
+                    let stage = format!("shrinkwrap {}", action.subcommand());
+                    if action != ShrinkwrapAction::Build {
+                        let t0 = Instant::now();
+                        let status = shrinkwrap.run(None, &log_path, false)?;
+                        timings.record(&stage, t0);
+                        if !status.success() {
+                            anyhow::bail!(
+                                "shrinkwrap {} {} (see {})",
+                                action.subcommand(),
+                                describe_exit_status(status),
+                                log_path.display()
+                            );
+                        }
+                        return Ok(());
+                    }
+
+                    if !watch {
+                        let Some(cache) = &redis_cache else {
+                            let t0 = Instant::now();
+                            let result = build_once(
+                                &shrinkwrap,
+                                &log_path,
+                                retry_failed_components,
+                                build_id_file.as_deref(),
+                                emit_artifact_index,
+                            );
+                            timings.record(&stage, t0);
+                            return result;
+                        };
+
+                        // Cache errors aren't fatal, they just mean building locally
+                        let package_dir = shrinkwrap_package_dir(shrinkwrap_state_dir.as_deref(), &platform_yaml)?;
+                        let key = format!("{}:{}", cache.key_prefix, build_fingerprint(&shrinkwrap)?);
+                        let t0 = Instant::now();
+                        let fetched = fetch_cached_build(cache, &key, &package_dir, &out_dir);
+                        timings.record("redis cache fetch", t0);
+                        match fetched {
+                            Ok(true) => {
+                                log::info!("Redis cache hit for {}, skipping build", key);
+                                // Later steps read the same metadata as after a
+                                // local build
+                                let build_id = start_build(&shrinkwrap, build_id_file.as_deref())?;
+                                return finish_build(&shrinkwrap, build_id.as_deref(), emit_artifact_index);
                             }
+                            Ok(false) => log::info!("Redis cache miss for {}, building...", key),
+                            Err(e) => log::warn!("Failed to fetch build from redis cache: {:#}", e),
                         }
+
+                        let t0 = Instant::now();
+                        let result = build_once(
+                            &shrinkwrap,
+                            &log_path,
+                            retry_failed_components,
+                            build_id_file.as_deref(),
+                            emit_artifact_index,
+                        );
+                        timings.record(&stage, t0);
+                        result?;
+
+                        let t0 = Instant::now();
+                        let stored = store_cached_build(cache, &key, &package_dir, &out_dir);
+                        timings.record("redis cache upload", t0);
+                        match stored {
+                            Ok(()) => log::info!("Uploaded build to redis cache as {}", key),
+                            Err(e) => log::warn!("Failed to upload build to redis cache: {:#}", e),
+                        }
+                        return Ok(());
                     }
-                });
 
-                // Wait for threads to finish
-                let _ = stdout_thread.join();
-                let _ = stderr_thread.join();
+                    let interrupted = Arc::new(AtomicBool::new(false));
+                    ctrlc::set_handler({
+                        let interrupted = interrupted.clone();
+                        move || interrupted.store(true, Ordering::SeqCst)
+                    })
+                    .context("failed to install Ctrl-C handler for watch mode")?;
 
-                // Wait for child process
-                let status = child.wait()?;
+                    // Absolute, so they compare equal to the paths in file events
+                    let watched = std::iter::once(&platform_yaml)
+                        .chain(overlays.iter())
+                        .map(std::path::absolute)
+                        .collect::<Result<Vec<_>, _>>()
+                        .context("failed to resolve watched files")?;
+                    let (_debouncer, events) = watch_files(&watched)?;
+                    let watched_list = watched
+                        .iter()
+                        .map(|file| file.display().to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ");
 
-                if !status.success() {
-                    anyhow::bail!(
-                        "shrinkwrap build failed (see {})",
-                        log_path.display()
-                    );
-                }
+                    loop {
+                        // Keep watching after a failed build, so it can be fixed
+                        let t0 = Instant::now();
+                        let result = build_once(
+                            &shrinkwrap,
+                            &log_path,
+                            retry_failed_components,
+                            build_id_file.as_deref(),
+                            emit_artifact_index,
+                        );
+                        timings.record(&stage, t0);
+                        if let Err(e) = result {
+                            log::error!("{:#}", e);
+                        }
+                        if interrupted.load(Ordering::SeqCst) {
+                            break;
+                        }
 
-                Ok(())
+                        log::info!("Watching for changes to {}...", watched_list);
+                        if !wait_for_change(&events, &watched, &interrupted)? {
+                            break;
+                        }
+                        log::info!("Change detected, rebuilding...");
+                    }
+
+                    log::info!("Exiting watch mode");
+                    Ok(())
+                })();
+
+                // Report the failure in the result file instead of failing the
+                // job, so the rest of the matrix still builds and runs
+                if let (Some(result_file), Err(e)) = (&result_file, &result) {
+                    log::error!("Shrinkwrap {} failed: {:#}", action.subcommand(), e);
+                    write_run_result(result_file, &result, Duration::ZERO, None)?;
+                    return Ok(());
+                }
+                result
             }
         });
 
@@ -0,0 +1,87 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Consolidated report of the runs of a CCA FVP configuration matrix.
+
+use flowey::node::prelude::*;
+
+/// Report file written to the matrix working dir.
+const MATRIX_REPORT: &str = "matrix-report.json";
+
+flowey_request! {
+    pub struct Params {
+        /// Matrix working dir, which the report is written to
+        pub matrix_dir: PathBuf,
+        /// Each configuration's name, and the result file its run node wrote
+        pub results: Vec<(String, PathBuf)>,
+        pub done: WriteVar<SideEffect>,
+    }
+}
+
+new_simple_flow_node!(struct Node);
+
+impl SimpleFlowNode for Node {
+    type Request = Params;
+
+    fn imports(_ctx: &mut ImportCtx<'_>) {}
+
+    fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Result<()> {
+        let Params {
+            matrix_dir,
+            results,
+            done,
+        } = request;
+
+        ctx.emit_rust_step("report matrix results", |ctx| {
+            done.claim(ctx);
+            move |_rt| {
+                let mut report = serde_json::Map::new();
+                let mut failed = Vec::new();
+
+                println!("CCA FVP matrix results:");
+                for (name, result_file) in &results {
+                    let result = if result_file.exists() {
+                        serde_json::from_str(&fs_err::read_to_string(result_file)?)?
+                    } else {
+                        serde_json::json!({
+                            "passed": false,
+                            "error": format!("no result recorded at {}", result_file.display()),
+                        })
+                    };
+
+                    let passed = result["passed"].as_bool().unwrap_or(false);
+                    let duration = result["duration_secs"]
+                        .as_f64()
+                        .map_or("-".to_string(), |secs| format!("{:.0}s", secs));
+                    println!(
+                        "  {:<24} {:<6} {:>8}  {}",
+                        name,
+                        if passed { "PASS" } else { "FAIL" },
+                        duration,
+                        result["error"].as_str().unwrap_or_default()
+                    );
+                    if !passed {
+                        failed.push(name.as_str());
+                    }
+                    report.insert(name.clone(), result);
+                }
+
+                let report_path = matrix_dir.join(MATRIX_REPORT);
+                fs_err::write(&report_path, serde_json::to_string_pretty(&report)?)?;
+                log::info!("Wrote matrix report to {}", report_path.display());
+
+                if !failed.is_empty() {
+                    anyhow::bail!(
+                        "{} of {} matrix configurations failed: {}",
+                        failed.len(),
+                        results.len(),
+                        failed.join(", ")
+                    );
+                }
+                Ok(())
+            }
+        });
+
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Archive a shrinkwrap build into a self-contained tarball, which can be
+//! copied to a lab machine and run there without re-running shrinkwrap.
+
+use crate::_jobs::shrinkwrap_util::describe_exit_status;
+use crate::_jobs::shrinkwrap_util::log_dry_run;
+use crate::_jobs::shrinkwrap_util::set_shrinkwrap_state_dir;
+use crate::_jobs::shrinkwrap_util::shrinkwrap_package_dir;
+use crate::_jobs::shrinkwrap_util::tee_command_output;
+use flowey::node::prelude::*;
+use std::process::Command;
+
+flowey_request! {
+    pub struct Params {
+        /// Output directory of the shrinkwrap build
+        pub out_dir: PathBuf,
+        /// Directory where shrinkwrap repo is cloned
+        pub shrinkwrap_dir: PathBuf,
+        /// Platform YAML the build was run with
+        pub platform_yaml: PathBuf,
+        /// Where to write the archive
+        pub output_archive: PathBuf,
+        /// Shrinkwrap build and package dirs the build used, if not the
+        /// user-wide ones under `~/.shrinkwrap`
+        pub shrinkwrap_state_dir: Option<PathBuf>,
+        /// Overwrite `output_archive` if it already exists
+        pub force: bool,
+        /// Only log the packaging command
+        pub dry_run: ReadVar<bool>,
+        pub done: WriteVar<SideEffect>,
+    }
+}
+
+new_simple_flow_node!(struct Node);
+
+impl SimpleFlowNode for Node {
+    type Request = Params;
+
+    fn imports(_ctx: &mut ImportCtx<'_>) {}
+
+    fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Result<()> {
+        let Params {
+            out_dir,
+            shrinkwrap_dir,
+            platform_yaml,
+            output_archive,
+            shrinkwrap_state_dir,
+            force,
+            dry_run,
+            done,
+        } = request;
+
+        ctx.emit_rust_step("run shrinkwrap package", |ctx| {
+            done.claim(ctx);
+            let dry_run = dry_run.claim(ctx);
+            move |rt| {
+                let dry_run = rt.read(dry_run);
+                if output_archive.exists() && !force {
+                    log::info!(
+                        "{} already exists, not packaging the build again (pass --force-package to overwrite it)",
+                        output_archive.display()
+                    );
+                    return Ok(());
+                }
+
+                // Same venv setup as the build step
+                let shrinkwrap_exe = shrinkwrap_dir.join("shrinkwrap").join("shrinkwrap");
+                let venv_dir = shrinkwrap_dir.join("venv");
+                let mut cmd = Command::new(&shrinkwrap_exe);
+                cmd.arg("package")
+                    .arg(&platform_yaml)
+                    .arg("--output")
+                    .arg(&output_archive)
+                    .current_dir(&out_dir)
+                    .env("VIRTUAL_ENV", &venv_dir)
+                    .env(
+                        "PATH",
+                        format!(
+                            "{}:{}",
+                            venv_dir.join("bin").display(),
+                            std::env::var("PATH").unwrap_or_default()
+                        ),
+                    );
+                if let Some(state_dir) = &shrinkwrap_state_dir {
+                    set_shrinkwrap_state_dir(&mut cmd, state_dir);
+                }
+
+                if dry_run {
+                    log_dry_run(&cmd);
+                    return Ok(());
+                }
+
+                let package_dir = shrinkwrap_package_dir(shrinkwrap_state_dir.as_deref(), &platform_yaml)?;
+                if !package_dir.exists() {
+                    anyhow::bail!(
+                        "no shrinkwrap build of {} to package at {}",
+                        platform_yaml.display(),
+                        package_dir.display()
+                    );
+                }
+
+                if let Some(parent) = output_archive.parent() {
+                    fs_err::create_dir_all(parent)?;
+                }
+                let log_dir = out_dir.join("logs");
+                fs_err::create_dir_all(&log_dir)?;
+                let log_path = log_dir.join("shrinkwrap-package.log");
+                fs_err::File::create(&log_path)?;
+
+                log::info!("Packaging {} into {}...", platform_yaml.display(), output_archive.display());
+                let status = tee_command_output(cmd, &log_path)?;
+                if !status.success() {
+                    anyhow::bail!("shrinkwrap package {} (see {})", describe_exit_status(status), log_path.display());
+                }
+                if !output_archive.exists() {
+                    anyhow::bail!(
+                        "shrinkwrap package appeared to succeed but {} was not created",
+                        output_archive.display()
+                    );
+                }
+
+                log::info!(
+                    "Packaged {} ({} MiB) into {}",
+                    package_dir.display(),
+                    fs_err::metadata(&output_archive)?.len() / (1024 * 1024),
+                    output_archive.display()
+                );
+                Ok(())
+            }
+        });
+
+        Ok(())
+    }
+}
@@ -1,10 +1,152 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
+use crate::_jobs::shrinkwrap_util::HOST_KERNEL_DIR;
+use crate::_jobs::shrinkwrap_util::KERNEL_ARCH;
+use crate::_jobs::shrinkwrap_util::KERNEL_IMAGE_TARGET;
+use crate::_jobs::shrinkwrap_util::PipelinePhase;
+use crate::_jobs::shrinkwrap_util::StageTimings;
+use crate::_jobs::shrinkwrap_util::TMK_DIR;
+use crate::_jobs::shrinkwrap_util::TmkVmmTarget;
+use crate::_jobs::shrinkwrap_util::ToolchainManifest;
+use crate::_jobs::shrinkwrap_util::assert_aarch64_elf;
+use crate::_jobs::shrinkwrap_util::check_dir_writable;
+use crate::_jobs::shrinkwrap_util::command_line;
+use crate::_jobs::shrinkwrap_util::describe_exit_status;
+use crate::_jobs::shrinkwrap_util::download_file;
+use crate::_jobs::shrinkwrap_util::enter_pipeline_phase;
+use crate::_jobs::shrinkwrap_util::forward_lines;
+use crate::_jobs::shrinkwrap_util::is_aarch64_elf;
+use crate::_jobs::shrinkwrap_util::is_arm64_kernel_image;
+use crate::_jobs::shrinkwrap_util::is_sha256_digest;
+use crate::_jobs::shrinkwrap_util::is_tfa_version;
+use crate::_jobs::shrinkwrap_util::kernel_image_path;
+use crate::_jobs::shrinkwrap_util::kill_process_group;
+use crate::_jobs::shrinkwrap_util::log_dry_run;
+use crate::_jobs::shrinkwrap_util::read_build_id;
+use crate::_jobs::shrinkwrap_util::report_invalid_params;
+use crate::_jobs::shrinkwrap_util::set_shrinkwrap_state_dir;
+use crate::_jobs::shrinkwrap_util::sha256_file;
+use crate::_jobs::shrinkwrap_util::shrinkwrap_package_dir;
+use crate::_jobs::shrinkwrap_util::simple_tmk_path;
+use crate::_jobs::shrinkwrap_util::spawn_in_process_group;
+use crate::_jobs::shrinkwrap_util::tee_command_output;
+use crate::_jobs::shrinkwrap_util::tfa_image_version;
+use crate::_jobs::shrinkwrap_util::tfa_version_matches;
+use crate::_jobs::shrinkwrap_util::tmk_binary_selected;
+use crate::_jobs::shrinkwrap_util::tmk_vmm_path;
+use crate::_jobs::shrinkwrap_util::validate_tmk_binaries;
+use crate::_jobs::shrinkwrap_util::wait_with_timeout;
+use crate::_jobs::shrinkwrap_util::write_line;
+use crate::_jobs::shrinkwrap_util::write_repro_script;
+use crate::_jobs::shrinkwrap_util::write_run_result;
 use flowey::node::prelude::*;
+use sha2::Digest;
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
 use std::fs;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
+use std::process::Stdio;
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Console line printed by the buildroot guest once it has finished booting.
+const BOOT_MARKER: &str = "Welcome to Buildroot";
+/// How long a smoke test waits for the guest to reach [`BOOT_MARKER`].
+const SMOKE_TEST_TIMEOUT: Duration = Duration::from_secs(600);
+/// Diagnostics bundle written to the out dir when the guest fails to boot.
+const BOOT_FAILURE_DIAGNOSTICS: &str = "boot-failure-diagnostics.txt";
+/// How many trailing lines of each log go into the diagnostics bundle.
+const DIAGNOSTICS_LOG_LINES: usize = 50;
+/// Largest Realm VCPU count accepted for `REALM_VCPUS`.
+const MAX_REALM_VCPUS: u32 = 16;
+/// Largest guest core count accepted for `GUEST_CORES` (FVP_Base_RevC has
+/// two clusters of up to 4 cores each).
+const MAX_GUEST_CORES: u32 = 8;
+
+/// How the saved copy of the modified rootfs is compressed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RootfsCompression {
+    /// Save it as-is
+    #[default]
+    None,
+    /// Compress with `gzip`, appending `.gz` to the saved path
+    Gzip,
+    /// Compress with `zstd`, appending `.zst` to the saved path
+    Zstd,
+}
+
+impl RootfsCompression {
+    /// Compressor command and extension, if compressed.
+    fn compressor(self) -> Option<(&'static str, &'static str)> {
+        match self {
+            RootfsCompression::None => None,
+            RootfsCompression::Gzip => Some(("gzip", "gz")),
+            RootfsCompression::Zstd => Some(("zstd", "zst")),
+        }
+    }
+}
+
+/// Build the RMM from source instead of using the one built by shrinkwrap.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RmmSourceConfig {
+    /// RMM git repo to clone
+    pub repo_url: String,
+    /// Branch of the RMM repo to build
+    pub branch: String,
+    /// Build script to run, relative to the RMM repo root
+    pub build_script: PathBuf,
+    /// Binary produced by the build script, relative to the RMM repo root
+    pub output_binary: PathBuf,
+}
+
+/// VMBus settings for the OHCL kernel's Hyper-V support (see the
+/// `HYPERV_CONFIGS` enabled by the install node).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VmbusConfig {
+    /// Injected as `VMBUS_ENABLED`
+    pub enable_vmbus: bool,
+    /// Injected as `VMBUS_CHANNELS`
+    pub vmbus_channels: u32,
+    /// Run MSHV in VTL mode, injected as `MSHV_VTL`. Requires VMBus.
+    pub mshv_vtl: bool,
+}
+
+/// A host directory shared with the guest over virtio-9p (see the
+/// `NINEP_CONFIGS` enabled by the install node).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NinePShare {
+    /// Host directory to share, injected as `NINEP_<tag>_HOST_PATH`
+    pub host_path: PathBuf,
+    /// virtio-9p mount tag identifying the share
+    pub mount_tag: String,
+    /// Where the share is mounted in the guest (via `/etc/fstab`)
+    pub mount_point: PathBuf,
+    /// Mount the share read-only
+    pub readonly: bool,
+}
+
+/// A base rootfs to download, rather than using one already on disk.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RootfsDownload {
+    /// http(s) URL of the rootfs image
+    pub url: String,
+    /// Expected sha256 of the downloaded image
+    pub sha256: Option<String>,
+}
 
 flowey_request! {
     /// Parameters for modifying rootfs.ext2 and running shrinkwrap.
@@ -17,280 +159,2353 @@ pub struct Params {
         pub platform_yaml: PathBuf,
         /// Path to rootfs.ext2 file
         pub rootfs_path: PathBuf,
+        /// Download the base rootfs into `<out_dir>/downloads` and copy it to
+        /// `rootfs_path` before injecting into it
+        pub rootfs_download: Option<RootfsDownload>,
+        /// Named rootfs images (e.g. a minimal and a full one) to inject and
+        /// run with in turn, instead of `rootfs_path`. Every variant is run
+        /// even if an earlier one fails.
+        pub rootfs_variants: Vec<(String, PathBuf)>,
+        /// Cargo workspace the TMK binaries were built in, if not the
+        /// OpenVMM-TMK clone
+        pub tmk_cargo_workspace: Option<PathBuf>,
+        /// TMK binaries to inject (`simple_tmk`, `tmk_vmm`). Injects all of
+        /// them when empty.
+        pub tmk_binaries: Vec<String>,
+        /// Rust target `tmk_vmm` was built for
+        pub tmk_vmm_target: TmkVmmTarget,
+        /// Dir the TMK binaries and kernel are injected into, relative to the
+        /// rootfs root (e.g. `cca`)
+        pub inject_dir: String,
+        /// Extra host files to inject into `inject_dir`, as (source, file
+        /// name in the rootfs)
+        pub extra_injections: Vec<(PathBuf, String)>,
         /// Runtime variables for shrinkwrap run (e.g., "ROOTFS=/path/to/rootfs.ext2")
         pub rtvars: Vec<String>,
+        /// Extra arguments appended, in order, after everything else passed
+        /// to `shrinkwrap run`, so they can override earlier ones
+        pub extra_args: Vec<String>,
+        /// Only boot the guest until it reaches the boot marker, then tear
+        /// down. Skips the TMK injection into rootfs.ext2.
+        pub smoke_test: bool,
+        /// Write into rootfs.ext2 with `debugfs` (from e2fsprogs) instead of
+        /// loop-mounting it with `sudo`, for hosts without sudo
+        pub use_debugfs: bool,
+        /// Container runtime to run e2fsck/resize2fs in, instead of the first
+        /// of `docker` and `podman` found on `PATH`
+        pub container_runtime: Option<String>,
+        /// Kill `shrinkwrap run` (and the FVP) and fail if it's still running
+        /// after this long
+        pub timeout: Option<Duration>,
+        /// Number of Realm VCPUs, injected as `REALM_VCPUS=<n>` (1..=16)
+        pub realm_vcpu_count: Option<u32>,
+        /// Host CPUs to pin the Realm VCPUs to, injected as
+        /// `REALM_VCPU_AFFINITY=<comma-separated>`
+        pub realm_vcpu_affinity: Option<Vec<u32>>,
+        /// Number of FVP guest cores, injected as `GUEST_CORES=<n>` (1..=8).
+        /// The platform's default is used when unset.
+        pub guest_cores: Option<u32>,
+        /// Extra `(ip_address, hostname)` entries for the guest's
+        /// `/etc/hosts`. Replaces any existing entries for the same hostname.
+        pub etc_hosts_entries: Vec<(String, String)>,
+        /// virtio-9p shares to mount in the guest
+        pub ninep_shares: Vec<NinePShare>,
+        /// Write a script reproducing the resolved `shrinkwrap run`
+        /// invocation into this dir (e.g. a published artifact)
+        pub repro_dir: Option<ReadVar<PathBuf>>,
+        /// Pipeline-wide timeout in seconds, counted from when the job starts
+        pub pipeline_timeout_sec: Option<u64>,
+        /// Git credential helper used when cloning the RMM repo
+        pub git_credential_helper: Option<String>,
+        /// How many times a failed clone or fetch of the RMM repo is retried
+        pub max_clone_retries: u32,
+        /// Verify the GPG signature of the RMM tag, if it's built from one
+        pub verify_signatures: bool,
+        /// GnuPG home dir holding the keyring to verify signatures with
+        pub gpg_keyring: Option<PathBuf>,
+        /// Force KASLR on or off in the OHCL kernel, via the
+        /// `OHCL_CMDLINE_EXTRA` rtvar. Disabling KASLR weakens the guest's
+        /// security properties, so it must not be used for production CCA
+        /// attestation tests.
+        pub kaslr_enabled: Option<bool>,
+        /// Extra OHCL kernel command line arguments, appended to the
+        /// `OHCL_CMDLINE_EXTRA` rtvar
+        pub kernel_cmdline: Option<String>,
+        /// Initial virtio-balloon size in MiB, injected as `BALLOON_INITIAL`
+        pub balloon_initial_mb: Option<u32>,
+        /// Maximum virtio-balloon size in MiB, injected as `BALLOON_MAX`
+        pub balloon_max_mb: Option<u32>,
+        /// VMBus settings, injected as rtvars
+        pub vmbus_config: Option<VmbusConfig>,
+        /// Build the RMM from source and pass it to shrinkwrap as the `RMM`
+        /// rtvar
+        pub rmm_source: Option<RmmSourceConfig>,
+        /// Fail before launching shrinkwrap unless the packaged TF-A
+        /// `bl1.bin` is this version (e.g. `2.10` matches any `2.10.x`)
+        pub required_tfa_version: Option<String>,
+        /// Save a copy of the modified rootfs here (e.g. to publish it)
+        pub rootfs_out: Option<PathBuf>,
+        /// How to compress the copy saved to `rootfs_out`
+        pub rootfs_compression: RootfsCompression,
+        /// Run shrinkwrap this many times (restoring the injected rootfs
+        /// between runs) and report how often each TMK test failed
+        pub repeat: u32,
+        /// Fail, rather than warn, if the host kernel and TMK binaries look
+        /// like they were built with incompatible toolchains
+        pub strict_toolchain_check: bool,
+        /// Instead of running the TMK suite, time this many boots of the
+        /// guest to [`BOOT_MARKER`] and report min/median/max
+        pub bench_boot: Option<u32>,
+        /// File to write the boot-time benchmark results to, as JSON
+        pub metrics_file: Option<PathBuf>,
+        /// Use shrinkwrap's build and package dirs under this dir, instead
+        /// of the user-wide ones under `~/.shrinkwrap`
+        pub shrinkwrap_state_dir: Option<PathBuf>,
+        /// Record whether the run passed in this file (as JSON), rather than
+        /// failing the job if it didn't, so several runs can be reported on
+        /// together
+        pub result_file: Option<PathBuf>,
+        /// Only log the `shrinkwrap run` command, leaving the rootfs untouched
+        pub dry_run: ReadVar<bool>,
         pub done: WriteVar<SideEffect>,
     }
 }
 
 new_simple_flow_node!(struct Node);
 
+impl Params {
+    /// Check the parameters up front, reporting every problem at once rather
+    /// than failing on the first one.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut problems = Vec::new();
+
+        check_dir_writable(&self.out_dir, &mut problems);
+        if let Some(toolchain_dir) = self.shrinkwrap_dir.parent() {
+            check_dir_writable(toolchain_dir, &mut problems);
+        } else {
+            problems.push(format!(
+                "shrinkwrap dir {} has no parent",
+                self.shrinkwrap_dir.display()
+            ));
+        }
+
+        for (ip, hostname) in &self.etc_hosts_entries {
+            if IpAddr::from_str(ip).is_err() {
+                problems.push(format!(
+                    "invalid IP address '{}' for guest host '{}'",
+                    ip, hostname
+                ));
+            }
+            if hostname.is_empty()
+                || !hostname
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
+            {
+                problems.push(format!("invalid guest hostname '{}'", hostname));
+            }
+        }
+
+        for share in &self.ninep_shares {
+            if share.mount_tag.is_empty()
+                || !share
+                    .mount_tag
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_')
+            {
+                problems.push(format!("invalid 9p mount tag '{}'", share.mount_tag));
+            }
+            if !share.mount_point.is_absolute() {
+                problems.push(format!(
+                    "9p share '{}' guest mount point must be absolute, got {}",
+                    share.mount_tag,
+                    share.mount_point.display()
+                ));
+            }
+            if !share.host_path.is_dir() {
+                problems.push(format!(
+                    "9p share '{}' host path {} is not a directory",
+                    share.mount_tag,
+                    share.host_path.display()
+                ));
+            }
+        }
+
+        if let Err(e) = validate_tmk_binaries(&self.tmk_binaries) {
+            problems.push(e.to_string());
+        }
+        if let Some(workspace) = &self.tmk_cargo_workspace
+            && !workspace.is_dir()
+        {
+            problems.push(format!(
+                "TMK cargo workspace {} is not a directory",
+                workspace.display()
+            ));
+        }
+
+        // The injection happens as root, so don't let it escape the rootfs
+        if self.inject_dir.is_empty()
+            || self.inject_dir.starts_with('/')
+            || Path::new(&self.inject_dir)
+                .components()
+                .any(|c| c == std::path::Component::ParentDir)
+        {
+            problems.push(format!(
+                "invalid inject dir '{}', expected a path relative to the rootfs root",
+                self.inject_dir
+            ));
+        }
+        for (_, name) in &self.extra_injections {
+            if name.is_empty()
+                || name.contains('/')
+                || name == "."
+                || name == ".."
+                || name == INJECTION_SENTINEL
+            {
+                problems.push(format!(
+                    "invalid extra injection destination '{}', expected a file name",
+                    name
+                ));
+            }
+        }
+
+        for (i, (name, path)) in self.rootfs_variants.iter().enumerate() {
+            // Names end up in log file names
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                problems.push(format!("invalid rootfs variant name '{}'", name));
+            }
+            if self.rootfs_variants[..i]
+                .iter()
+                .any(|(other, _)| other == name)
+            {
+                problems.push(format!("duplicate rootfs variant '{}'", name));
+            }
+            if !path.is_file() {
+                problems.push(format!(
+                    "rootfs variant '{}' image {} not found",
+                    name,
+                    path.display()
+                ));
+            }
+        }
+        if !self.rootfs_variants.is_empty() {
+            if self.rootfs_download.is_some() {
+                problems
+                    .push("a rootfs download can't be combined with rootfs variants".to_string());
+            }
+            if self.rootfs_out.is_some() || self.metrics_file.is_some() {
+                problems.push(
+                    "saving the rootfs or boot metrics isn't supported with rootfs variants"
+                        .to_string(),
+                );
+            }
+        }
+
+        if let Some(download) = &self.rootfs_download {
+            if !download.url.starts_with("http://") && !download.url.starts_with("https://") {
+                problems.push(format!(
+                    "rootfs URL must be http(s), got '{}'",
+                    download.url
+                ));
+            }
+            if let Some(sha256) = &download.sha256
+                && !is_sha256_digest(sha256)
+            {
+                problems.push(format!("invalid rootfs sha256 '{}'", sha256));
+            }
+        }
+
+        if self
+            .kernel_cmdline
+            .as_ref()
+            .is_some_and(|cmdline| cmdline.trim().is_empty())
+        {
+            problems.push("kernel command line must not be empty".to_string());
+        }
+
+        if self.repeat == 0 {
+            problems.push("repeat count must be at least 1".to_string());
+        }
+        if self.repeat > 1 && self.smoke_test {
+            problems.push("repeated runs are not supported in smoke test mode".to_string());
+        }
+        if let Some(iterations) = self.bench_boot {
+            if iterations == 0 {
+                problems.push("boot benchmark needs at least 1 iteration".to_string());
+            }
+            if self.smoke_test || self.repeat > 1 {
+                problems.push(
+                    "boot benchmark can't be combined with smoke test or repeated runs".to_string(),
+                );
+            }
+        } else if self.metrics_file.is_some() {
+            problems.push("a metrics file is only written by the boot benchmark".to_string());
+        }
+
+        for (name, mb) in [
+            ("initial", self.balloon_initial_mb),
+            ("max", self.balloon_max_mb),
+        ] {
+            if let Some(mb) = mb
+                && mb % 4 != 0
+            {
+                problems.push(format!(
+                    "balloon {} size must be a multiple of 4 MiB, got {}",
+                    name, mb
+                ));
+            }
+        }
+        if let (Some(initial), Some(max)) = (self.balloon_initial_mb, self.balloon_max_mb)
+            && initial > max
+        {
+            problems.push(format!(
+                "balloon initial size ({} MiB) exceeds its max size ({} MiB)",
+                initial, max
+            ));
+        }
+        if let Some(vmbus) = &self.vmbus_config {
+            if vmbus.mshv_vtl && !vmbus.enable_vmbus {
+                problems.push("MSHV VTL mode requires VMBus to be enabled".to_string());
+            }
+            if vmbus.enable_vmbus && vmbus.vmbus_channels == 0 {
+                problems.push("VMBus needs at least one channel".to_string());
+            }
+        }
+
+        if let Some(n) = self.guest_cores
+            && !(1..=MAX_GUEST_CORES).contains(&n)
+        {
+            problems.push(format!(
+                "guest core count must be between 1 and {}, got {}",
+                MAX_GUEST_CORES, n
+            ));
+        }
+        if let Some(n) = self.realm_vcpu_count
+            && !(1..=MAX_REALM_VCPUS).contains(&n)
+        {
+            problems.push(format!(
+                "realm VCPU count must be between 1 and {}, got {}",
+                MAX_REALM_VCPUS, n
+            ));
+        }
+        if let Some(affinity) = &self.realm_vcpu_affinity {
+            if affinity.is_empty() {
+                problems.push("realm VCPU affinity must list at least one host CPU".to_string());
+            }
+            if let Some(n) = self.realm_vcpu_count
+                && affinity.len() != n as usize
+            {
+                problems.push(format!(
+                    "realm VCPU affinity lists {} host CPUs, but {} realm VCPUs were requested",
+                    affinity.len(),
+                    n
+                ));
+            }
+        }
+
+        if let Some(version) = &self.required_tfa_version
+            && !is_tfa_version(version)
+        {
+            problems.push(format!(
+                "invalid required TF-A version '{}', expected e.g. 2.10",
+                version
+            ));
+        }
+
+        report_invalid_params("run", problems)
+    }
+}
+
+/// Records the sha256 of every file injected into the rootfs (one
+/// `<sha256>  <name>` line per file), in the inject dir.
+const INJECTION_SENTINEL: &str = ".injected.sha256";
+/// Sentinel entry covering the `/etc/hosts` and `/etc/fstab` updates.
+const ETC_UPDATE_SENTINEL_ENTRY: &str = "etc-update";
+/// Size rootfs.ext2 is grown to if its current size can't be read.
+const FALLBACK_ROOTFS_SIZE_MB: u64 = 1024;
+/// Free space kept in rootfs.ext2 on top of its contents, in percent.
+const ROOTFS_HEADROOM_PERCENT: u64 = 20;
+
+/// Container runtimes to look for on `PATH`, in order of preference. podman
+/// accepts the same commands as docker for everything run here.
+const CONTAINER_RUNTIMES: [&str; 2] = ["docker", "podman"];
+/// Image with e2fsprogs preinstalled that e2fsck and resize2fs are run in,
+/// built from [`E2FSPROGS_DOCKERFILE`] on first use and cached by the
+/// container runtime. The base image is fully qualified, since podman won't
+/// resolve short names without a configured registry.
+const E2FSPROGS_IMAGE: &str = "cca-fvp-e2fsprogs:24.04";
+const E2FSPROGS_DOCKERFILE: &str = "FROM docker.io/library/ubuntu:24.04\nRUN apt-get update && apt-get install -y e2fsprogs && rm -rf /var/lib/apt/lists/*\n";
+/// Checks the filesystem `$1` then, if `$2` is set, grows it to `$2`. Prints
+/// the exit status of each tool on stdout (as `<tool>=<status>`), so they
+/// can be reported separately.
+const E2FSCK_RESIZE_SCRIPT: &str = r#"e2fsck -fp "$1" >&2; echo "e2fsck=$?"; if [ -n "$2" ]; then resize2fs "$1" "$2" >&2; echo "resize2fs=$?"; fi"#;
+
+/// Size of an ext2 filesystem, from its superblock.
+struct Ext2Size {
+    block_count: u64,
+    free_blocks: u64,
+    block_size: u64,
+}
+
+impl Ext2Size {
+    /// Read the size of the ext2/3/4 filesystem in `image` from its
+    /// superblock, or `None` if it doesn't have one.
+    fn read_superblock(image: &Path) -> anyhow::Result<Option<Self>> {
+        const SUPERBLOCK_OFFSET: u64 = 1024;
+        const EXT2_MAGIC: u16 = 0xEF53;
+        const INCOMPAT_64BIT: u32 = 0x80;
+        // 1K to 64K blocks
+        const MAX_LOG_BLOCK_SIZE: u32 = 6;
+
+        let mut sb = [0; 0x15c];
+        let mut file = fs_err::File::open(image)?;
+        file.seek(SeekFrom::Start(SUPERBLOCK_OFFSET))?;
+        match file.read_exact(&mut sb) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let u16_at = |offset: usize| u16::from_le_bytes([sb[offset], sb[offset + 1]]);
+        let u32_at = |offset: usize| u32::from_le_bytes(sb[offset..offset + 4].try_into().unwrap());
+        if u16_at(0x38) != EXT2_MAGIC {
+            return Ok(None);
+        }
+
+        let log_block_size = u32_at(0x18);
+        if log_block_size > MAX_LOG_BLOCK_SIZE {
+            anyhow::bail!(
+                "{} has an invalid ext2 block size (s_log_block_size {})",
+                image.display(),
+                log_block_size
+            );
+        }
+
+        let mut block_count = u32_at(0x4) as u64;
+        let mut free_blocks = u32_at(0xc) as u64;
+        if u32_at(0x60) & INCOMPAT_64BIT != 0 {
+            block_count |= (u32_at(0x150) as u64) << 32;
+            free_blocks |= (u32_at(0x158) as u64) << 32;
+        }
+        Ok(Some(Self {
+            block_count,
+            free_blocks,
+            block_size: 1024 << log_block_size,
+        }))
+    }
+
+    fn total_mb(&self) -> u64 {
+        self.block_count * self.block_size / (1024 * 1024)
+    }
+
+    /// Size in MiB that fits the current contents plus `injected_bytes`,
+    /// with headroom.
+    fn target_mb(&self, injected_bytes: u64) -> u64 {
+        let used = (self.block_count - self.free_blocks.min(self.block_count)) * self.block_size;
+        let needed = (used + injected_bytes) * (100 + ROOTFS_HEADROOM_PERCENT) / 100;
+        needed.div_ceil(1024 * 1024)
+    }
+}
+
+/// Find the container runtime to run e2fsprogs in: `override_runtime` if
+/// given, otherwise the first of [`CONTAINER_RUNTIMES`] on `PATH`.
+fn resolve_container_runtime(override_runtime: Option<&str>) -> anyhow::Result<PathBuf> {
+    let runtime = match override_runtime {
+        Some(runtime) => {
+            which::which(runtime).with_context(|| format!("container runtime '{}' not found", runtime))?
+        }
+        None => CONTAINER_RUNTIMES
+            .into_iter()
+            .find_map(|runtime| which::which(runtime).ok())
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "neither docker nor podman found on PATH, install one (or pass --container-runtime) to check and resize rootfs.ext2"
+                )
+            })?,
+    };
+    log::info!("Using container runtime {}", runtime.display());
+    Ok(runtime)
+}
+
+/// Build [`E2FSPROGS_IMAGE`], unless `runtime` already has it.
+fn ensure_e2fsprogs_image(runtime: &Path) -> anyhow::Result<()> {
+    let present = Command::new(runtime)
+        .args(["image", "inspect", E2FSPROGS_IMAGE])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success());
+    if present {
+        return Ok(());
+    }
+
+    log::info!("Building the {} container image...", E2FSPROGS_IMAGE);
+    let mut build = Command::new(runtime);
+    build.args(["build", "-t", E2FSPROGS_IMAGE]);
+    if runtime
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with("podman"))
+    {
+        // podman can't take the Dockerfile as the build context, so give it
+        // an empty one
+        let context_dir = std::env::temp_dir().join("cca-fvp-e2fsprogs-context");
+        fs_err::create_dir_all(&context_dir)?;
+        build.arg("-f").arg("-").arg(context_dir);
+    } else {
+        build.arg("-");
+    }
+    let mut child = build
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run {} build", runtime.display()))?;
+    child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow::anyhow!("failed to open stdin of {} build", runtime.display()))?
+        .write_all(E2FSPROGS_DOCKERFILE.as_bytes())?;
+    let status = child.wait()?;
+    if !status.success() {
+        anyhow::bail!(
+            "building the {} container image {}",
+            E2FSPROGS_IMAGE,
+            describe_exit_status(status)
+        );
+    }
+    Ok(())
+}
+
+/// Read the injection sentinel in `inject_dir` from `rootfs_ext2`, with
+/// debugfs if `use_debugfs` and otherwise by mounting it read-only. Empty if
+/// it can't be read (e.g. nothing has been injected yet).
+fn read_injection_sentinel(
+    rootfs_ext2: &Path,
+    rootfs_dir: &Path,
+    inject_dir: &str,
+    use_debugfs: bool,
+) -> BTreeMap<String, String> {
+    let parse = |contents: &[u8]| {
+        String::from_utf8_lossy(contents)
+            .lines()
+            .filter_map(|line| line.split_once("  "))
+            .map(|(hash, name)| (name.to_string(), hash.to_string()))
+            .collect()
+    };
+    if use_debugfs {
+        let output = Command::new("debugfs")
+            .arg("-R")
+            .arg(format!("cat /{}/{}", inject_dir, INJECTION_SENTINEL))
+            .arg(rootfs_ext2)
+            .stderr(Stdio::null())
+            .output();
+        return match output {
+            Ok(output) if output.status.success() => parse(&output.stdout),
+            _ => BTreeMap::new(),
+        };
+    }
+
+    let mnt = rootfs_dir.join("mnt-sentinel");
+    if fs_err::create_dir_all(&mnt).is_err() {
+        return BTreeMap::new();
+    }
+    let mut sentinel = BTreeMap::new();
+    let mounted = Command::new("sudo")
+        .args(["mount", "-o", "ro"])
+        .arg(rootfs_ext2)
+        .arg(&mnt)
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success());
+    if mounted {
+        let output = Command::new("sudo")
+            .arg("cat")
+            .arg(mnt.join(inject_dir).join(INJECTION_SENTINEL))
+            .stderr(Stdio::null())
+            .output();
+        if let Ok(output) = output
+            && output.status.success()
+        {
+            sentinel = parse(&output.stdout);
+        }
+        unmount(&mnt);
+    }
+    let _ = fs_err::remove_dir(&mnt);
+    sentinel
+}
+
+/// Log and run `cmd`, failing if it can't be run or exits unsuccessfully.
+///
+/// This doesn't escalate by itself: steps that need root (mounting and
+/// writing into the rootfs) pass a `sudo` command, while ones working on
+/// the user's own files (e.g. `sed` on a host-side copy) run unprivileged.
+fn run_checked(cmd: &mut Command) -> anyhow::Result<()> {
+    let command = command_line(cmd);
+    log::info!("$ {}", command);
+    match cmd.status() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => anyhow::bail!("`{}` {}", command, describe_exit_status(status)),
+        Err(e) => anyhow::bail!("failed to run `{}`: {}", command, e),
+    }
+}
+
+/// Write `contents` to the root-owned `path` (appending, with `append`),
+/// through `sudo tee`.
+fn write_privileged(path: &Path, contents: &str, append: bool) -> anyhow::Result<()> {
+    let mut cmd = Command::new("sudo");
+    cmd.arg("tee");
+    if append {
+        cmd.arg("-a");
+    }
+    cmd.arg(path).stdin(Stdio::piped()).stdout(Stdio::null());
+    let command = command_line(&cmd);
+    log::info!("$ {}", command);
+    let mut child = cmd
+        .spawn()
+        .with_context(|| format!("failed to run `{}`", command))?;
+    child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow::anyhow!("failed to open stdin of `{}`", command))?
+        .write_all(contents.as_bytes())?;
+    let status = child.wait()?;
+    if !status.success() {
+        anyhow::bail!("`{}` {}", command, describe_exit_status(status));
+    }
+    Ok(())
+}
+
+/// Unmount `mnt`, lazily if it's busy.
+fn unmount(mnt: &Path) {
+    if run_checked(Command::new("sudo").arg("umount").arg(mnt)).is_err()
+        && let Err(e) = run_checked(Command::new("sudo").args(["umount", "-l"]).arg(mnt))
+    {
+        log::warn!("Failed to unmount {}: {:#}", mnt.display(), e);
+    }
+}
+
+/// An update to a config file in the rootfs: drop the lines matching the
+/// `sed -E` address `stale`, then append `line`.
+struct EtcUpdate {
+    /// File to update, relative to the rootfs root
+    file: &'static str,
+    stale: String,
+    line: String,
+    /// Guest dir to create along with the update (a 9p mount point)
+    create_dir: Option<PathBuf>,
+}
+
+/// Copy `files` (source, file name in the rootfs) into `inject_dir` and
+/// apply `etc_updates` in the rootfs mounted at `mnt`, then record `sentinel`
+/// in it.
+fn inject_into_mounted_rootfs(
+    mnt: &Path,
+    inject_dir: &str,
+    files: &[(&Path, &str)],
+    etc_updates: &[EtcUpdate],
+    sentinel: &str,
+) -> anyhow::Result<()> {
+    let target_dir = mnt.join(inject_dir);
+    run_checked(Command::new("sudo").args(["mkdir", "-p"]).arg(&target_dir))?;
+    for (source, name) in files {
+        run_checked(
+            Command::new("sudo")
+                .arg("cp")
+                .arg(source)
+                .arg(target_dir.join(name)),
+        )?;
+    }
+    for update in etc_updates {
+        if let Some(dir) = &update.create_dir {
+            let dir = mnt.join(dir.strip_prefix("/").unwrap_or(dir));
+            run_checked(Command::new("sudo").args(["mkdir", "-p"]).arg(dir))?;
+        }
+        let file = mnt.join(update.file);
+        run_checked(
+            Command::new("sudo")
+                .args(["sed", "-i", "-E"])
+                .arg(format!("{}d", update.stale))
+                .arg(&file),
+        )?;
+        write_privileged(&file, &format!("{}\n", update.line), true)?;
+    }
+    write_privileged(
+        &target_dir.join(INJECTION_SENTINEL),
+        &format!("{}\n", sentinel),
+        false,
+    )?;
+    run_checked(&mut Command::new("sync"))
+}
+
+/// Run the debugfs `commands` against `rootfs_ext2`, opened read-write if
+/// `write`, and return its stdout. debugfs exits successfully even when one
+/// of its commands fails, so callers check the result themselves.
+fn run_debugfs(rootfs_ext2: &Path, commands: &[String], write: bool) -> anyhow::Result<String> {
+    let rootfs_dir = rootfs_ext2
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("rootfs.ext2 has no parent directory"))?;
+    let command_file = rootfs_dir.join("debugfs-commands");
+    fs_err::write(&command_file, commands.join("\n") + "\n")?;
+
+    let mut cmd = Command::new("debugfs");
+    if write {
+        cmd.arg("-w");
+    }
+    cmd.arg("-f").arg(&command_file).arg(rootfs_ext2);
+    let command = command_line(&cmd);
+    log::info!("$ {}", command);
+    for line in commands {
+        log::debug!("debugfs: {}", line);
+    }
+    let output = cmd.output();
+    let _ = fs_err::remove_file(&command_file);
+    let output =
+        output.with_context(|| format!("failed to run `{}`, is e2fsprogs installed?", command))?;
+    if !output.status.success() {
+        anyhow::bail!("`{}` {}", command, describe_exit_status(output.status));
+    }
+    for line in String::from_utf8_lossy(&output.stderr).lines() {
+        log::debug!("debugfs: {}", line);
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Copy `files` (source, file name in the rootfs) into `inject_dir` and
+/// apply `etc_updates` in `rootfs_ext2` with debugfs, without mounting it,
+/// then record `sentinel` in it. Checks that every file landed by listing
+/// `inject_dir` afterwards.
+fn inject_with_debugfs(
+    rootfs_ext2: &Path,
+    inject_dir: &str,
+    files: &[(&Path, &str)],
+    etc_updates: &[EtcUpdate],
+    sentinel: &str,
+) -> anyhow::Result<()> {
+    let rootfs_dir = rootfs_ext2
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("rootfs.ext2 has no parent directory"))?;
+
+    // debugfs `write` doesn't replace existing files and takes the owner of
+    // the host file, so remove each file first and chown it to root after.
+    // `mkdir` fails harmlessly if the dir already exists.
+    let write = |commands: &mut Vec<String>, dir: &str, source: &Path, name: &str| {
+        commands.push(format!("cd /{}", dir));
+        commands.push(format!("rm \"{}\"", name));
+        commands.push(format!("write \"{}\" \"{}\"", source.display(), name));
+        commands.push(format!("sif \"{}\" uid 0", name));
+        commands.push(format!("sif \"{}\" gid 0", name));
+    };
+    // debugfs creates top-level dirs in its cwd, not in /, and has no
+    // `mkdir -p`
+    let mkdir_all = |commands: &mut Vec<String>, dir: &Path| {
+        commands.push("cd /".to_string());
+        let mut guest_dir = PathBuf::from("/");
+        for component in dir.strip_prefix("/").unwrap_or(dir).components() {
+            guest_dir.push(component);
+            commands.push(format!("mkdir \"{}\"", guest_dir.display()));
+        }
+    };
+    let mut commands = Vec::new();
+    mkdir_all(&mut commands, Path::new(inject_dir));
+    let mut expected = vec![INJECTION_SENTINEL.to_string()];
+    for (source, name) in files {
+        write(&mut commands, inject_dir, source, name);
+        expected.push(name.to_string());
+    }
+
+    // Edit a copy of each /etc file on the host, then write it back
+    let mut temp_files = Vec::new();
+    let mut etc_copies: BTreeMap<&str, PathBuf> = BTreeMap::new();
+    for update in etc_updates {
+        if let Some(dir) = &update.create_dir {
+            mkdir_all(&mut commands, dir);
+        }
+        let copy = match etc_copies.get(update.file) {
+            Some(copy) => copy.clone(),
+            None => {
+                let copy = rootfs_dir.join(format!("debugfs-{}", update.file.replace('/', "-")));
+                let contents = run_debugfs(rootfs_ext2, &[format!("cat /{}", update.file)], false)?;
+                fs_err::write(&copy, contents)?;
+                temp_files.push(copy.clone());
+                etc_copies.insert(update.file, copy.clone());
+                copy
+            }
+        };
+        run_checked(
+            Command::new("sed")
+                .args(["-i", "-E"])
+                .arg(format!("{}d", update.stale))
+                .arg(&copy),
+        )?;
+        let mut contents = fs_err::read_to_string(&copy)?;
+        contents.push_str(&format!("{}\n", update.line));
+        fs_err::write(&copy, contents)?;
+    }
+    for (file, copy) in &etc_copies {
+        let (dir, name) = file.rsplit_once('/').unwrap_or(("", file));
+        write(&mut commands, dir, copy, name);
+    }
+
+    let sentinel_file = rootfs_dir.join("debugfs-sentinel");
+    fs_err::write(&sentinel_file, format!("{}\n", sentinel))?;
+    temp_files.push(sentinel_file.clone());
+    write(
+        &mut commands,
+        inject_dir,
+        &sentinel_file,
+        INJECTION_SENTINEL,
+    );
+
+    let result = run_debugfs(rootfs_ext2, &commands, true);
+    for file in temp_files {
+        let _ = fs_err::remove_file(file);
+    }
+    result?;
+
+    // `ls -p` prints one `/inode/mode/uid/gid/name/size/` line per entry
+    let listing = run_debugfs(rootfs_ext2, &[format!("ls -p \"/{}\"", inject_dir)], false)?;
+    let present: Vec<&str> = listing
+        .lines()
+        .filter_map(|line| line.split('/').nth(5))
+        .collect();
+    let missing: Vec<&String> = expected
+        .iter()
+        .filter(|name| !present.contains(&name.as_str()))
+        .collect();
+    if !missing.is_empty() {
+        anyhow::bail!(
+            "debugfs did not write {} into /{}",
+            missing
+                .iter()
+                .map(|name| name.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+            inject_dir
+        );
+    }
+    log::info!(
+        "Verified /{} contents with debugfs: {}",
+        inject_dir,
+        present.join(", ")
+    );
+    Ok(())
+}
+
+/// Check that the host kernel and the selected TMK binaries were built with
+/// compatible toolchains, going by both the install node's toolchain
+/// manifest and the artifacts' own headers. Problems fail the run if
+/// `strict`, and are only warned about otherwise.
+fn check_toolchain_compat(
+    shrinkwrap_dir: &Path,
+    tmk_cargo_workspace: Option<&Path>,
+    tmk_binaries: &[String],
+    tmk_vmm_target: TmkVmmTarget,
+    strict: bool,
+) -> anyhow::Result<()> {
+    let toolchain_dir = shrinkwrap_dir
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("shrinkwrap_dir has no parent"))?;
+
+    let mut problems = match ToolchainManifest::read(toolchain_dir)? {
+        Some(manifest) => manifest.compat_problems(),
+        None => {
+            log::warn!(
+                "No toolchain manifest in {}, only checking artifact headers",
+                toolchain_dir.display()
+            );
+            Vec::new()
+        }
+    };
+
+    let kernel_image = kernel_image_path(
+        &toolchain_dir.join(HOST_KERNEL_DIR),
+        KERNEL_ARCH,
+        KERNEL_IMAGE_TARGET,
+    );
+    if kernel_image.exists() && !is_arm64_kernel_image(&kernel_image)? {
+        problems.push(format!(
+            "{} is not an arm64 kernel Image",
+            kernel_image.display()
+        ));
+    }
+    let tmk_kernel_dir = toolchain_dir.join(TMK_DIR);
+    let tmk_workspace = tmk_cargo_workspace.unwrap_or(&tmk_kernel_dir);
+    for (name, path) in [
+        ("simple_tmk", simple_tmk_path(tmk_workspace)),
+        ("tmk_vmm", tmk_vmm_path(tmk_workspace, tmk_vmm_target)),
+    ] {
+        if tmk_binary_selected(tmk_binaries, name) && path.exists() && !is_aarch64_elf(&path)? {
+            problems.push(format!(
+                "{} at {} is not an AArch64 ELF binary",
+                name,
+                path.display()
+            ));
+        }
+    }
+
+    if problems.is_empty() {
+        log::info!("Host kernel and TMK binaries were built with compatible toolchains");
+        return Ok(());
+    }
+    if strict {
+        anyhow::bail!(
+            "host kernel and TMK binaries were built with incompatible toolchains:\n  {}",
+            problems.join("\n  ")
+        );
+    }
+    for problem in &problems {
+        log::warn!("Toolchain mismatch: {}", problem);
+    }
+    Ok(())
+}
+
+/// Resize rootfs.ext2, inject the selected TMK binaries and kernel into
+/// `inject_dir`, and add `etc_hosts_entries` to `/etc/hosts`.
+///
+/// Only files that changed since the last injection are copied, and the
+/// rootfs isn't touched at all if nothing changed. Returns the sha256 of each
+/// injected file, by name.
+fn modify_rootfs(
+    shrinkwrap_dir: &Path,
+    tmk_cargo_workspace: Option<&Path>,
+    tmk_binaries: &[String],
+    tmk_vmm_target: TmkVmmTarget,
+    inject_dir: &str,
+    extra_injections: &[(PathBuf, String)],
+    rootfs_ext2: &Path,
+    etc_hosts_entries: &[(String, String)],
+    ninep_shares: &[NinePShare],
+    use_debugfs: bool,
+    container_runtime: Option<&str>,
+) -> anyhow::Result<BTreeMap<String, String>> {
+    // Compute paths the same way as install job
+    // Get the parent directory (toolchain_dir) where everything is built
+    let toolchain_dir = shrinkwrap_dir
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("shrinkwrap_dir has no parent"))?;
+
+    let tmk_kernel_dir = toolchain_dir.join(TMK_DIR);
+    let host_kernel_dir = toolchain_dir.join(HOST_KERNEL_DIR);
+
+    let tmk_workspace = tmk_cargo_workspace.unwrap_or(&tmk_kernel_dir);
+
+    let simple_tmk = simple_tmk_path(tmk_workspace);
+    let tmk_vmm = tmk_vmm_path(tmk_workspace, tmk_vmm_target);
+    let kernel_image_path = kernel_image_path(&host_kernel_dir, KERNEL_ARCH, KERNEL_IMAGE_TARGET);
+
+    // Modify rootfs.ext2 to inject TMK binaries and kernel
+    log::info!("Starting rootfs.ext2 modification...");
+
+    log::info!("Found rootfs.ext2 at {}", rootfs_ext2.display());
+
+    // Get the directory containing rootfs.ext2 for docker mounting
+    let rootfs_dir = rootfs_ext2
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("rootfs.ext2 has no parent directory"))?;
+    let rootfs_filename = rootfs_ext2
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("Invalid rootfs path"))?
+        .to_string_lossy();
+
+    // Same directory as rootfs.ext2
+    let guest_disk = rootfs_dir.join("guest-disk.img");
+    let kvmtool_efi = rootfs_dir.join("KVMTOOL_EFI.fd");
+    let lkvm = rootfs_dir.join("lkvm");
+
+    log::info!("Using simple_tmk from: {}", simple_tmk.display());
+    log::info!("Using tmk_vmm from: {}", tmk_vmm.display());
+    log::info!("Using kernel Image from: {}", kernel_image_path.display());
+
+    // Copy kernel to Image_ohcl
+    let image_ohcl = rootfs_dir.join("Image_ohcl");
+    if kernel_image_path.exists() {
+        if !is_arm64_kernel_image(&kernel_image_path)? {
+            anyhow::bail!(
+                "{} is not an arm64 kernel Image, check that the kernel was cross-compiled",
+                kernel_image_path.display()
+            );
+        }
+        fs::copy(&kernel_image_path, &image_ohcl)
+            .map_err(|e| anyhow::anyhow!("Failed to copy kernel Image: {}", e))?;
+        log::info!("Copied kernel to Image_ohcl");
+    } else {
+        log::warn!("Kernel image not found at {}", kernel_image_path.display());
+    }
+
+    // Files to inject into inject_dir. A host binary built by mistake would only
+    // fail once the FVP boots, so check the architecture up front.
+    let mut injected_files: Vec<(&Path, String)> = Vec::new();
+    for (name, path) in [("simple_tmk", &simple_tmk), ("tmk_vmm", &tmk_vmm)] {
+        if !tmk_binary_selected(tmk_binaries, name) {
+            continue;
+        }
+        if path.exists() {
+            assert_aarch64_elf(path)?;
+            injected_files.push((path, name.to_string()));
+        } else {
+            log::warn!("{} not found", path.display());
+        }
+    }
+    for path in [&guest_disk, &kvmtool_efi, &image_ohcl, &lkvm] {
+        if let Some(name) = path.file_name()
+            && path.exists()
+        {
+            injected_files.push((path, name.to_string_lossy().into_owned()));
+        }
+    }
+    for (source, name) in extra_injections {
+        if !source.is_file() {
+            log::warn!(
+                "Extra injection {} not found, not injecting it as {}",
+                source.display(),
+                name
+            );
+            continue;
+        }
+        if injected_files.iter().any(|(_, injected)| injected == name) {
+            log::warn!(
+                "Extra injection {} replaces the built-in {}",
+                source.display(),
+                name
+            );
+            injected_files.retain(|(_, injected)| injected != name);
+        }
+        injected_files.push((source, name.clone()));
+    }
+
+    // Replace any existing entry for each hostname, then append the new one
+    let mut etc_updates = Vec::new();
+    for (ip, hostname) in etc_hosts_entries {
+        log::debug!("Injecting /etc/hosts entry: {} {}", ip, hostname);
+        etc_updates.push(EtcUpdate {
+            file: "etc/hosts",
+            stale: format!(
+                "/[[:space:]]{}([[:space:]]|$)/",
+                hostname.replace('.', "\\.")
+            ),
+            line: format!("{} {}", ip, hostname),
+            create_dir: None,
+        });
+    }
+
+    // Replace any existing fstab entry for each 9p share, then append the new one
+    for share in ninep_shares {
+        let options = if share.readonly {
+            "trans=virtio,version=9p2000.L,ro"
+        } else {
+            "trans=virtio,version=9p2000.L"
+        };
+        log::debug!(
+            "Injecting /etc/fstab entry for 9p share '{}' at {}",
+            share.mount_tag,
+            share.mount_point.display()
+        );
+        etc_updates.push(EtcUpdate {
+            file: "etc/fstab",
+            stale: format!("/^{}[[:space:]]/", share.mount_tag),
+            line: format!(
+                "{} {} 9p {} 0 0",
+                share.mount_tag,
+                share.mount_point.display(),
+                options
+            ),
+            create_dir: Some(share.mount_point.clone()),
+        });
+    }
+
+    // Record what's injected, so unchanged files aren't copied again
+    let mut injected = BTreeMap::new();
+    for (path, name) in &injected_files {
+        injected.insert(name.clone(), sha256_file(path)?);
+    }
+    if !etc_updates.is_empty() {
+        let mut hasher = Sha256::new();
+        for update in &etc_updates {
+            for field in [update.file, &update.stale, &update.line] {
+                hasher.update(field.as_bytes());
+                hasher.update([0]);
+            }
+        }
+        injected.insert(
+            ETC_UPDATE_SENTINEL_ENTRY.to_string(),
+            format!("{:x}", hasher.finalize()),
+        );
+    }
+
+    let previously_injected =
+        read_injection_sentinel(rootfs_ext2, rootfs_dir, inject_dir, use_debugfs);
+    let changed: Vec<&str> = injected
+        .iter()
+        .filter(|(name, hash)| previously_injected.get(*name) != Some(*hash))
+        .map(|(name, _)| name.as_str())
+        .collect();
+    if changed.is_empty() {
+        log::info!("rootfs.ext2 already has the current files injected, skipping injection");
+        return Ok(injected);
+    }
+    log::info!("Injecting changed files: {}", changed.join(", "));
+
+    // Step 1: Check the filesystem and grow it to fit what's injected, in a
+    // single container
+    let injected_bytes = injected_files
+        .iter()
+        .map(|(path, _)| Ok(fs_err::metadata(path)?.len()))
+        .sum::<anyhow::Result<u64>>()?;
+    let fs_size = Ext2Size::read_superblock(rootfs_ext2)?;
+    let target_mb = match &fs_size {
+        Some(size) => size.target_mb(injected_bytes),
+        None => {
+            log::warn!(
+                "Could not read the size of rootfs.ext2 from its superblock, resizing to {}M",
+                FALLBACK_ROOTFS_SIZE_MB
+            );
+            FALLBACK_ROOTFS_SIZE_MB
+        }
+    };
+    let resize_to = if let Some(size) = fs_size.as_ref().filter(|size| size.total_mb() >= target_mb)
+    {
+        log::info!(
+            "rootfs.ext2 is already {}M, enough for the {} bytes injected, not resizing",
+            size.total_mb(),
+            injected_bytes
+        );
+        None
+    } else {
+        log::info!(
+            "Resizing rootfs.ext2 to {}M to fit {} injected bytes...",
+            target_mb,
+            injected_bytes
+        );
+        Some(format!("{}M", target_mb))
+    };
+
+    let runtime = resolve_container_runtime(container_runtime)?;
+    ensure_e2fsprogs_image(&runtime)?;
+    log::info!("Running e2fsck on rootfs.ext2...");
+    let output = Command::new(&runtime)
+        .args(["run", "--rm", "-v"])
+        .arg(format!("{}:{}", rootfs_dir.display(), rootfs_dir.display()))
+        .arg("-w")
+        .arg(rootfs_dir)
+        .args([E2FSPROGS_IMAGE, "sh", "-c", E2FSCK_RESIZE_SCRIPT, "sh"])
+        .arg(rootfs_filename.as_ref())
+        .arg(resize_to.as_deref().unwrap_or_default())
+        .stderr(Stdio::inherit())
+        .output()
+        .context("Failed to run e2fsck")?;
+    if !output.status.success() {
+        log::warn!(
+            "{} run {}",
+            runtime.display(),
+            describe_exit_status(output.status)
+        );
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let exit_codes: BTreeMap<&str, &str> = stdout
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .collect();
+    let mut tools = vec!["e2fsck"];
+    if resize_to.is_some() {
+        tools.push("resize2fs");
+    }
+    for tool in tools {
+        match exit_codes.get(tool) {
+            Some(&"0") => log::info!("{} completed successfully", tool),
+            Some(code) => log::warn!("{} exited with status {}", tool, code),
+            None => log::warn!("{} did not run", tool),
+        }
+    }
+
+    // Step 2: Mount rootfs, inject files, and unmount
+    let copies = injected_files
+        .iter()
+        .filter(|(_, name)| changed.contains(&name.as_str()))
+        .map(|(path, name)| (*path, name.as_str()))
+        .collect::<Vec<_>>();
+    let etc_updates = if changed.contains(&ETC_UPDATE_SENTINEL_ENTRY) {
+        etc_updates.as_slice()
+    } else {
+        &[]
+    };
+    let sentinel = injected
+        .iter()
+        .map(|(name, hash)| format!("{}  {}", hash, name))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    if use_debugfs {
+        log::info!(
+            "Injecting TMK binaries into /{} with debugfs...",
+            inject_dir
+        );
+        inject_with_debugfs(rootfs_ext2, inject_dir, &copies, etc_updates, &sentinel)
+            .context("Failed to inject files into rootfs.ext2")?;
+        log::info!("rootfs.ext2 updated successfully with TMK binaries");
+        return Ok(injected);
+    }
+
+    let mnt = rootfs_dir.join("mnt");
+    log::info!(
+        "Mounting rootfs.ext2 and injecting TMK binaries into {}...",
+        mnt.join(inject_dir).display()
+    );
+    fs_err::create_dir_all(&mnt)?;
+    run_checked(Command::new("sudo").arg("mount").arg(rootfs_ext2).arg(&mnt))
+        .context("Failed to mount rootfs.ext2")?;
+    let result = inject_into_mounted_rootfs(&mnt, inject_dir, &copies, etc_updates, &sentinel);
+
+    // Unmount even if the injection failed, so the rootfs isn't left mounted
+    unmount(&mnt);
+    let _ = Command::new("sync").status();
+    let mut removed = false;
+    for _ in 0..5 {
+        if fs_err::remove_dir(&mnt).is_ok() || !mnt.exists() {
+            removed = true;
+            break;
+        }
+        thread::sleep(Duration::from_millis(500));
+    }
+    if !removed {
+        log::warn!("Failed to remove mount point {}", mnt.display());
+    }
+
+    result.context("Failed to inject files into rootfs.ext2")?;
+    log::info!("rootfs.ext2 updated successfully with TMK binaries");
+
+    Ok(injected)
+}
+
+/// Spawn `cmd` and echo its stdout (saving it to `log_path`) until a line
+/// containing `marker` shows up, then tear it down. Returns how long the
+/// guest took to reach the marker.
+fn run_until_boot_marker(
+    mut cmd: Command,
+    marker: &str,
+    timeout: Duration,
+    log_path: &Path,
+) -> anyhow::Result<Duration> {
+    if let Some(parent) = log_path.parent() {
+        fs_err::create_dir_all(parent)?;
+    }
+    let mut log_file = std::io::BufWriter::new(fs_err::File::create(log_path)?);
+
+    cmd.stdout(Stdio::piped());
+
+    let mut child = spawn_in_process_group(&mut cmd).context("failed to spawn shrinkwrap run")?;
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| anyhow::anyhow!("failed to capture stdout"))?;
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let start = Instant::now();
+    let result = loop {
+        match rx.recv_timeout(timeout.saturating_sub(start.elapsed())) {
+            Ok(line) => {
+                println!("{}", line);
+                writeln!(log_file, "{}", line)?;
+                if line.contains(marker) {
+                    break Ok(start.elapsed());
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                break Err(anyhow::anyhow!(
+                    "guest did not reach boot marker '{}' within {}s",
+                    marker,
+                    timeout.as_secs()
+                ));
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                let status = wait_with_timeout(&mut child, None)?;
+                break Err(anyhow::anyhow!(
+                    "shrinkwrap run {} before guest reached boot marker '{}'",
+                    describe_exit_status(status),
+                    marker
+                ));
+            }
+        }
+    };
+
+    kill_process_group(&mut child);
+    log_file.flush()?;
+    result
+}
+
+/// The last `n` lines of the file at `path`, or a note that it's missing.
+fn log_tail(path: &Path, n: usize) -> String {
+    match fs_err::read_to_string(path) {
+        Ok(log) => {
+            let lines: Vec<&str> = log.lines().collect();
+            lines[lines.len().saturating_sub(n)..].join("\n")
+        }
+        Err(e) => format!("(unavailable: {})", e),
+    }
+}
+
+/// Write `<out_dir>/boot-failure-diagnostics.txt`, collecting what's needed
+/// to triage a guest that didn't boot: the tails of the run's console log
+/// (`run_log`) and of the shrinkwrap build log, the files injected into the
+/// rootfs, and the build and toolchain manifests (the latter from next to
+/// `shrinkwrap_dir`). Returns `error`, pointing at the diagnostics.
+fn report_boot_failure(
+    out_dir: &Path,
+    shrinkwrap_dir: &Path,
+    run_log: &Path,
+    injected: &BTreeMap<String, String>,
+    error: anyhow::Error,
+) -> anyhow::Error {
+    let mut report = String::new();
+    report.push_str(&format!("Boot failure: {:#}\n", error));
+    report.push_str(&format!("Boot marker: {}\n", BOOT_MARKER));
+
+    report.push_str("\n== Build ==\n");
+    match fs_err::read_to_string(out_dir.join("artifacts.json"))
+        .ok()
+        .and_then(|manifest| serde_json::from_str::<serde_json::Value>(&manifest).ok())
+    {
+        Some(serde_json::Value::Object(fields)) => {
+            for (key, value) in fields {
+                // Scalar fields only; the per-artifact hashes are in the file
+                if !value.is_object() && !value.is_array() {
+                    report.push_str(&format!("{}: {}\n", key, value));
+                }
+            }
+        }
+        _ => report.push_str("(no artifacts.json)\n"),
+    }
+    match shrinkwrap_dir.parent().map(ToolchainManifest::read) {
+        Some(Ok(Some(toolchains))) => {
+            report.push_str(&format!(
+                "kernel: ARCH={} CROSS_COMPILE={}\n",
+                toolchains.kernel_arch.as_deref().unwrap_or("unknown"),
+                toolchains
+                    .kernel_cross_compile
+                    .as_deref()
+                    .unwrap_or("unknown")
+            ));
+            for (name, target) in &toolchains.tmk_targets {
+                report.push_str(&format!("{}: {}\n", name, target));
+            }
+        }
+        _ => report.push_str("(no toolchain manifest)\n"),
+    }
+
+    report.push_str("\n== Injected files (sha256) ==\n");
+    if injected.is_empty() {
+        report.push_str("(none)\n");
+    }
+    for (name, sha256) in injected {
+        report.push_str(&format!("{}  {}\n", sha256, name));
+    }
+
+    report.push_str(&format!(
+        "\n== Console output (last {} lines of {}) ==\n{}\n",
+        DIAGNOSTICS_LOG_LINES,
+        run_log.display(),
+        log_tail(run_log, DIAGNOSTICS_LOG_LINES)
+    ));
+    let build_log = out_dir.join("logs").join("shrinkwrap-build.log");
+    report.push_str(&format!(
+        "\n== Shrinkwrap build output (last {} lines of {}) ==\n{}\n",
+        DIAGNOSTICS_LOG_LINES,
+        build_log.display(),
+        log_tail(&build_log, DIAGNOSTICS_LOG_LINES)
+    ));
+
+    let path = out_dir.join(BOOT_FAILURE_DIAGNOSTICS);
+    match fs_err::write(&path, report) {
+        Ok(()) => {
+            log::error!(
+                "Guest failed to boot, diagnostics written to {}",
+                path.display()
+            );
+            error.context(format!(
+                "guest failed to boot (diagnostics in {})",
+                path.display()
+            ))
+        }
+        Err(e) => {
+            log::warn!("Failed to write boot failure diagnostics: {}", e);
+            error
+        }
+    }
+}
+
+/// `shrinkwrap` from the clone in `shrinkwrap_dir`, set up to run in its
+/// venv from `out_dir`, where the build artifacts are.
+fn shrinkwrap_command(shrinkwrap_dir: &Path, out_dir: &Path) -> Command {
+    let venv_dir = shrinkwrap_dir.join("venv");
+    let mut cmd = Command::new(shrinkwrap_dir.join("shrinkwrap").join("shrinkwrap"));
+    cmd.env(
+        "PATH",
+        format!(
+            "{}:{}",
+            venv_dir.join("bin").display(),
+            std::env::var("PATH").unwrap_or_default()
+        ),
+    )
+    .env("VIRTUAL_ENV", venv_dir)
+    .current_dir(out_dir);
+    cmd
+}
+
+/// Arguments of `shrinkwrap run` for `platform_yaml`: each of `rtvars`
+/// (`KEY=VALUE`) as a `--rtvar`, followed by the `extra_args` escape hatch,
+/// which comes last so it can override anything before it.
+fn build_run_args(platform_yaml: &Path, rtvars: &[String], extra_args: &[String]) -> Vec<OsString> {
+    let mut args = vec![OsString::from("run"), platform_yaml.into()];
+    for rtvar in rtvars {
+        args.push("--rtvar".into());
+        args.push(rtvar.into());
+    }
+    args.extend(extra_args.iter().map(OsString::from));
+    args
+}
+
+/// Run `cmd`, echoing its stdout and saving it to `log_path`.
+fn run_logged(
+    mut cmd: Command,
+    log_path: &Path,
+    timeout: Option<Duration>,
+) -> anyhow::Result<std::process::ExitStatus> {
+    if let Some(parent) = log_path.parent() {
+        fs_err::create_dir_all(parent)?;
+    }
+    let log_file = fs_err::File::create(log_path)?;
+    // With no timeout to enforce, a plain tee will do
+    let Some(timeout) = timeout else {
+        return tee_command_output(cmd, log_path);
+    };
+    let mut log_file = std::io::BufWriter::new(log_file);
+
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+    let mut child = spawn_in_process_group(&mut cmd)?;
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| anyhow::anyhow!("failed to capture stdout"))?;
+    let stderr = child
+        .stderr
+        .take()
+        .ok_or_else(|| anyhow::anyhow!("failed to capture stderr"))?;
+
+    // Tee the output the same way the build does, from a thread so the
+    // timeout can be enforced here
+    let (tx, rx) = mpsc::channel();
+    forward_lines(stdout, false, tx.clone());
+    forward_lines(stderr, true, tx);
+    let tee = thread::spawn(move || -> anyhow::Result<()> {
+        for (is_stderr, line) in rx {
+            write_line(&mut log_file, is_stderr, &line)?;
+        }
+        log_file.flush()?;
+        Ok(())
+    });
+
+    let status = wait_with_timeout(&mut child, Some(timeout));
+    // Once the process group is gone the pipes close, so the log is complete
+    // up to the exit (or the kill) either way
+    tee.join()
+        .map_err(|_| anyhow::anyhow!("shrinkwrap output thread panicked"))??;
+    status
+}
+
+/// Check the guest console log for the virtio-balloon driver reporting its
+/// size (`virtio_balloon: ... pages`).
+fn check_balloon_initialized(log_path: &Path) -> anyhow::Result<()> {
+    let log = fs_err::read_to_string(log_path)?;
+    match log
+        .lines()
+        .find(|line| line.contains("virtio_balloon") && line.contains("pages"))
+    {
+        Some(line) => {
+            log::info!("virtio-balloon initialized: {}", line.trim());
+            Ok(())
+        }
+        None => anyhow::bail!(
+            "virtio-balloon was configured, but the guest never reported initializing it (see {})",
+            log_path.display()
+        ),
+    }
+}
+
+/// Save a copy of `rootfs_ext2` to `rootfs_out` (plus the compression's
+/// extension), and record it in the build's `artifacts.json` in `out_dir`
+/// along with the uncompressed sha256, so it can be verified after
+/// decompressing.
+fn save_rootfs(
+    rootfs_ext2: &Path,
+    rootfs_out: &Path,
+    compression: RootfsCompression,
+    out_dir: &Path,
+) -> anyhow::Result<()> {
+    if let Some(parent) = rootfs_out.parent() {
+        fs_err::create_dir_all(parent)?;
+    }
+
+    let saved = match compression.compressor() {
+        None => {
+            fs_err::copy(rootfs_ext2, rootfs_out)?;
+            rootfs_out.to_path_buf()
+        }
+        Some((compressor, extension)) => {
+            let mut saved = rootfs_out.as_os_str().to_owned();
+            saved.push(format!(".{}", extension));
+            let saved = PathBuf::from(saved);
+            let status = Command::new(compressor)
+                .arg("-c")
+                .arg(rootfs_ext2)
+                .stdout(fs_err::File::create(&saved)?.into_parts().0)
+                .status()
+                .with_context(|| format!("failed to run {}", compressor))?;
+            if !status.success() {
+                anyhow::bail!("{} {}", compressor, describe_exit_status(status));
+            }
+            saved
+        }
+    };
+    log::info!("Saved modified rootfs to {}", saved.display());
+
+    let manifest_path = out_dir.join("artifacts.json");
+    let mut manifest: serde_json::Value = if manifest_path.exists() {
+        serde_json::from_str(&fs_err::read_to_string(&manifest_path)?)?
+    } else {
+        serde_json::json!({})
+    };
+    manifest["rootfs"] = serde_json::json!({
+        "path": saved,
+        "compression": compression.compressor().map(|(compressor, _)| compressor),
+        "sha256": sha256_file(rootfs_ext2)?,
+        "compressed_sha256": match compression {
+            RootfsCompression::None => None,
+            _ => Some(sha256_file(&saved)?),
+        },
+    });
+    fs_err::write(&manifest_path, serde_json::to_string_pretty(&manifest)?)?;
+    Ok(())
+}
+
+/// Per-test results (`true` if passed) reported by `tmk_vmm` in the run log
+/// at `log_path`, from its `test passed` / `test failed` lines.
+fn parse_test_results(log_path: &Path) -> anyhow::Result<BTreeMap<String, bool>> {
+    let log = fs_err::read_to_string(log_path)?;
+    let mut results = BTreeMap::new();
+    for line in log.lines() {
+        let passed = if line.contains("test passed") {
+            true
+        } else if line.contains("test failed") {
+            false
+        } else {
+            continue;
+        };
+        let Some((_, rest)) = line.split_once("name=") else {
+            continue;
+        };
+        let name = rest
+            .trim_start_matches('"')
+            .split(|c: char| c == '"' || c.is_whitespace())
+            .next()
+            .unwrap_or_default();
+        if !name.is_empty() {
+            results.insert(name.to_string(), passed);
+        }
+    }
+    Ok(results)
+}
+
+/// A copy of `cmd`, so it can be spawned again.
+fn clone_command(cmd: &Command) -> Command {
+    let mut clone = Command::new(cmd.get_program());
+    clone.args(cmd.get_args());
+    for (key, value) in cmd.get_envs() {
+        match value {
+            Some(value) => clone.env(key, value),
+            None => clone.env_remove(key),
+        };
+    }
+    if let Some(cwd) = cmd.get_current_dir() {
+        clone.current_dir(cwd);
+    }
+    clone
+}
+
+/// Run `cmd` `repeat` times, restoring `rootfs_ext2` to its injected state
+/// before each run, and report how often each TMK test failed.
+fn run_repeated(
+    cmd: &Command,
+    repeat: u32,
+    rootfs_ext2: &Path,
+    log_dir: &Path,
+    timeout: Option<Duration>,
+) -> anyhow::Result<()> {
+    let pristine_rootfs = rootfs_ext2.with_extension("ext2.pristine");
+    fs_err::copy(rootfs_ext2, &pristine_rootfs)?;
+
+    let mut failed_runs = 0;
+    // Test name -> (failures, runs)
+    let mut test_failures: BTreeMap<String, (u32, u32)> = BTreeMap::new();
+    for iteration in 1..=repeat {
+        if iteration > 1 {
+            fs_err::copy(&pristine_rootfs, rootfs_ext2)?;
+        }
+        log::info!("Run {}/{}...", iteration, repeat);
+
+        let log_path = log_dir.join(format!("shrinkwrap-run-{}.log", iteration));
+        let status = run_logged(clone_command(cmd), &log_path, timeout)
+            .with_context(|| format!("run {}/{} failed", iteration, repeat))?;
+        if !status.success() {
+            log::warn!(
+                "Run {}/{} failed: shrinkwrap run {}",
+                iteration,
+                repeat,
+                describe_exit_status(status)
+            );
+            failed_runs += 1;
+        }
+        for (name, passed) in parse_test_results(&log_path)? {
+            let (failures, runs) = test_failures.entry(name).or_default();
+            *runs += 1;
+            if !passed {
+                *failures += 1;
+            }
+        }
+    }
+    fs_err::remove_file(&pristine_rootfs)?;
+
+    println!("Flakiness summary ({} runs):", repeat);
+    println!("  shrinkwrap run failed {}/{} times", failed_runs, repeat);
+    for (name, (failures, runs)) in &test_failures {
+        if *failures > 0 {
+            println!("  test {} failed {}/{} times", name, failures, runs);
+        }
+    }
+    let flaky_tests = test_failures
+        .values()
+        .filter(|(failures, _)| *failures > 0)
+        .count();
+    if flaky_tests == 0 {
+        println!("  all {} tests passed every run", test_failures.len());
+    }
+
+    if failed_runs > 0 || flaky_tests > 0 {
+        anyhow::bail!(
+            "{} of {} runs failed, {} tests failed at least once (logs in {})",
+            failed_runs,
+            repeat,
+            flaky_tests,
+            log_dir.display()
+        );
+    }
+    Ok(())
+}
+
+/// Boot `cmd` to [`BOOT_MARKER`] `iterations` times, restoring `rootfs_ext2`
+/// to its injected state before each boot, and report min/median/max boot
+/// times. Also writes them to `metrics_file`, if given. A failed boot is
+/// passed to `report_failure` with its console log.
+fn bench_boot(
+    cmd: &Command,
+    iterations: u32,
+    rootfs_ext2: &Path,
+    metrics_file: Option<&Path>,
+    build_id: Option<&str>,
+    out_dir: &Path,
+    report_failure: impl Fn(&Path, anyhow::Error) -> anyhow::Error,
+) -> anyhow::Result<()> {
+    let pristine_rootfs = rootfs_ext2.with_extension("ext2.pristine");
+    fs_err::copy(rootfs_ext2, &pristine_rootfs)?;
+
+    let mut boot_times = Vec::new();
+    for iteration in 1..=iterations {
+        if iteration > 1 {
+            fs_err::copy(&pristine_rootfs, rootfs_ext2)?;
+        }
+        log::info!("Boot {}/{}...", iteration, iterations);
+        let log_path = out_dir
+            .join("logs")
+            .join(format!("shrinkwrap-boot-{}.log", iteration));
+        let boot_time = run_until_boot_marker(
+            clone_command(cmd),
+            BOOT_MARKER,
+            SMOKE_TEST_TIMEOUT,
+            &log_path,
+        )
+        .map_err(|e| report_failure(&log_path, e))
+        .with_context(|| format!("boot {}/{} failed", iteration, iterations))?;
+        log::info!(
+            "Boot {}/{}: {:.1}s",
+            iteration,
+            iterations,
+            boot_time.as_secs_f64()
+        );
+        boot_times.push(boot_time.as_secs_f64());
+    }
+    fs_err::remove_file(&pristine_rootfs)?;
+
+    let mut sorted = boot_times.clone();
+    sorted.sort_by(f64::total_cmp);
+    let mid = sorted.len() / 2;
+    let median = if sorted.len() % 2 == 0 {
+        (sorted[mid - 1] + sorted[mid]) / 2.0
+    } else {
+        sorted[mid]
+    };
+    let (min, max) = (sorted[0], sorted[sorted.len() - 1]);
+
+    println!(
+        "Boot-time benchmark ({} boots to '{}'):",
+        iterations, BOOT_MARKER
+    );
+    println!("  min:    {:.2}s", min);
+    println!("  median: {:.2}s", median);
+    println!("  max:    {:.2}s", max);
+
+    if let Some(metrics_file) = metrics_file {
+        if let Some(parent) = metrics_file.parent() {
+            fs_err::create_dir_all(parent)?;
+        }
+        let metrics = serde_json::json!({
+            "build_id": build_id,
+            "boot_marker": BOOT_MARKER,
+            "iterations": iterations,
+            "boot_time_secs": {
+                "min": min,
+                "median": median,
+                "max": max,
+                "samples": boot_times,
+            },
+        });
+        fs_err::write(metrics_file, serde_json::to_string_pretty(&metrics)?)?;
+        log::info!("Wrote boot-time metrics to {}", metrics_file.display());
+    }
+    Ok(())
+}
+
+/// Rtvar holding extra arguments for the OHCL kernel command line.
+const OHCL_CMDLINE_EXTRA: &str = "OHCL_CMDLINE_EXTRA";
+
+/// Add or remove `nokaslr` in the `OHCL_CMDLINE_EXTRA` rtvar of `rtvars`,
+/// adding the rtvar if it is needed and missing.
+fn apply_kaslr(rtvars: &mut Vec<String>, kaslr_enabled: bool) {
+    let prefix = format!("{}=", OHCL_CMDLINE_EXTRA);
+    let existing = rtvars.iter().position(|rtvar| rtvar.starts_with(&prefix));
+    let cmdline = existing.map_or("", |i| &rtvars[i][prefix.len()..]);
+
+    let mut args: Vec<&str> = cmdline
+        .split_whitespace()
+        .filter(|arg| *arg != "nokaslr" && *arg != "kaslr")
+        .collect();
+    if !kaslr_enabled {
+        args.push("nokaslr");
+    }
+    let rtvar = format!("{}{}", prefix, args.join(" "));
+
+    match existing {
+        Some(i) => rtvars[i] = rtvar,
+        None if args.is_empty() => {}
+        None => rtvars.push(rtvar),
+    }
+}
+
+/// Append `args` to the `OHCL_CMDLINE_EXTRA` rtvar of `rtvars`, adding the
+/// rtvar if it is missing.
+fn append_kernel_cmdline(rtvars: &mut Vec<String>, args: &str) {
+    let prefix = format!("{}=", OHCL_CMDLINE_EXTRA);
+    match rtvars.iter_mut().find(|rtvar| rtvar.starts_with(&prefix)) {
+        Some(rtvar) if rtvar.len() > prefix.len() => {
+            rtvar.push(' ');
+            rtvar.push_str(args);
+        }
+        Some(rtvar) => rtvar.push_str(args),
+        None => rtvars.push(format!("{}{}", prefix, args)),
+    }
+}
+
 impl SimpleFlowNode for Node {
     type Request = Params;
 
-    fn imports(_ctx: &mut ImportCtx<'_>) {}
+    fn imports(ctx: &mut ImportCtx<'_>) {
+        ctx.import::<crate::_jobs::local_rmm_build::Node>();
+    }
 
     fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Result<()> {
+        request.validate()?;
+
         let Params {
             out_dir,
             shrinkwrap_dir,
             platform_yaml,
             rootfs_path,
+            rootfs_download,
+            rootfs_variants,
+            tmk_cargo_workspace,
+            tmk_binaries,
+            tmk_vmm_target,
+            inject_dir,
+            extra_injections,
             rtvars,
+            extra_args,
+            smoke_test,
+            use_debugfs,
+            container_runtime,
+            timeout,
+            realm_vcpu_count,
+            realm_vcpu_affinity,
+            guest_cores,
+            etc_hosts_entries,
+            ninep_shares,
+            kaslr_enabled,
+            kernel_cmdline,
+            balloon_initial_mb,
+            balloon_max_mb,
+            vmbus_config,
+            rmm_source,
+            required_tfa_version,
+            rootfs_out,
+            rootfs_compression,
+            repeat,
+            strict_toolchain_check,
+            bench_boot: bench_boot_iterations,
+            metrics_file,
+            shrinkwrap_state_dir,
+            result_file,
+            repro_dir,
+            pipeline_timeout_sec,
+            git_credential_helper,
+            max_clone_retries,
+            verify_signatures,
+            gpg_keyring,
+            dry_run,
             done,
         } = request;
 
-        ctx.emit_rust_step("modify rootfs.ext2", |ctx| {
-            done.claim(ctx);
-            move |_rt| {
-                // Compute paths the same way as install job
-                // Get the parent directory (toolchain_dir) where everything is built
-                let toolchain_dir = shrinkwrap_dir.parent()
+        let kernel_cmdline = kernel_cmdline.map(|cmdline| cmdline.trim().to_string());
+
+        let rmm_binary = match rmm_source {
+            Some(rmm) => {
+                let toolchain_dir = shrinkwrap_dir
+                    .parent()
                     .ok_or_else(|| anyhow::anyhow!("shrinkwrap_dir has no parent"))?;
+                Some(ctx.reqv(|v| crate::_jobs::local_rmm_build::Params {
+                    rmm_dir: toolchain_dir.join("rmm"),
+                    repo_url: rmm.repo_url,
+                    branch: rmm.branch,
+                    build_script: rmm.build_script,
+                    output_binary: rmm.output_binary,
+                    toolchain_dir: toolchain_dir.to_path_buf(),
+                    git_credential_helper,
+                    max_clone_retries,
+                    verify_signatures,
+                    gpg_keyring,
+                    dry_run: dry_run.clone(),
+                    rmm_binary: v,
+                }))
+            }
+            None => None,
+        };
 
-                let tmk_kernel_dir = toolchain_dir.join("OpenVMM-TMK");
-                let host_kernel_dir = toolchain_dir.join("OHCL-Linux-Kernel");
+        ctx.emit_rust_step("modify rootfs.ext2", |ctx| {
+            done.claim(ctx);
+            let rmm_binary = rmm_binary.claim(ctx);
+            let repro_dir = repro_dir.claim(ctx);
+            let dry_run = dry_run.claim(ctx);
+            move |rt| {
+                let dry_run = rt.read(dry_run);
+                let rmm_binary = rt.read(rmm_binary);
+                let repro_dir = rt.read(repro_dir);
+                let started = Instant::now();
+                let mut timings = StageTimings::new("run", out_dir.join("logs"));
 
-                let simple_tmk = tmk_kernel_dir.join("target/aarch64-minimal_rt-none/debug/simple_tmk");
-                let tmk_vmm = tmk_kernel_dir.join("target/aarch64-unknown-linux-gnu/debug/tmk_vmm");
-                let kernel_image_path = host_kernel_dir.join("arch/arm64/boot/Image");
+                // The build node only leaves a result behind if the build
+                // failed, which is already recorded
+                if let Some(result_file) = &result_file
+                    && !dry_run
+                    && result_file.exists()
+                {
+                    log::warn!(
+                        "Build failed (see {}), skipping shrinkwrap run",
+                        result_file.display()
+                    );
+                    return Ok(());
+                }
 
-                // Modify rootfs.ext2 to inject TMK binaries and kernel
-                log::info!("Starting rootfs.ext2 modification...");
+                // Inject into and run with one rootfs. Variants get their own
+                // logs and repro script, suffixed with their name.
+                let mut run_variant = |variant: Option<&str>, rootfs_ext2: &Path| -> anyhow::Result<()> {
+                    let suffix = variant.map_or(String::new(), |name| format!("-{}", name));
 
-                // Use the rootfs path provided by the user command
-                let rootfs_ext2 = rootfs_path;
+                    // Start from a fresh copy of the downloaded base rootfs, keeping
+                    // the download itself pristine so it can be reused
+                    if let (Some(download), true) = (&rootfs_download, dry_run) {
+                        log::info!("DRY-RUN: download {} and copy it to {}", download.url, rootfs_ext2.display());
+                    } else if let Some(download) = &rootfs_download {
+                        let file_name = rootfs_ext2.file_name()
+                            .ok_or_else(|| anyhow::anyhow!("Invalid rootfs path"))?;
+                        let downloaded = out_dir.join("downloads").join(file_name);
+                        if download.sha256.is_none() {
+                            log::warn!("No sha256 given for the rootfs download, not verifying it");
+                        }
+                        let t0 = Instant::now();
+                        download_file(&download.url, &downloaded, download.sha256.as_deref())?;
+                        if let Some(parent) = rootfs_ext2.parent() {
+                            fs_err::create_dir_all(parent)?;
+                        }
+                        fs_err::copy(&downloaded, rootfs_ext2)?;
+                        log::info!("Copied downloaded rootfs to {}", rootfs_ext2.display());
+                        timings.record(&format!("rootfs download{}", suffix), t0);
+                    }
 
-                if !rootfs_ext2.exists() {
-                    anyhow::bail!("rootfs.ext2 not found at {}", rootfs_ext2.display());
-                }
+                    if !rootfs_ext2.exists() && (!dry_run || rootfs_download.is_none()) {
+                        anyhow::bail!("rootfs.ext2 not found at {}", rootfs_ext2.display());
+                    }
 
-                log::info!("Found rootfs.ext2 at {}", rootfs_ext2.display());
-
-                // Get the directory containing rootfs.ext2 for docker mounting
-                let rootfs_dir = rootfs_ext2.parent()
-                    .ok_or_else(|| anyhow::anyhow!("rootfs.ext2 has no parent directory"))?;
-                let rootfs_filename = rootfs_ext2.file_name()
-                    .ok_or_else(|| anyhow::anyhow!("Invalid rootfs path"))?
-                    .to_string_lossy();
-
-                // Step 1: Run e2fsck to check filesystem
-                log::info!("Running e2fsck on rootfs.ext2...");
-                let e2fsck_status = Command::new("docker")
-                    .args(&["run", "--rm", "-v"])
-                    .arg(format!("{}:{}", rootfs_dir.display(), rootfs_dir.display()))
-                    .args(&["-w", &rootfs_dir.to_string_lossy()])
-                    .args(&["ubuntu:24.04", "bash", "-lc"])
-                    .arg(format!("apt-get update && apt-get install -y e2fsprogs && e2fsck -fp {}", rootfs_filename))
-                    .status();
-
-                match e2fsck_status {
-                    Ok(status) if status.success() => log::info!("e2fsck completed successfully"),
-                    Ok(status) => log::warn!("e2fsck exited with status: {}", status),
-                    Err(e) => anyhow::bail!("Failed to run e2fsck: {}", e),
-                }
+                    let injected = if smoke_test {
+                        log::info!("Smoke test: skipping TMK injection into rootfs.ext2");
+                        if !etc_hosts_entries.is_empty() {
+                            log::warn!("Smoke test: not injecting /etc/hosts entries");
+                        }
+                        if !ninep_shares.is_empty() {
+                            log::warn!("Smoke test: not injecting /etc/fstab entries for 9p shares");
+                        }
+                        BTreeMap::new()
+                    } else if dry_run {
+                        log::info!("DRY-RUN: inject the TMK binaries into {}", rootfs_ext2.display());
+                        BTreeMap::new()
+                    } else {
+                        check_toolchain_compat(
+                            &shrinkwrap_dir,
+                            tmk_cargo_workspace.as_deref(),
+                            &tmk_binaries,
+                            tmk_vmm_target,
+                            strict_toolchain_check,
+                        )?;
+                        let t0 = Instant::now();
+                        let injected = modify_rootfs(
+                            &shrinkwrap_dir,
+                            tmk_cargo_workspace.as_deref(),
+                            &tmk_binaries,
+                            tmk_vmm_target,
+                            &inject_dir,
+                            &extra_injections,
+                            rootfs_ext2,
+                            &etc_hosts_entries,
+                            &ninep_shares,
+                            use_debugfs,
+                            container_runtime.as_deref(),
+                        )?;
+                        timings.record(&format!("inject rootfs{}", suffix), t0);
+                        injected
+                    };
 
-                // Step 2: Resize the filesystem
-                log::info!("Resizing rootfs.ext2 to 1024M...");
-                let resize_status = Command::new("docker")
-                    .args(&["run", "--rm", "-v"])
-                    .arg(format!("{}:{}", rootfs_dir.display(), rootfs_dir.display()))
-                    .args(&["-w", &rootfs_dir.to_string_lossy()])
-                    .args(&["ubuntu:24.04", "bash", "-lc"])
-                    .arg(format!("apt-get update && apt-get install -y e2fsprogs && e2fsck -fp {} && resize2fs {} 1024M", rootfs_filename, rootfs_filename))
-                    .status();
-
-                match resize_status {
-                    Ok(status) if status.success() => log::info!("resize2fs completed successfully"),
-                    Ok(status) => log::warn!("resize2fs exited with status: {}", status),
-                    Err(e) => anyhow::bail!("Failed to run resize2fs: {}", e),
-                }
+                    if let (Some(rootfs_out), true) = (&rootfs_out, dry_run) {
+                        log::info!("DRY-RUN: save {} to {}", rootfs_ext2.display(), rootfs_out.display());
+                    } else if let Some(rootfs_out) = &rootfs_out {
+                        let t0 = Instant::now();
+                        save_rootfs(rootfs_ext2, rootfs_out, rootfs_compression, &out_dir)?;
+                        timings.record("save rootfs", t0);
+                    }
 
-                // Step 3: Mount rootfs, inject files, and unmount
-                log::info!("Mounting rootfs.ext2 and injecting TMK binaries...");
-
-                // Use paths from parameters
-                log::info!("Using simple_tmk from: {}", simple_tmk.display());
-                log::info!("Using tmk_vmm from: {}", tmk_vmm.display());
-                log::info!("Using kernel Image from: {}", kernel_image_path.display());
-
-                // Same directory as rootfs.ext2
-                let guest_disk = rootfs_dir.join("guest-disk.img");
-                let kvmtool_efi = rootfs_dir.join("KVMTOOL_EFI.fd");
-                let lkvm = rootfs_dir.join("lkvm");
-
-                // Copy kernel to Image_ohcl
-                let image_ohcl = rootfs_dir.join("Image_ohcl");
-                if kernel_image_path.exists() {
-                    fs::copy(&kernel_image_path, &image_ohcl)
-                        .map_err(|e| anyhow::anyhow!("Failed to copy kernel Image: {}", e))?;
-                    log::info!("Copied kernel to Image_ohcl");
-                } else {
-                    log::warn!("Kernel image not found at {}", kernel_image_path.display());
-                }
+                    // Step 3: Run shrinkwrap with the modified rootfs
+                    log::info!("Running shrinkwrap with platform YAML: {}", platform_yaml.display());
 
-                // Build the mount/inject script
-                let mount_script = format!(
-                    r#"
-                    set -e
-                    mkdir -p mnt
-                    mount {rootfs_filename} mnt
-                    mkdir -p mnt/cca
-                    {simple_tmk_copy}
-                    {tmk_vmm_copy}
-                    {guest_disk_copy}
-                    {kvmtool_efi_copy}
-                    {image_ohcl_copy}
-                    {lkvm_copy}
-                    sync
-                    umount mnt || umount -l mnt || true
-                    sync
-                    sleep 1
-                    # Try multiple times to remove the directory
-                    for i in 1 2 3 4 5; do
-                        if [ -d mnt ]; then
-                            rmdir mnt 2>/dev/null && break || sleep 0.5
-                        else
-                            break
-                        fi
-                    done
-                    # If still exists, force remove
-                    [ -d mnt ] && rm -rf mnt || true
-                    "#,
-                    rootfs_filename = rootfs_filename,
-                    simple_tmk_copy = if simple_tmk.exists() {
-                        format!("cp {} mnt/cca/", simple_tmk.display())
-                    } else {
-                        format!("echo 'Warning: {} not found'", simple_tmk.display())
-                    },
-                    tmk_vmm_copy = if tmk_vmm.exists() {
-                        format!("cp {} mnt/cca/", tmk_vmm.display())
-                    } else {
-                        format!("echo 'Warning: {} not found'", tmk_vmm.display())
-                    },
-                    guest_disk_copy = if guest_disk.exists() {
-                        format!("cp {} mnt/cca/", guest_disk.display())
-                    } else {
-                        "".to_string()
-                    },
-                    kvmtool_efi_copy = if kvmtool_efi.exists() {
-                        format!("cp {} mnt/cca/", kvmtool_efi.display())
+                    // Get the canonical path to rootfs.ext2 (which a dry run may
+                    // not have downloaded)
+                    let rootfs_canonical = if dry_run {
+                        std::path::absolute(rootfs_ext2)?
                     } else {
-                        "".to_string()
-                    },
-                    image_ohcl_copy = if image_ohcl.exists() {
-                        format!("cp {} mnt/cca/", image_ohcl.display())
-                    } else {
-                        "".to_string()
-                    },
-                    lkvm_copy = if lkvm.exists() {
-                        format!("cp {} mnt/cca/", lkvm.display())
+                        fs::canonicalize(rootfs_ext2)
+                            .map_err(|e| anyhow::anyhow!("Failed to canonicalize rootfs path: {}", e))?
+                    };
+
+                    // Prepare shrinkwrap command
+                    let shrinkwrap_exe = shrinkwrap_dir.join("shrinkwrap").join("shrinkwrap");
+                    let venv_dir = shrinkwrap_dir.join("venv");
+
+                    if !shrinkwrap_exe.exists() {
+                        anyhow::bail!("shrinkwrap executable not found at {}", shrinkwrap_exe.display());
+                    }
+
+                    // Determine the platform YAML path to use
+                    // If platform_yaml is absolute, try to make it relative to out_dir
+                    // Otherwise, shrinkwrap will look for artifacts relative to the YAML location
+                    let platform_yaml_to_use = if platform_yaml.is_absolute() {
+                        // Try to use just the filename - shrinkwrap should have copied/processed it
+                        platform_yaml.file_name()
+                            .map(|name| PathBuf::from(name))
+                            .unwrap_or_else(|| platform_yaml.clone())
                     } else {
-                        "".to_string()
-                    },
-                );
+                        platform_yaml.clone()
+                    };
+
+                    log::info!("Using platform YAML: {} (relative to {})",
+                        platform_yaml_to_use.display(),
+                        out_dir.display());
+
+                    // Collect the rtvars to run with
+                    let mut run_rtvars = Vec::new();
 
-                let mount_status = Command::new("sudo")
-                    .arg("bash")
-                    .arg("-c")
-                    .arg(&mount_script)
-                    .current_dir(rootfs_dir)
-                    .status();
+                    // Add the ROOTFS rtvar pointing to the modified rootfs.ext2
+                    run_rtvars.push(format!("ROOTFS={}", rootfs_canonical.display()));
 
-                match mount_status {
-                    Ok(status) if status.success() => {
-                        log::info!("rootfs.ext2 updated successfully with TMK binaries");
+                    // Add any additional rtvars from parameters
+                    let mut rtvars = rtvars.clone();
+                    if let Some(cmdline) = &kernel_cmdline {
+                        log::info!("Kernel command line: appending '{}'", cmdline);
+                        append_kernel_cmdline(&mut rtvars, cmdline);
                     }
-                    Ok(status) => {
-                        anyhow::bail!("Failed to mount/inject files: exit status {}", status);
+                    if let Some(kaslr_enabled) = kaslr_enabled {
+                        if kaslr_enabled {
+                            log::info!("KASLR: enabled");
+                        } else {
+                            log::warn!("KASLR: disabled (nokaslr). Do not use for production CCA attestation tests");
+                        }
+                        apply_kaslr(&mut rtvars, kaslr_enabled);
                     }
-                    Err(e) => {
-                        anyhow::bail!("Failed to execute mount script: {}", e);
+                    run_rtvars.extend(rtvars);
+
+                    // Use the RMM built from source, if configured
+                    if let Some(rmm_binary) = &rmm_binary {
+                        log::info!("Using RMM built from source: {}", rmm_binary.display());
+                        run_rtvars.push(format!("RMM={}", rmm_binary.display()));
                     }
-                }
 
-                // Step 4: Run shrinkwrap with the modified rootfs
-                log::info!("Running shrinkwrap with platform YAML: {}", platform_yaml.display());
+                    // Add the guest core count, if configured
+                    match guest_cores {
+                        Some(n) => {
+                            log::info!("Guest cores: {}", n);
+                            run_rtvars.push(format!("GUEST_CORES={}", n));
+                        }
+                        None => log::info!("Guest cores: platform default"),
+                    }
 
-                // Get the canonical path to rootfs.ext2
-                let rootfs_canonical = fs::canonicalize(&rootfs_ext2)
-                    .map_err(|e| anyhow::anyhow!("Failed to canonicalize rootfs path: {}", e))?;
+                    // Add the virtio-balloon config, if configured
+                    if let Some(mb) = balloon_initial_mb {
+                        run_rtvars.push(format!("BALLOON_INITIAL={}", mb));
+                    }
+                    if let Some(mb) = balloon_max_mb {
+                        run_rtvars.push(format!("BALLOON_MAX={}", mb));
+                    }
+                    let check_balloon = balloon_initial_mb.is_some() || balloon_max_mb.is_some();
 
-                // Prepare shrinkwrap command
-                let shrinkwrap_exe = shrinkwrap_dir.join("shrinkwrap").join("shrinkwrap");
-                let venv_dir = shrinkwrap_dir.join("venv");
+                    // Add the VMBus settings, if configured
+                    if let Some(vmbus) = &vmbus_config {
+                        log::info!(
+                            "VMBus: enabled={}, channels={}, mshv_vtl={}",
+                            vmbus.enable_vmbus,
+                            vmbus.vmbus_channels,
+                            vmbus.mshv_vtl
+                        );
+                        run_rtvars.push(format!("VMBUS_ENABLED={}", u8::from(vmbus.enable_vmbus)));
+                        run_rtvars.push(format!("VMBUS_CHANNELS={}", vmbus.vmbus_channels));
+                        run_rtvars.push(format!("MSHV_VTL={}", u8::from(vmbus.mshv_vtl)));
+                    }
 
-                if !shrinkwrap_exe.exists() {
-                    anyhow::bail!("shrinkwrap executable not found at {}", shrinkwrap_exe.display());
-                }
+                    // Point each 9p share's mount tag at its host dir
+                    for share in &ninep_shares {
+                        log::info!(
+                            "9p share '{}': {} -> {}{}",
+                            share.mount_tag,
+                            share.host_path.display(),
+                            share.mount_point.display(),
+                            if share.readonly { " (read-only)" } else { "" }
+                        );
+                        run_rtvars.push(format!("NINEP_{}_HOST_PATH={}", share.mount_tag, share.host_path.display()));
+                    }
+
+                    // Add the Realm VCPU topology, if configured
+                    if let Some(n) = realm_vcpu_count {
+                        run_rtvars.push(format!("REALM_VCPUS={}", n));
+                    }
+                    if let Some(affinity) = &realm_vcpu_affinity {
+                        let affinity = affinity
+                            .iter()
+                            .map(|cpu| cpu.to_string())
+                            .collect::<Vec<_>>()
+                            .join(",");
+                        run_rtvars.push(format!("REALM_VCPU_AFFINITY={}", affinity));
+                    }
+                    if realm_vcpu_count.is_some() || realm_vcpu_affinity.is_some() {
+                        log::info!(
+                            "Realm VCPU topology: {} VCPUs, affinity {}",
+                            realm_vcpu_count.map_or("platform default".to_string(), |n| n.to_string()),
+                            realm_vcpu_affinity.as_ref().map_or("unpinned".to_string(), |a| format!("{:?}", a)),
+                        );
+                    }
+
+                    let run_args = build_run_args(&platform_yaml_to_use, &run_rtvars, &extra_args);
+                    log::info!("Running: {} {}",
+                        shrinkwrap_exe.display(),
+                        run_args.iter().map(|arg| arg.to_string_lossy()).collect::<Vec<_>>().join(" "));
+
+                    // Set environment to use venv Python
+                    log::info!("Setting VIRTUAL_ENV={}", venv_dir.display());
+
+                    let mut cmd = shrinkwrap_command(&shrinkwrap_dir, &out_dir);
+                    cmd.args(&run_args);
+                    if let Some(state_dir) = &shrinkwrap_state_dir {
+                        set_shrinkwrap_state_dir(&mut cmd, state_dir);
+                    }
+
+                    let build_id = read_build_id(&out_dir)?;
+
+                    if let Some(repro_dir) = &repro_dir {
+                        let script = write_repro_script(repro_dir, &format!("shrinkwrap-run{}", suffix), &cmd)?;
+                        log::info!("Wrote repro script to {}", script.display());
+                    }
+
+                    if dry_run {
+                        log_dry_run(&cmd);
+                        return Ok(());
+                    }
+
+                    let stage = format!("shrinkwrap run{}", suffix);
+                    let t0 = Instant::now();
+                    if smoke_test {
+                        log::info!("Smoke test: waiting up to {}s for boot marker '{}'",
+                            SMOKE_TEST_TIMEOUT.as_secs(),
+                            BOOT_MARKER);
+                        let run_log = out_dir.join("logs").join(format!("shrinkwrap-run{}.log", suffix));
+                        let boot_time = run_until_boot_marker(cmd, BOOT_MARKER, SMOKE_TEST_TIMEOUT, &run_log);
+                        timings.record(&stage, t0);
+                        let boot_time = boot_time
+                            .map_err(|e| report_boot_failure(&out_dir, &shrinkwrap_dir, &run_log, &injected, e))
+                            .context("smoke test failed")?;
+                        log::info!("Smoke test passed: guest booted in {:.1}s", boot_time.as_secs_f64());
+                        if let Some(build_id) = &build_id {
+                            log::info!("Build ID: {}", build_id);
+                        }
+                        return Ok(());
+                    }
+
+                    if let Some(iterations) = bench_boot_iterations {
+                        let result = bench_boot(
+                            &cmd,
+                            iterations,
+                            rootfs_ext2,
+                            metrics_file.as_deref(),
+                            build_id.as_deref(),
+                            &out_dir,
+                            |run_log, e| report_boot_failure(&out_dir, &shrinkwrap_dir, run_log, &injected, e),
+                        );
+                        timings.record(&stage, t0);
+                        result?;
+                        if let Some(build_id) = &build_id {
+                            log::info!("Build ID: {}", build_id);
+                        }
+                        return Ok(());
+                    }
+
+                    if repeat > 1 {
+                        let result = run_repeated(&cmd, repeat, rootfs_ext2, &out_dir.join("logs"), timeout);
+                        timings.record(&stage, t0);
+                        result?;
+                        if let Some(build_id) = &build_id {
+                            log::info!("Build ID: {}", build_id);
+                        }
+                        return Ok(());
+                    }
+
+                    // The console output is needed to verify the balloon came up,
+                    // and to diagnose boot failures
+                    let run_log = out_dir.join("logs").join(format!("shrinkwrap-run{}.log", suffix));
+                    let shrinkwrap_run_status = run_logged(cmd, &run_log, timeout);
+                    timings.record(&stage, t0);
 
-                // Determine the platform YAML path to use
-                // If platform_yaml is absolute, try to make it relative to out_dir
-                // Otherwise, shrinkwrap will look for artifacts relative to the YAML location
-                let platform_yaml_to_use = if platform_yaml.is_absolute() {
-                    // Try to use just the filename - shrinkwrap should have copied/processed it
-                    platform_yaml.file_name()
-                        .map(|name| PathBuf::from(name))
-                        .unwrap_or_else(|| platform_yaml.clone())
-                } else {
-                    platform_yaml.clone()
+                    match shrinkwrap_run_status {
+                        Ok(status) if status.success() => {
+                            log::info!("Shrinkwrap run completed successfully");
+                            if let Some(build_id) = &build_id {
+                                log::info!("Build ID: {}", build_id);
+                            }
+                            if check_balloon {
+                                check_balloon_initialized(&run_log)?;
+                            }
+                        }
+                        Ok(status) => {
+                            let error = anyhow::anyhow!("Shrinkwrap run failed: {}", describe_exit_status(status));
+                            let booted = fs_err::read_to_string(&run_log)
+                                .is_ok_and(|log| log.contains(BOOT_MARKER));
+                            if !booted {
+                                return Err(report_boot_failure(&out_dir, &shrinkwrap_dir, &run_log, &injected, error));
+                            }
+                            return Err(error);
+                        }
+                        Err(e) => {
+                            anyhow::bail!(
+                                "shrinkwrap run of {} failed: {:#} (see {})",
+                                platform_yaml_to_use.display(),
+                                e,
+                                run_log.display()
+                            );
+                        }
+                    }
+
+                    Ok(())
                 };
 
-                log::info!("Using platform YAML: {} (relative to {})",
-                    platform_yaml_to_use.display(),
-                    out_dir.display());
+                let result = (|| -> anyhow::Result<()> {
+                    enter_pipeline_phase(pipeline_timeout_sec, PipelinePhase::Run)?;
 
-                // Build the rtvar arguments
-                let mut rtvar_args = Vec::new();
+                    // A mismatched EL3 firmware can break the Realm's security
+                    // guarantees without any visible failure
+                    if let Some(required) = &required_tfa_version {
+                        let bl1 = shrinkwrap_package_dir(shrinkwrap_state_dir.as_deref(), &platform_yaml)?.join("bl1.bin");
+                        if dry_run {
+                            log::info!("DRY-RUN: check that {} is TF-A {}", bl1.display(), required);
+                        } else {
+                            let version = tfa_image_version(&bl1)?
+                                .ok_or_else(|| anyhow::anyhow!("no TF-A version string found in {}", bl1.display()))?;
+                            if !tfa_version_matches(&version, required) {
+                                anyhow::bail!("{} is TF-A {}, but {} is required", bl1.display(), version, required);
+                            }
+                            log::info!("{} is TF-A {}", bl1.display(), version);
+                        }
+                    }
 
-                // Add the ROOTFS rtvar pointing to the modified rootfs.ext2
-                rtvar_args.push("--rtvar".to_string());
-                rtvar_args.push(format!("ROOTFS={}", rootfs_canonical.display()));
+                    if rootfs_variants.is_empty() {
+                        return run_variant(None, &rootfs_path);
+                    }
 
-                // Add any additional rtvars from parameters
-                for rtvar in rtvars {
-                    rtvar_args.push("--rtvar".to_string());
-                    rtvar_args.push(rtvar);
-                }
+                    let mut results: Vec<(String, anyhow::Result<()>)> = Vec::new();
+                    for (name, rootfs) in &rootfs_variants {
+                        log::info!("Running rootfs variant '{}' ({})", name, rootfs.display());
+                        results.push((name.clone(), run_variant(Some(name), rootfs)));
+                    }
 
-                log::info!("Running: {} run {} {}",
-                    shrinkwrap_exe.display(),
-                    platform_yaml_to_use.display(),
-                    rtvar_args.join(" "));
-
-                // Set environment to use venv Python
-                let venv_bin = venv_dir.join("bin");
-
-                log::info!("Setting VIRTUAL_ENV={}", venv_dir.display());
-
-                let shrinkwrap_run_status = Command::new(&shrinkwrap_exe)
-                    .arg("run")
-                    .arg(&platform_yaml_to_use)
-                    .args(&rtvar_args)
-                    .env("VIRTUAL_ENV", &venv_dir)
-                    .env("PATH", format!("{}:{}",
-                        venv_bin.display(),
-                        std::env::var("PATH").unwrap_or_default()
-                    ))
-                    .current_dir(&out_dir)  // Run from out_dir where build artifacts are
-                    .status();
-
-                match shrinkwrap_run_status {
-                    Ok(status) if status.success() => {
-                        log::info!("Shrinkwrap run completed successfully");
+                    let mut failures = Vec::new();
+                    for (name, result) in &results {
+                        match result {
+                            Ok(()) => log::info!("rootfs variant '{}' passed", name),
+                            Err(e) => {
+                                log::error!("rootfs variant '{}' failed: {:#}", name, e);
+                                failures.push(name.as_str());
+                            }
+                        }
                     }
-                    Ok(status) => {
-                        anyhow::bail!("Shrinkwrap run failed with exit status: {}", status);
+                    if !failures.is_empty() {
+                        anyhow::bail!(
+                            "{} of {} rootfs variant(s) failed: {}",
+                            failures.len(),
+                            results.len(),
+                            failures.join(", ")
+                        );
                     }
-                    Err(e) => {
-                        anyhow::bail!("Failed to execute shrinkwrap run: {}", e);
+                    Ok(())
+                })();
+
+                // Report the failure in the result file instead of failing the job
+                if let Some(result_file) = &result_file {
+                    if let Err(e) = &result {
+                        log::error!("Shrinkwrap run failed: {:#}", e);
                     }
+                    write_run_result(result_file, &result, started.elapsed(), read_build_id(&out_dir).ok().flatten().as_deref())?;
+                    return Ok(());
                 }
-
-                Ok(())
+                result
             }
         });
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn superblock_rejects_invalid_block_size() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let image = dir.path().join("rootfs.ext2");
+        let mut data = vec![0u8; 1024 + 0x15c];
+        data[1024 + 0x38..1024 + 0x3a].copy_from_slice(&0xEF53u16.to_le_bytes());
+        data[1024 + 0x4..1024 + 0x8].copy_from_slice(&4096u32.to_le_bytes());
+        data[1024 + 0x18..1024 + 0x1c].copy_from_slice(&2u32.to_le_bytes());
+        fs_err::write(&image, &data).unwrap();
+        let size = Ext2Size::read_superblock(&image).unwrap().unwrap();
+        assert_eq!(size.block_size, 4096);
+        assert_eq!(size.total_mb(), 16);
+
+        data[1024 + 0x18..1024 + 0x1c].copy_from_slice(&64u32.to_le_bytes());
+        fs_err::write(&image, &data).unwrap();
+        let err = Ext2Size::read_superblock(&image).err().unwrap();
+        assert!(
+            err.to_string().contains("invalid ext2 block size"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn run_args_order() {
+        let args = build_run_args(
+            Path::new("cca-3world.yaml"),
+            &["ROOTFS=/tmp/rootfs.ext2".into(), "GUEST_CORES=2".into()],
+            &["--dry-run".into(), "--rtvar".into(), "GUEST_CORES=4".into()],
+        );
+        assert_eq!(
+            args,
+            [
+                "run",
+                "cca-3world.yaml",
+                "--rtvar",
+                "ROOTFS=/tmp/rootfs.ext2",
+                "--rtvar",
+                "GUEST_CORES=2",
+                // --run-arg values come last, so they override the rtvars
+                "--dry-run",
+                "--rtvar",
+                "GUEST_CORES=4",
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_logged_kills_hung_shrinkwrap() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // A fake shrinkwrap clone, whose `shrinkwrap run` starts an FVP
+        // (found on the venv's PATH) that never exits
+        let dir = tempfile::TempDir::new().unwrap();
+        let shrinkwrap_dir = dir.path().join("shrinkwrap");
+        let scripts = [
+            (
+                shrinkwrap_dir.join("shrinkwrap").join("shrinkwrap"),
+                "#!/bin/sh\necho \"shrinkwrap $*\"\nexec fake-fvp\n",
+            ),
+            (
+                shrinkwrap_dir.join("venv").join("bin").join("fake-fvp"),
+                "#!/bin/sh\necho booting\nwhile true; do sleep 1; done\n",
+            ),
+        ];
+        for (path, script) in scripts {
+            fs_err::create_dir_all(path.parent().unwrap()).unwrap();
+            fs_err::write(&path, script).unwrap();
+            fs_err::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let mut cmd = shrinkwrap_command(&shrinkwrap_dir, dir.path());
+        cmd.args(["run", "cca-3world.yaml"]);
+        let log_path = dir.path().join("logs").join("shrinkwrap-run.log");
+
+        let start = Instant::now();
+        let err = run_logged(cmd, &log_path, Some(Duration::from_secs(1))).unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(err.to_string().contains("timed out after 1s"), "{}", err);
+        // Everything printed before the kill made it into the log
+        let log = fs_err::read_to_string(&log_path).unwrap();
+        assert!(log.contains("shrinkwrap run cca-3world.yaml\n"), "{}", log);
+        assert!(log.contains("booting\n"), "{}", log);
+    }
+}
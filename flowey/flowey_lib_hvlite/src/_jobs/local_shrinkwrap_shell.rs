@@ -0,0 +1,84 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Drop into an interactive shell set up for working with shrinkwrap and the
+//! OHCL kernel.
+
+use crate::_jobs::shrinkwrap_util::KERNEL_ARCH;
+use crate::_jobs::shrinkwrap_util::arm_gnu_cross_compile_prefix;
+use crate::_jobs::shrinkwrap_util::describe_exit_status;
+use crate::_jobs::shrinkwrap_util::installed_arm_gnu_toolchain_dir;
+use flowey::node::prelude::*;
+use std::process::Command;
+
+flowey_request! {
+    pub struct Params {
+        /// Directory where the shrinkwrap repo is cloned (e.g. <out_dir>/shrinkwrap)
+        pub shrinkwrap_dir: PathBuf,
+        pub done: WriteVar<SideEffect>,
+    }
+}
+
+new_simple_flow_node!(struct Node);
+
+impl SimpleFlowNode for Node {
+    type Request = Params;
+
+    fn imports(_ctx: &mut ImportCtx<'_>) {}
+
+    fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Result<()> {
+        let Params {
+            shrinkwrap_dir,
+            done,
+        } = request;
+
+        if !matches!(ctx.backend(), FlowBackend::Local) {
+            log::warn!("An interactive shell is only available on the local backend, skipping it");
+            ctx.emit_side_effect_step([], [done]);
+            return Ok(());
+        }
+
+        ctx.emit_rust_step("interactive shrinkwrap shell", |ctx| {
+            done.claim(ctx);
+            move |_rt| {
+                let toolchain_dir = shrinkwrap_dir
+                    .parent()
+                    .ok_or_else(|| anyhow::anyhow!("shrinkwrap_dir has no parent"))?;
+                let venv_dir = shrinkwrap_dir.join("venv");
+                let cross_compile =
+                    arm_gnu_cross_compile_prefix(&installed_arm_gnu_toolchain_dir(toolchain_dir));
+                let path = format!(
+                    "{}:{}:{}",
+                    venv_dir.join("bin").display(),
+                    shrinkwrap_dir.join("shrinkwrap").display(),
+                    std::env::var("PATH").unwrap_or_default()
+                );
+                let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".into());
+
+                log::info!(
+                    "Starting {} in {} (exit the shell to continue)",
+                    shell,
+                    toolchain_dir.display()
+                );
+                log::info!("  VIRTUAL_ENV={}", venv_dir.display());
+                log::info!("  ARCH={}", KERNEL_ARCH);
+                log::info!("  CROSS_COMPILE={}", cross_compile.display());
+
+                let status = Command::new(&shell)
+                    .current_dir(toolchain_dir)
+                    .env("VIRTUAL_ENV", &venv_dir)
+                    .env("PATH", path)
+                    .env("ARCH", KERNEL_ARCH)
+                    .env("CROSS_COMPILE", &cross_compile)
+                    .status()
+                    .with_context(|| format!("failed to start {}", shell))?;
+                // The status is that of the last command run in the shell,
+                // which isn't a failure of the pipeline
+                log::info!("Shell {}", describe_exit_status(status));
+                Ok(())
+            }
+        });
+
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Check that every artifact the CCA FVP run node injects into the rootfs
+//! was built, so a missing one is reported up front rather than part-way
+//! through the run.
+
+use crate::_jobs::shrinkwrap_util::is_arm64_kernel_image;
+use crate::_jobs::shrinkwrap_util::is_elf;
+use flowey::node::prelude::*;
+
+flowey_request! {
+    pub struct Params {
+        /// Host kernel `Image` injected into the rootfs
+        pub kernel_image: PathBuf,
+        /// ELF binaries injected into the rootfs, by name
+        pub elf_binaries: Vec<(String, PathBuf)>,
+        /// Nothing is built in a dry run, so there is nothing to check
+        pub dry_run: ReadVar<bool>,
+        pub done: WriteVar<SideEffect>,
+    }
+}
+
+new_simple_flow_node!(struct Node);
+
+impl SimpleFlowNode for Node {
+    type Request = Params;
+
+    fn imports(_ctx: &mut ImportCtx<'_>) {}
+
+    fn process_request(request: Self::Request, ctx: &mut NodeCtx<'_>) -> anyhow::Result<()> {
+        let Params {
+            kernel_image,
+            elf_binaries,
+            dry_run,
+            done,
+        } = request;
+
+        ctx.emit_rust_step("verify cca artifacts", |ctx| {
+            done.claim(ctx);
+            let dry_run = dry_run.claim(ctx);
+            move |rt| {
+                if rt.read(dry_run) {
+                    log::info!("DRY-RUN: skipping artifact verification");
+                    return Ok(());
+                }
+
+                let mut problems = Vec::new();
+                match is_arm64_kernel_image(&kernel_image) {
+                    Ok(true) => log::info!("Kernel Image present at {}", kernel_image.display()),
+                    Ok(false) => problems.push(format!("kernel Image at {} does not have an arm64 Image header", kernel_image.display())),
+                    Err(_) => problems.push(format!("kernel Image is missing at {}", kernel_image.display())),
+                }
+                for (name, path) in &elf_binaries {
+                    match is_elf(path) {
+                        Ok(true) => log::info!("{} present at {}", name, path.display()),
+                        Ok(false) => problems.push(format!("{} at {} is not an ELF binary", name, path.display())),
+                        Err(_) => problems.push(format!("{} is missing at {}", name, path.display())),
+                    }
+                }
+
+                if !problems.is_empty() {
+                    anyhow::bail!(
+                        "{} CCA artifact(s) missing or invalid, re-run the install step to build them:\n  {}",
+                        problems.len(),
+                        problems.join("\n  ")
+                    );
+                }
+
+                log::info!("All CCA artifacts are present");
+                Ok(())
+            }
+        });
+
+        Ok(())
+    }
+}
@@ -26,6 +26,17 @@
 pub mod local_restore_packages;
 pub mod publish_vmgstool_gh_release;
 pub mod test_local_flowey_build_igvm;
+pub mod local_check_shrinkwrap_dir;
+pub mod local_clean_cca_dir;
+pub mod local_cleanup_cca_build;
+pub mod local_generate_cca_report;
 pub mod local_install_shrinkwrap;
+pub mod local_list_shrinkwrap_overlays;
+pub mod local_rmm_build;
 pub mod local_shrinkwrap_build;
+pub mod local_shrinkwrap_matrix_report;
+pub mod local_shrinkwrap_package;
 pub mod local_shrinkwrap_run;
+pub mod local_shrinkwrap_shell;
+pub mod local_verify_cca_artifacts;
+pub mod shrinkwrap_util;
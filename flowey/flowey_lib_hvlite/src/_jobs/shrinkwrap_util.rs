@@ -0,0 +1,1430 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Helpers shared by the local CCA FVP (shrinkwrap) job nodes.
+
+use anyhow::Context;
+use flowey::shell::FloweyCmd;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use serde::Serialize;
+use sha2::Digest;
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Child;
+use std::process::Command;
+use std::process::ExitStatus;
+use std::process::Output;
+use std::process::Stdio;
+use std::sync::Once;
+use std::sync::OnceLock;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// OHCL host kernel clone, relative to the pipeline working dir.
+pub const HOST_KERNEL_DIR: &str = "OHCL-Linux-Kernel";
+/// OpenVMM TMK clone, relative to the pipeline working dir.
+pub const TMK_DIR: &str = "OpenVMM-TMK";
+/// cca_config clone, relative to the pipeline working dir.
+pub const CCA_CONFIG_DIR: &str = "cca_config";
+/// ARM GNU toolchain release installed unless another one is requested.
+pub const DEFAULT_ARM_GNU_TOOLCHAIN_VERSION: &str = "14.3.rel1";
+/// Python packages shrinkwrap needs installed in its venv.
+pub const SHRINKWRAP_PYTHON_PACKAGES: &[&str] = &["pyyaml", "termcolor", "tuxmake"];
+
+/// Kernel `ARCH=` used when building the OHCL host kernel.
+pub const KERNEL_ARCH: &str = "arm64";
+/// `make` target that produces the OHCL host kernel image.
+pub const KERNEL_IMAGE_TARGET: &str = "Image";
+
+/// Where the kernel's `compile_commands.json` is exported to, relative to
+/// the pipeline working dir.
+pub const KERNEL_COMPILE_COMMANDS: &str = "kernel-compile_commands.json";
+
+/// Cargo config used to build `simple_tmk`, relative to the TMK workspace.
+pub const SIMPLE_TMK_CARGO_CONFIG: &str = "openhcl/minimal_rt/aarch64-config.toml";
+
+/// Rust target `simple_tmk` is built for (via [`SIMPLE_TMK_CARGO_CONFIG`]).
+pub const SIMPLE_TMK_TARGET: &str = "aarch64-minimal_rt-none";
+/// Rust target `tmk_vmm` is built for. It runs as a userspace process on the
+/// host kernel, so it must target the same architecture and Linux.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TmkVmmTarget {
+    /// `aarch64-unknown-linux-gnu`, linked against the rootfs's glibc
+    #[default]
+    GnuLinux,
+    /// `aarch64-unknown-linux-musl`, statically linked so it doesn't depend
+    /// on the libc of the rootfs
+    MuslLinux,
+}
+
+impl TmkVmmTarget {
+    pub fn triple(self) -> &'static str {
+        match self {
+            TmkVmmTarget::GnuLinux => "aarch64-unknown-linux-gnu",
+            TmkVmmTarget::MuslLinux => "aarch64-unknown-linux-musl",
+        }
+    }
+
+    /// Cargo args building `tmk_vmm` for this target. The musl target links
+    /// with the `musl-cross` toolchain's gcc.
+    pub fn cargo_args(self) -> Vec<String> {
+        let mut args = vec!["--target".to_string(), self.triple().to_string()];
+        if self == TmkVmmTarget::MuslLinux {
+            args.push("--config".into());
+            args.push(format!(
+                "target.{}.linker=\"aarch64-linux-musl-gcc\"",
+                self.triple()
+            ));
+        }
+        args
+    }
+}
+
+/// Cross gcc of the ARM GNU toolchain, relative to its `bin` dir.
+const ARM_GNU_TOOLCHAIN_GCC: &str = "aarch64-none-elf-gcc";
+
+/// Name of the ARM GNU toolchain `version` archive for this build host, which
+/// is also the top-level directory it extracts to.
+pub fn arm_gnu_toolchain_name(version: &str) -> String {
+    let host = match std::env::consts::ARCH {
+        "aarch64" => "aarch64",
+        _ => "x86_64",
+    };
+    format!("arm-gnu-toolchain-{}-{}-aarch64-none-elf", version, host)
+}
+
+/// Download URL of the ARM GNU toolchain `version` archive for this build
+/// host.
+pub fn arm_gnu_toolchain_url(version: &str) -> String {
+    format!(
+        "https://developer.arm.com/-/media/Files/downloads/gnu/{}/binrel/{}.tar.xz",
+        version,
+        arm_gnu_toolchain_name(version)
+    )
+}
+
+/// Dir of the ARM GNU toolchain the install node extracted under
+/// `toolchain_dir`, as recorded in its [`ToolchainManifest`]. Falls back to
+/// where the default version extracts to if none is recorded.
+pub fn installed_arm_gnu_toolchain_dir(toolchain_dir: &Path) -> PathBuf {
+    let toolchains = ToolchainManifest::read(toolchain_dir)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    toolchains.arm_gnu_toolchain_dir.unwrap_or_else(|| {
+        arm_gnu_toolchain_dir(
+            toolchain_dir,
+            toolchains
+                .arm_gnu_toolchain_version
+                .as_deref()
+                .unwrap_or(DEFAULT_ARM_GNU_TOOLCHAIN_VERSION),
+        )
+    })
+}
+
+/// Whether `dir` is an extracted ARM GNU toolchain (i.e: has
+/// `bin/aarch64-none-elf-gcc`).
+pub fn is_arm_gnu_toolchain(dir: &Path) -> bool {
+    dir.join("bin").join(ARM_GNU_TOOLCHAIN_GCC).is_file()
+}
+
+/// Dirs directly under `dir` that are extracted ARM GNU toolchains.
+pub fn find_arm_gnu_toolchains(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut toolchains = Vec::new();
+    for entry in fs_err::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() && is_arm_gnu_toolchain(&path) {
+            toolchains.push(path);
+        }
+    }
+    toolchains.sort();
+    Ok(toolchains)
+}
+
+/// Dir the ARM GNU toolchain `version` archive is expected to extract to
+/// under `toolchain_dir` ([`arm_gnu_toolchain_name`]).
+pub fn arm_gnu_toolchain_dir(toolchain_dir: &Path, version: &str) -> PathBuf {
+    toolchain_dir.join(arm_gnu_toolchain_name(version))
+}
+
+/// `CROSS_COMPILE=` prefix of the ARM GNU toolchain extracted to
+/// `extracted_dir`.
+pub fn arm_gnu_cross_compile_prefix(extracted_dir: &Path) -> PathBuf {
+    extracted_dir.join("bin").join("aarch64-none-elf-")
+}
+
+/// Path of the kernel image produced by `make ARCH=<arch> <target>`.
+///
+/// `build_dir` is the kernel source tree for in-tree builds, or the `O=`
+/// directory for out-of-tree builds. Both the install and run nodes must go
+/// through this function so they agree on where the image lives.
+pub fn kernel_image_path(build_dir: &Path, arch: &str, target: &str) -> PathBuf {
+    build_dir.join("arch").join(arch).join("boot").join(target)
+}
+
+/// TMK packages built by the install node and injected by the run node.
+pub const TMK_BINARIES: &[&str] = &["simple_tmk", "tmk_vmm"];
+
+/// Check that every entry of `selected` names a known TMK binary.
+pub fn validate_tmk_binaries(selected: &[String]) -> anyhow::Result<()> {
+    for name in selected {
+        if !TMK_BINARIES.contains(&name.as_str()) {
+            anyhow::bail!(
+                "unknown TMK binary '{}', expected one of: {}",
+                name,
+                TMK_BINARIES.join(", ")
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Fail with every problem found in the `Params` of `node`, one per line, so
+/// they can all be fixed in one go.
+pub fn report_invalid_params(node: &str, problems: Vec<String>) -> anyhow::Result<()> {
+    match problems.as_slice() {
+        [] => Ok(()),
+        [problem] => anyhow::bail!("invalid {} parameters: {}", node, problem),
+        _ => anyhow::bail!("invalid {} parameters:\n  {}", node, problems.join("\n  ")),
+    }
+}
+
+/// Record a problem if `dir` can't be created or written to, by creating a
+/// temporary file in its closest existing ancestor. Permission bits alone
+/// don't tell, e.g. for root, ACLs or read-only mounts.
+pub fn check_dir_writable(dir: &Path, problems: &mut Vec<String>) {
+    let Some(existing) = dir.ancestors().find(|ancestor| ancestor.exists()) else {
+        return;
+    };
+    if !existing.is_dir() {
+        problems.push(format!("{} is not a directory", existing.display()));
+    } else if let Err(e) = tempfile::tempfile_in(existing) {
+        problems.push(format!(
+            "{} is not writable, so {} can't be created: {}",
+            existing.display(),
+            dir.display(),
+            e
+        ));
+    }
+}
+
+/// Returns true if TMK binary `name` is part of the `selected` subset. An
+/// empty selection means all of them.
+pub fn tmk_binary_selected(selected: &[String], name: &str) -> bool {
+    selected.is_empty() || selected.iter().any(|s| s == name)
+}
+
+/// Cargo target dir TMK `package` is built into in the TMK cargo workspace.
+/// Each package has its own, so their builds don't serialize on the lock of
+/// a shared one (they're for different targets, so there's nothing to share).
+pub fn tmk_target_dir(tmk_workspace: &Path, package: &str) -> PathBuf {
+    tmk_workspace.join("target").join(package)
+}
+
+/// Path of the `simple_tmk` binary built in the TMK cargo workspace.
+pub fn simple_tmk_path(tmk_workspace: &Path) -> PathBuf {
+    tmk_target_dir(tmk_workspace, "simple_tmk")
+        .join(SIMPLE_TMK_TARGET)
+        .join("debug")
+        .join("simple_tmk")
+}
+
+/// Path of the `tmk_vmm` binary built for `target` in the TMK cargo
+/// workspace.
+pub fn tmk_vmm_path(tmk_workspace: &Path, target: TmkVmmTarget) -> PathBuf {
+    tmk_target_dir(tmk_workspace, "tmk_vmm")
+        .join(target.triple())
+        .join("debug")
+        .join("tmk_vmm")
+}
+
+/// Read up to `N` leading bytes of `path`, returning `None` if the file is
+/// shorter than that.
+fn read_header<const N: usize>(path: &Path) -> anyhow::Result<Option<[u8; N]>> {
+    let mut header = [0; N];
+    let mut file = fs_err::File::open(path)?;
+    match file.read_exact(&mut header) {
+        Ok(()) => Ok(Some(header)),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// ELF `e_machine` of AArch64.
+const EM_AARCH64: u16 = 0xB7;
+
+/// Returns true if `path` is an ELF file built for AArch64.
+pub fn is_aarch64_elf(path: &Path) -> anyhow::Result<bool> {
+    let Some(header) = read_header::<20>(path)? else {
+        return Ok(false);
+    };
+    // e_machine is always little-endian for the AArch64 binaries we build.
+    Ok(header[..4] == *b"\x7fELF" && u16::from_le_bytes([header[18], header[19]]) == EM_AARCH64)
+}
+
+/// Fail unless `path` is an ELF file built for AArch64, naming the machine it
+/// was built for otherwise (e.g. a host binary built by mistake).
+pub fn assert_aarch64_elf(path: &Path) -> anyhow::Result<()> {
+    let header = read_header::<20>(path)?
+        .filter(|header| header[..4] == *b"\x7fELF")
+        .ok_or_else(|| anyhow::anyhow!("{} is not an ELF binary", path.display()))?;
+    let machine = match u16::from_le_bytes([header[18], header[19]]) {
+        EM_AARCH64 => return Ok(()),
+        0x03 => "x86".to_string(),
+        0x28 => "32-bit ARM".to_string(),
+        0x3E => "x86-64".to_string(),
+        0xF3 => "RISC-V".to_string(),
+        machine => format!("machine {:#x}", machine),
+    };
+    anyhow::bail!(
+        "{} is an ELF binary for {}, not AArch64; check that it was cross-compiled",
+        path.display(),
+        machine
+    )
+}
+
+/// Returns true if `path` starts with the ELF magic bytes.
+pub fn is_elf(path: &Path) -> anyhow::Result<bool> {
+    Ok(read_header::<4>(path)?.is_some_and(|magic| magic == *b"\x7fELF"))
+}
+
+/// Returns true if `path` has a valid arm64 Linux kernel `Image` header.
+pub fn is_arm64_kernel_image(path: &Path) -> anyhow::Result<bool> {
+    // See Documentation/arch/arm64/booting.rst: the magic lives at offset 0x38.
+    let Some(header) = read_header::<64>(path)? else {
+        return Ok(false);
+    };
+    Ok(header[0x38..0x3c] == *b"ARM\x64")
+}
+
+/// Shrinkwrap's `kind` (`build` or `package`) dir:
+/// `${SHRINKWRAP_<KIND>:-$HOME/.shrinkwrap/<kind>}`, or `<state_dir>/<kind>`
+/// (see [`set_shrinkwrap_state_dir`]).
+pub fn shrinkwrap_output_dir(state_dir: Option<&Path>, kind: &str) -> anyhow::Result<PathBuf> {
+    let var = format!("SHRINKWRAP_{}", kind.to_uppercase());
+    Ok(match (state_dir, std::env::var_os(&var)) {
+        (Some(state_dir), _) => state_dir.join(kind),
+        (None, Some(dir)) => PathBuf::from(dir),
+        (None, None) => PathBuf::from(std::env::var_os("HOME").ok_or_else(|| {
+            anyhow::anyhow!("Either {} or HOME environment variable must be set", var)
+        })?)
+        .join(".shrinkwrap")
+        .join(kind),
+    })
+}
+
+/// Directory shrinkwrap packages the artifacts of `platform_yaml` into:
+/// `<package dir>/<platform>` (see [`shrinkwrap_output_dir`]).
+pub fn shrinkwrap_package_dir(
+    state_dir: Option<&Path>,
+    platform_yaml: &Path,
+) -> anyhow::Result<PathBuf> {
+    Ok(shrinkwrap_output_dir(state_dir, "package")?.join(platform_name(platform_yaml)?))
+}
+
+/// Directory shrinkwrap builds the components of `platform_yaml` in:
+/// `<build dir>/build/<platform>` (see [`shrinkwrap_output_dir`]). Their
+/// sources are checked out next to it, under `<build dir>/source`.
+pub fn shrinkwrap_build_dir(
+    state_dir: Option<&Path>,
+    platform_yaml: &Path,
+) -> anyhow::Result<PathBuf> {
+    Ok(shrinkwrap_output_dir(state_dir, "build")?
+        .join("build")
+        .join(platform_name(platform_yaml)?))
+}
+
+/// Name shrinkwrap knows the platform config `platform_yaml` by.
+fn platform_name(platform_yaml: &Path) -> anyhow::Result<&std::ffi::OsStr> {
+    platform_yaml
+        .file_stem()
+        .ok_or_else(|| anyhow::anyhow!("invalid platform YAML path {}", platform_yaml.display()))
+}
+
+/// Point the shrinkwrap invocation `cmd` at build and package dirs under
+/// `state_dir`, rather than the user-wide ones under `~/.shrinkwrap`, so
+/// separate configurations of the same platform don't share build state.
+pub fn set_shrinkwrap_state_dir(cmd: &mut Command, state_dir: &Path) {
+    cmd.env("SHRINKWRAP_BUILD", state_dir.join("build"));
+    cmd.env("SHRINKWRAP_PACKAGE", state_dir.join("package"));
+}
+
+/// Returns the lowercase hex SHA-256 digest of the file at `path`.
+pub fn sha256_file(path: &Path) -> anyhow::Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut fs_err::File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// TF-A version of the TF-A image (e.g. `bl1.bin`) at `path`, from the
+/// `v<major>.<minor>[.<patch>](<build type>)` version string its boot banner
+/// prints, such as `v2.10.0(release)`. `None` if there isn't one.
+pub fn tfa_image_version(path: &Path) -> anyhow::Result<Option<String>> {
+    Ok(find_tfa_version(&fs_err::read(path)?))
+}
+
+fn find_tfa_version(image: &[u8]) -> Option<String> {
+    image
+        .iter()
+        .enumerate()
+        .filter(|(_, b)| **b == b'v')
+        .find_map(|(i, _)| {
+            let rest = &image[i + 1..];
+            let len = rest
+                .iter()
+                .take_while(|b| b.is_ascii_digit() || **b == b'.')
+                .count();
+            let version = std::str::from_utf8(&rest[..len]).ok()?;
+            (is_tfa_version(version) && version.contains('.') && rest.get(len) == Some(&b'('))
+                .then(|| version.to_string())
+        })
+}
+
+/// Returns true if `version` is a dotted TF-A version (e.g. `2.10` or
+/// `v2.10.0`).
+pub fn is_tfa_version(version: &str) -> bool {
+    version
+        .strip_prefix('v')
+        .unwrap_or(version)
+        .split('.')
+        .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Returns true if TF-A `version` starts with the components of `required`,
+/// so that a required `2.10` matches `2.10.0` but not `2.1` or `2.100`.
+pub fn tfa_version_matches(version: &str, required: &str) -> bool {
+    let version: Vec<&str> = version
+        .strip_prefix('v')
+        .unwrap_or(version)
+        .split('.')
+        .collect();
+    let required: Vec<&str> = required
+        .strip_prefix('v')
+        .unwrap_or(required)
+        .split('.')
+        .collect();
+    version.starts_with(&required)
+}
+
+/// Check that the file at `path` has the SHA-256 digest `expected`.
+pub fn verify_sha256(path: &Path, expected: &str) -> anyhow::Result<()> {
+    let actual = sha256_file(path)?;
+    if !actual.eq_ignore_ascii_case(expected) {
+        anyhow::bail!(
+            "{} has sha256 {}, expected {}",
+            path.display(),
+            actual,
+            expected
+        );
+    }
+    Ok(())
+}
+
+/// Process groups started by [`spawn_in_process_group`] that haven't been
+/// reaped yet, so the pipeline watchdog can tear them down.
+static PROCESS_GROUPS: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+/// Set by the pipeline watchdog once the pipeline timeout has passed.
+static PIPELINE_TIMED_OUT: AtomicBool = AtomicBool::new(false);
+
+/// Spawn `cmd` in a new process group, so [`kill_process_group`] (or the
+/// pipeline watchdog) can tear down everything it spawns. The child must be
+/// reaped with [`kill_process_group`] or [`wait_with_timeout`].
+///
+/// Fails without spawning anything once the pipeline has timed out.
+pub fn spawn_in_process_group(cmd: &mut Command) -> std::io::Result<Child> {
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(cmd, 0);
+    // Held across the spawn, so the watchdog either sees the new group or
+    // the spawn sees the timeout
+    let mut groups = PROCESS_GROUPS.lock();
+    if PIPELINE_TIMED_OUT.load(Ordering::SeqCst) {
+        return Err(std::io::Error::other("pipeline timed out"));
+    }
+    let child = cmd.spawn()?;
+    groups.push(child.id());
+    Ok(child)
+}
+
+/// Spawn `cmd`, which may prompt on the terminal (e.g. sudo or git asking
+/// for a password). A background process group can't read the terminal, so
+/// it's only moved into its own group when the pipeline watchdog is armed
+/// and needs to be able to tear it down.
+fn spawn_interactive(cmd: &mut Command) -> std::io::Result<Child> {
+    if PIPELINE_WATCHDOG.is_completed() {
+        spawn_in_process_group(cmd)
+    } else {
+        cmd.spawn()
+    }
+}
+
+/// Run `cmd` to completion (see [`spawn_interactive`]) and collect its
+/// output, whether or not it succeeded.
+pub fn interactive_output(cmd: FloweyCmd<'_>) -> anyhow::Result<Output> {
+    let mut cmd = cmd.into_command();
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+    let child = spawn_interactive(&mut cmd)?;
+    let pgid = child.id();
+    let output = child.wait_with_output()?;
+    PROCESS_GROUPS.lock().retain(|id| *id != pgid);
+    check_pipeline_timeout()?;
+    Ok(output)
+}
+
+/// Fail if the pipeline watchdog has cancelled the pipeline.
+fn check_pipeline_timeout() -> anyhow::Result<()> {
+    if PIPELINE_TIMED_OUT.load(Ordering::SeqCst) {
+        let phase = PipelinePhase::ALL[CURRENT_PHASE.load(Ordering::SeqCst)];
+        anyhow::bail!("pipeline timed out during the {} phase", phase.name());
+    }
+    Ok(())
+}
+
+/// Send `signal` (e.g. `-KILL`) to every process in the process group led by
+/// `pgid`.
+fn signal_process_group(pgid: u32, signal: &str) {
+    #[cfg(unix)]
+    {
+        let _ = Command::new("kill")
+            .arg(signal)
+            .arg("--")
+            .arg(format!("-{}", pgid))
+            .status();
+    }
+    #[cfg(not(unix))]
+    let _ = (pgid, signal);
+}
+
+/// Stop tracking the process group of `child`, once it has been reaped.
+fn forget_process_group(child: &Child) {
+    PROCESS_GROUPS.lock().retain(|pgid| *pgid != child.id());
+}
+
+/// Tear down `child` along with everything it spawned (i.e: the FVP model).
+pub fn kill_process_group(child: &mut Child) {
+    signal_process_group(child.id(), "-KILL");
+    let _ = child.kill();
+    let _ = child.wait();
+    forget_process_group(child);
+}
+
+/// Wait for `child` to exit. If it's still running after `timeout`, tear it
+/// down along with its process group (see [`spawn_in_process_group`]) and
+/// fail. Also fails if the pipeline watchdog tore it down.
+pub fn wait_with_timeout(
+    child: &mut Child,
+    timeout: Option<Duration>,
+) -> anyhow::Result<ExitStatus> {
+    let Some(timeout) = timeout else {
+        let status = child.wait()?;
+        forget_process_group(child);
+        check_pipeline_timeout()?;
+        return Ok(status);
+    };
+
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            forget_process_group(child);
+            check_pipeline_timeout()?;
+            return Ok(status);
+        }
+        if start.elapsed() >= timeout {
+            kill_process_group(child);
+            anyhow::bail!("timed out after {}s", start.elapsed().as_secs());
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Describe how a process exited, telling a kill by a signal (e.g. the OOM
+/// killer's SIGKILL, or the pipeline watchdog's SIGTERM) apart from a
+/// normal exit with a failure code.
+pub fn describe_exit_status(status: ExitStatus) -> String {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return match signal_name(signal) {
+                Some(name) => format!("killed by signal {} ({})", signal, name),
+                None => format!("killed by signal {}", signal),
+            };
+        }
+    }
+    match status.code() {
+        Some(code) => format!("exited with code {}", code),
+        None => format!("exited with {}", status),
+    }
+}
+
+/// Name of the common signals that are numbered the same on every Unix.
+#[cfg(unix)]
+fn signal_name(signal: i32) -> Option<&'static str> {
+    Some(match signal {
+        1 => "SIGHUP",
+        2 => "SIGINT",
+        3 => "SIGQUIT",
+        4 => "SIGILL",
+        6 => "SIGABRT",
+        8 => "SIGFPE",
+        9 => "SIGKILL",
+        11 => "SIGSEGV",
+        13 => "SIGPIPE",
+        14 => "SIGALRM",
+        15 => "SIGTERM",
+        _ => return None,
+    })
+}
+
+/// Download `url` to `dest`, resuming a partial download left over from an
+/// interrupted attempt, and verify it against `sha256` if given.
+///
+/// An existing `dest` is kept if it matches `sha256` (or if there is no
+/// checksum to check it against), and downloaded again otherwise.
+pub fn download_file(url: &str, dest: &Path, sha256: Option<&str>) -> anyhow::Result<()> {
+    if dest.exists() {
+        match sha256 {
+            None => {
+                log::info!("{} already downloaded", dest.display());
+                return Ok(());
+            }
+            Some(expected) => match verify_sha256(dest, expected) {
+                Ok(()) => {
+                    log::info!("{} already downloaded (sha256 verified)", dest.display());
+                    return Ok(());
+                }
+                Err(e) => {
+                    log::warn!("{:#}, downloading it again", e);
+                    fs_err::remove_file(dest)?;
+                }
+            },
+        }
+    }
+
+    if let Some(parent) = dest.parent() {
+        fs_err::create_dir_all(parent)?;
+    }
+    let partial = PathBuf::from(format!("{}.part", dest.display()));
+    log::info!("Downloading {} to {}", url, dest.display());
+    let mut child = spawn_in_process_group(
+        Command::new("wget")
+            .args(["--continue", "--tries=3", "-O"])
+            .arg(&partial)
+            .arg(url),
+    )
+    .context("failed to run wget")?;
+    let status = wait_with_timeout(&mut child, None)?;
+    if !status.success() {
+        anyhow::bail!(
+            "downloading {} {} (partial download kept at {} to resume)",
+            url,
+            describe_exit_status(status),
+            partial.display()
+        );
+    }
+
+    if let Some(expected) = sha256
+        && let Err(e) = verify_sha256(&partial, expected)
+    {
+        fs_err::remove_file(&partial)?;
+        return Err(e.context(format!("download of {} is corrupt", url)));
+    }
+    fs_err::rename(&partial, dest)?;
+    Ok(())
+}
+
+/// Whether `s` looks like a sha256 digest (64 hex digits).
+pub fn is_sha256_digest(s: &str) -> bool {
+    s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Quote `s` for a POSIX shell.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// `cmd` as a shell command line, prefixed with its working dir and
+/// environment overrides, for logging.
+pub fn command_line(cmd: &Command) -> String {
+    let mut line = String::new();
+    if let Some(cwd) = cmd.get_current_dir() {
+        line.push_str(&format!("cd {} && ", shell_quote(&cwd.to_string_lossy())));
+    }
+    if cmd.get_envs().next().is_some() {
+        line.push_str("env ");
+    }
+    // `env` takes its `-u` options before the assignments
+    for (key, _) in cmd.get_envs().filter(|(_, value)| value.is_none()) {
+        line.push_str(&format!("-u {} ", key.to_string_lossy()));
+    }
+    for (key, value) in cmd.get_envs() {
+        if let Some(value) = value {
+            line.push_str(&format!(
+                "{}={} ",
+                key.to_string_lossy(),
+                shell_quote(&value.to_string_lossy())
+            ));
+        }
+    }
+    let argv = std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| shell_quote(&arg.to_string_lossy()))
+        .collect::<Vec<_>>();
+    line.push_str(&argv.join(" "));
+    line
+}
+
+/// Log what a dry run would have spawned: the full command line, then its
+/// working dir, environment overrides and argument vector one by one.
+pub fn log_dry_run(cmd: &Command) {
+    log::info!("DRY-RUN: {}", command_line(cmd));
+    if let Some(cwd) = cmd.get_current_dir() {
+        log::info!("DRY-RUN:   cwd: {}", cwd.display());
+    }
+    for (key, value) in cmd.get_envs() {
+        match value {
+            Some(value) => log::info!(
+                "DRY-RUN:   env: {}={}",
+                key.to_string_lossy(),
+                value.to_string_lossy()
+            ),
+            None => log::info!("DRY-RUN:   env: unset {}", key.to_string_lossy()),
+        }
+    }
+    let argv = std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| arg.to_string_lossy())
+        .collect::<Vec<_>>();
+    log::info!("DRY-RUN:   argv: {:?}", argv);
+}
+
+/// Run `cmd`, or with `dry_run` only log it.
+pub fn run_or_log(cmd: FloweyCmd<'_>, dry_run: bool) -> anyhow::Result<()> {
+    let line = cmd.to_string();
+    if dry_run {
+        log::info!("DRY-RUN: {}", line);
+        return Ok(());
+    }
+    eprintln!("$ {}", line);
+    let mut child = spawn_interactive(&mut cmd.into_command())
+        .with_context(|| format!("failed to spawn `{}`", line))?;
+    let status = wait_with_timeout(&mut child, None)?;
+    if !status.success() {
+        anyhow::bail!("`{}` {}", line, describe_exit_status(status));
+    }
+    Ok(())
+}
+
+/// Write a line of command output to the console and the log.
+pub fn write_line(log_file: &mut impl Write, is_stderr: bool, line: &str) -> anyhow::Result<()> {
+    if is_stderr {
+        eprintln!("{}", line);
+        writeln!(log_file, "STDERR: {}", line)?;
+    } else {
+        println!("{}", line);
+        writeln!(log_file, "{}", line)?;
+    }
+    Ok(())
+}
+
+/// Forward each line read from `reader` to `tx`, tagged with whether it came
+/// from stderr.
+pub fn forward_lines(
+    reader: impl Read + Send + 'static,
+    is_stderr: bool,
+    tx: mpsc::Sender<(bool, String)>,
+) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        for line in BufReader::new(reader).lines().map_while(Result::ok) {
+            if tx.send((is_stderr, line)).is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// Run `cmd` to completion, echoing its stdout/stderr to the console and
+/// appending them to `log_path`. Returns its exit status, whether or not it
+/// succeeded.
+pub fn tee_command_output(mut cmd: Command, log_path: &Path) -> anyhow::Result<ExitStatus> {
+    if let Some(parent) = log_path.parent() {
+        fs_err::create_dir_all(parent)?;
+    }
+    let mut log_file = std::io::BufWriter::new(
+        fs_err::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_path)?,
+    );
+
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+    let mut child = spawn_in_process_group(&mut cmd)?;
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| anyhow::anyhow!("failed to capture stdout"))?;
+    let stderr = child
+        .stderr
+        .take()
+        .ok_or_else(|| anyhow::anyhow!("failed to capture stderr"))?;
+
+    // Funnel both streams through a single channel, so lines are written
+    // in the order they were read
+    let (tx, rx) = mpsc::channel();
+    let stdout_thread = forward_lines(stdout, false, tx.clone());
+    let stderr_thread = forward_lines(stderr, true, tx);
+    for (is_stderr, line) in rx {
+        write_line(&mut log_file, is_stderr, &line)?;
+    }
+    let _ = stdout_thread.join();
+    let _ = stderr_thread.join();
+    log_file.flush()?;
+
+    wait_with_timeout(&mut child, None)
+}
+
+/// Write `<dir>/<name>.sh`, a script that re-runs `cmd` with the same working
+/// dir and environment overrides. Returns the path of the script.
+pub fn write_repro_script(dir: &Path, name: &str, cmd: &Command) -> anyhow::Result<PathBuf> {
+    let mut script = String::from("#!/usr/bin/env bash\nset -e\n");
+    if let Some(cwd) = cmd.get_current_dir() {
+        script.push_str(&format!("cd {}\n", shell_quote(&cwd.to_string_lossy())));
+    }
+    for (key, value) in cmd.get_envs() {
+        let key = key.to_string_lossy();
+        match value {
+            Some(value) => script.push_str(&format!(
+                "export {}={}\n",
+                key,
+                shell_quote(&value.to_string_lossy())
+            )),
+            None => script.push_str(&format!("unset {}\n", key)),
+        }
+    }
+    let argv = std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| shell_quote(&arg.to_string_lossy()))
+        .collect::<Vec<_>>();
+    script.push_str(&format!("exec {}\n", argv.join(" ")));
+
+    fs_err::create_dir_all(dir)?;
+    let path = dir.join(format!("{}.sh", name));
+    fs_err::write(&path, script)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs_err::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(path)
+}
+
+/// Build ID file written to the build's out dir.
+pub const BUILD_ID_FILE: &str = "build-id.json";
+/// How long to wait for another build to release the build counter lock.
+const BUILD_COUNTER_LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Removes the build counter lock file when dropped.
+struct BuildCounterLock(PathBuf);
+
+impl Drop for BuildCounterLock {
+    fn drop(&mut self) {
+        let _ = fs_err::remove_file(&self.0);
+    }
+}
+
+/// Take the lock on `counter_file` by exclusively creating
+/// `<counter_file>.lock`, waiting for any other holder to release it.
+fn lock_build_counter(counter_file: &Path) -> anyhow::Result<BuildCounterLock> {
+    let mut lock_path = counter_file.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let lock_path = PathBuf::from(lock_path);
+
+    let start = Instant::now();
+    loop {
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+        {
+            Ok(_) => return Ok(BuildCounterLock(lock_path)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                if start.elapsed() > BUILD_COUNTER_LOCK_TIMEOUT {
+                    anyhow::bail!(
+                        "timed out waiting for build counter lock {} (remove it if stale)",
+                        lock_path.display()
+                    );
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            Err(e) => {
+                return Err(anyhow::Error::new(e)
+                    .context(format!("failed to create {}", lock_path.display())));
+            }
+        }
+    }
+}
+
+/// Increment the build counter in `counter_file` (a newline-terminated
+/// integer, starting from 0 if missing), and return a build ID of the form
+/// `<hostname>-<epoch>-<counter>`.
+pub fn next_build_id(counter_file: &Path) -> anyhow::Result<String> {
+    let counter = {
+        let _lock = lock_build_counter(counter_file)?;
+        let counter = if counter_file.exists() {
+            let contents = fs_err::read_to_string(counter_file)?;
+            contents.trim().parse::<u64>().with_context(|| {
+                format!(
+                    "invalid build counter '{}' in {}",
+                    contents.trim(),
+                    counter_file.display()
+                )
+            })?
+        } else {
+            0
+        } + 1;
+        fs_err::write(counter_file, format!("{}\n", counter))?;
+        counter
+    };
+
+    let hostname = Command::new("hostname")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|hostname| !hostname.is_empty())
+        .unwrap_or_else(|| "unknown-host".into());
+    let epoch = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+    Ok(format!("{}-{}-{}", hostname, epoch, counter))
+}
+
+/// Build ID recorded in `<out_dir>/build-id.json`, if any.
+pub fn read_build_id(out_dir: &Path) -> anyhow::Result<Option<String>> {
+    let path = out_dir.join(BUILD_ID_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents: serde_json::Value = serde_json::from_str(&fs_err::read_to_string(&path)?)
+        .with_context(|| format!("failed to parse {}", path.display()))?;
+    Ok(contents["build_id"].as_str().map(str::to_string))
+}
+
+/// Toolchain manifest written to the pipeline working dir by the install node.
+pub const TOOLCHAIN_MANIFEST: &str = "toolchains.json";
+
+/// The toolchains/targets the install node built the host kernel and TMK
+/// binaries with, so the run node can check they're compatible.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ToolchainManifest {
+    /// Version of the ARM GNU toolchain the host kernel was built with
+    pub arm_gnu_toolchain_version: Option<String>,
+    /// Dir that version was extracted to, which isn't necessarily
+    /// [`arm_gnu_toolchain_dir`] if ARM renamed the archive's top-level dir
+    pub arm_gnu_toolchain_dir: Option<PathBuf>,
+    /// Kernel `ARCH=` the host kernel was built with
+    pub kernel_arch: Option<String>,
+    /// `CROSS_COMPILE=` prefix the host kernel was built with
+    pub kernel_cross_compile: Option<String>,
+    /// Rust target each TMK binary was built for, by binary name
+    pub tmk_targets: BTreeMap<String, String>,
+}
+
+impl ToolchainManifest {
+    /// Manifest in `toolchain_dir`, if the install node wrote one.
+    pub fn read(toolchain_dir: &Path) -> anyhow::Result<Option<Self>> {
+        let path = toolchain_dir.join(TOOLCHAIN_MANIFEST);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let manifest = serde_json::from_str(&fs_err::read_to_string(&path)?)
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+        Ok(Some(manifest))
+    }
+
+    /// Write the manifest to `toolchain_dir`.
+    pub fn write(&self, toolchain_dir: &Path) -> anyhow::Result<()> {
+        fs_err::write(
+            toolchain_dir.join(TOOLCHAIN_MANIFEST),
+            serde_json::to_string_pretty(self)?,
+        )?;
+        Ok(())
+    }
+
+    /// Recorded combinations that imply an ABI mismatch between the host
+    /// kernel and the TMK binaries: everything must target AArch64, and
+    /// `tmk_vmm` must target Linux.
+    pub fn compat_problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Some(arch) = &self.kernel_arch
+            && arch != KERNEL_ARCH
+        {
+            problems.push(format!(
+                "host kernel was built for ARCH={}, expected {}",
+                arch, KERNEL_ARCH
+            ));
+        }
+        if let Some(cross_compile) = &self.kernel_cross_compile {
+            let prefix = Path::new(cross_compile)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            if !prefix.starts_with("aarch64-") {
+                problems.push(format!(
+                    "host kernel was built with cross toolchain {}, which doesn't target aarch64",
+                    cross_compile
+                ));
+            }
+        }
+        for (name, target) in &self.tmk_targets {
+            if !target.starts_with("aarch64-") {
+                problems.push(format!(
+                    "{} was built for {}, which doesn't target aarch64",
+                    name, target
+                ));
+            } else if name == "tmk_vmm" && !target.contains("-linux-") {
+                problems.push(format!(
+                    "tmk_vmm was built for {}, but it runs on the Linux host kernel (expected {} or {})",
+                    target,
+                    TmkVmmTarget::GnuLinux.triple(),
+                    TmkVmmTarget::MuslLinux.triple()
+                ));
+            }
+        }
+        problems
+    }
+}
+
+/// Phases of the CCA FVP pipeline, for reporting pipeline timeouts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PipelinePhase {
+    Install,
+    Build,
+    Run,
+}
+
+impl PipelinePhase {
+    const ALL: [PipelinePhase; 3] = [
+        PipelinePhase::Install,
+        PipelinePhase::Build,
+        PipelinePhase::Run,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            PipelinePhase::Install => "install",
+            PipelinePhase::Build => "build",
+            PipelinePhase::Run => "run",
+        }
+    }
+}
+
+/// Index into [`PipelinePhase::ALL`] of the phase currently running.
+static CURRENT_PHASE: AtomicUsize = AtomicUsize::new(0);
+static PIPELINE_WATCHDOG: Once = Once::new();
+/// How long the pipeline gets to clean up (e.g. unmount the rootfs) after
+/// timing out, before it's exited regardless.
+const PIPELINE_CANCEL_GRACE: Duration = Duration::from_secs(60);
+
+/// Mark `phase` as running, and enforce the pipeline `timeout` (in seconds),
+/// if any. The timeout counts from the first phase to start in this process.
+///
+/// The first call starts a watchdog that, once the timeout passes, reports
+/// which phase was active and terminates the process groups started by
+/// [`spawn_in_process_group`]. Waiting on those then fails, so the step
+/// returns an error and runs its cleanup. If the pipeline still hasn't exited
+/// after [`PIPELINE_CANCEL_GRACE`], the watchdog exits it.
+pub fn enter_pipeline_phase(timeout: Option<u64>, phase: PipelinePhase) -> anyhow::Result<()> {
+    let Some(timeout) = timeout else {
+        return Ok(());
+    };
+
+    CURRENT_PHASE.store(
+        PipelinePhase::ALL
+            .iter()
+            .position(|p| *p == phase)
+            .unwrap_or_default(),
+        Ordering::SeqCst,
+    );
+    check_pipeline_timeout()?;
+
+    let timeout = Duration::from_secs(timeout);
+    PIPELINE_WATCHDOG.call_once(|| {
+        std::thread::spawn(move || {
+            std::thread::sleep(timeout);
+            let phase = PipelinePhase::ALL[CURRENT_PHASE.load(Ordering::SeqCst)];
+            log::error!("pipeline timed out during the {} phase", phase.name());
+            PIPELINE_TIMED_OUT.store(true, Ordering::SeqCst);
+            for pgid in PROCESS_GROUPS.lock().iter() {
+                signal_process_group(*pgid, "-TERM");
+            }
+
+            std::thread::sleep(PIPELINE_CANCEL_GRACE);
+            log::error!(
+                "pipeline didn't exit within {}s of timing out, exiting",
+                PIPELINE_CANCEL_GRACE.as_secs()
+            );
+            for pgid in PROCESS_GROUPS.lock().iter() {
+                signal_process_group(*pgid, "-KILL");
+            }
+            std::process::exit(1);
+        });
+    });
+    Ok(())
+}
+
+/// Record the outcome of a build or run that took `duration` in
+/// `result_file`, as read by the matrix report.
+pub fn write_run_result(
+    result_file: &Path,
+    result: &anyhow::Result<()>,
+    duration: Duration,
+    build_id: Option<&str>,
+) -> anyhow::Result<()> {
+    if let Some(parent) = result_file.parent() {
+        fs_err::create_dir_all(parent)?;
+    }
+    let record = serde_json::json!({
+        "passed": result.is_ok(),
+        "error": result.as_ref().err().map(|e| format!("{:#}", e)),
+        "duration_secs": duration.as_secs_f64(),
+        "build_id": build_id,
+    });
+    fs_err::write(result_file, serde_json::to_string_pretty(&record)?)?;
+    Ok(())
+}
+
+/// Lock file in the pipeline working dir, so concurrent pipeline runs don't
+/// inject into (and build over) each other's rootfs.
+pub const PIPELINE_LOCK_FILE: &str = ".cca-pipeline.lock";
+/// How long to wait for another pipeline run to release its lock.
+const PIPELINE_LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The pipeline lock, held until this process exits, at which point the OS
+/// releases it (whether the pipeline succeeded or failed).
+static PIPELINE_LOCK: OnceLock<fs_err::File> = OnceLock::new();
+
+/// Take the exclusive pipeline lock at `lock_path` for the rest of the
+/// pipeline run, recording this process's PID in it. Fails with the PID of
+/// the owning run if another run holds it for longer than
+/// [`PIPELINE_LOCK_TIMEOUT`].
+pub fn acquire_pipeline_lock(lock_path: &Path) -> anyhow::Result<()> {
+    if PIPELINE_LOCK.get().is_some() {
+        return Ok(());
+    }
+    if let Some(parent) = lock_path.parent() {
+        fs_err::create_dir_all(parent)?;
+    }
+    // Not truncated, since the PID in it belongs to the owner until locked
+    let mut file = fs_err::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(lock_path)?;
+
+    let started = Instant::now();
+    let mut waiting = false;
+    loop {
+        match file.file().try_lock() {
+            Ok(()) => break,
+            Err(std::fs::TryLockError::WouldBlock) => {}
+            Err(std::fs::TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("failed to lock {}", lock_path.display()));
+            }
+        }
+        let owner = fs_err::read_to_string(lock_path).unwrap_or_default();
+        let owner = owner.trim();
+        let owner = if owner.is_empty() { "unknown" } else { owner };
+        if started.elapsed() >= PIPELINE_LOCK_TIMEOUT {
+            anyhow::bail!(
+                "another pipeline run (PID {}) is using this working dir, gave up waiting for {} after {}s",
+                owner,
+                lock_path.display(),
+                PIPELINE_LOCK_TIMEOUT.as_secs()
+            );
+        }
+        if !waiting {
+            log::info!(
+                "Waiting for the pipeline run with PID {} to release {}",
+                owner,
+                lock_path.display()
+            );
+            waiting = true;
+        }
+        std::thread::sleep(Duration::from_millis(500));
+    }
+
+    file.set_len(0)?;
+    write!(file, "{}", std::process::id())?;
+    log::info!("Acquired pipeline lock {}", lock_path.display());
+    let _ = PIPELINE_LOCK.set(file);
+    Ok(())
+}
+
+/// Wall-clock time of one stage of a step.
+#[derive(Serialize)]
+struct StageTiming {
+    name: String,
+    secs: f64,
+}
+
+/// Wall-clock time spent in each stage of a step (e.g. clone vs. kernel
+/// compile), written along with the step's total to
+/// `<log_dir>/<step>-timing.json` when dropped. Writing on drop means the
+/// file is there however the step ends, including early returns for cached
+/// artifacts and failures.
+pub struct StageTimings {
+    step: &'static str,
+    log_dir: PathBuf,
+    started: Instant,
+    stages: Vec<StageTiming>,
+}
+
+impl StageTimings {
+    pub fn new(step: &'static str, log_dir: PathBuf) -> Self {
+        Self {
+            step,
+            log_dir,
+            started: Instant::now(),
+            stages: Vec::new(),
+        }
+    }
+
+    /// Record that stage `name`, started at `t0`, is done.
+    pub fn record(&mut self, name: &str, t0: Instant) {
+        let secs = t0.elapsed().as_secs_f64();
+        log::info!("{} took {:.1}s", name, secs);
+        self.stages.push(StageTiming {
+            name: name.to_string(),
+            secs,
+        });
+    }
+
+    fn write(&self) -> anyhow::Result<PathBuf> {
+        #[derive(Serialize)]
+        struct Summary<'a> {
+            step: &'a str,
+            total_secs: f64,
+            stages: &'a [StageTiming],
+        }
+
+        fs_err::create_dir_all(&self.log_dir)?;
+        let path = self.log_dir.join(format!("{}-timing.json", self.step));
+        let summary = Summary {
+            step: self.step,
+            total_secs: self.started.elapsed().as_secs_f64(),
+            stages: &self.stages,
+        };
+        fs_err::write(&path, serde_json::to_string_pretty(&summary)?)?;
+        Ok(path)
+    }
+}
+
+impl Drop for StageTimings {
+    fn drop(&mut self) {
+        match self.write() {
+            Ok(path) => log::info!(
+                "{} step took {:.1}s (see {})",
+                self.step,
+                self.started.elapsed().as_secs_f64(),
+                path.display()
+            ),
+            Err(e) => log::warn!("Failed to write the {} step timings: {:#}", self.step, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernel_image_path_cases() {
+        // (build dir: kernel source tree or `O=` dir, arch, target, expected)
+        let cases = [
+            (
+                "/src/linux",
+                KERNEL_ARCH,
+                KERNEL_IMAGE_TARGET,
+                "/src/linux/arch/arm64/boot/Image",
+            ),
+            (
+                "/src/linux",
+                KERNEL_ARCH,
+                "Image.gz",
+                "/src/linux/arch/arm64/boot/Image.gz",
+            ),
+            (
+                "/build/out",
+                KERNEL_ARCH,
+                KERNEL_IMAGE_TARGET,
+                "/build/out/arch/arm64/boot/Image",
+            ),
+            (
+                "/build/out",
+                KERNEL_ARCH,
+                "Image.gz",
+                "/build/out/arch/arm64/boot/Image.gz",
+            ),
+            (
+                "/src/linux",
+                "x86",
+                "bzImage",
+                "/src/linux/arch/x86/boot/bzImage",
+            ),
+            (
+                "/build/out",
+                "x86",
+                "bzImage",
+                "/build/out/arch/x86/boot/bzImage",
+            ),
+        ];
+        for (build_dir, arch, target, expected) in cases {
+            assert_eq!(
+                kernel_image_path(Path::new(build_dir), arch, target),
+                Path::new(expected)
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exit_status_signal_vs_code() {
+        use std::os::unix::process::ExitStatusExt;
+        assert_eq!(
+            describe_exit_status(ExitStatus::from_raw(9)),
+            "killed by signal 9 (SIGKILL)"
+        );
+        assert_eq!(
+            describe_exit_status(ExitStatus::from_raw(15)),
+            "killed by signal 15 (SIGTERM)"
+        );
+        assert_eq!(
+            describe_exit_status(ExitStatus::from_raw(2 << 8)),
+            "exited with code 2"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn wait_with_timeout_kills_hung_run() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // A stand-in for a `shrinkwrap run` whose FVP never exits
+        let dir = tempfile::TempDir::new().unwrap();
+        let shrinkwrap = dir.path().join("shrinkwrap");
+        fs_err::write(&shrinkwrap, "#!/bin/sh\nwhile true; do sleep 1; done\n").unwrap();
+        fs_err::set_permissions(&shrinkwrap, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut cmd = Command::new(&shrinkwrap);
+        cmd.arg("run");
+        let mut child = spawn_in_process_group(&mut cmd).unwrap();
+
+        let start = Instant::now();
+        let err = wait_with_timeout(&mut child, Some(Duration::from_secs(1))).unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(err.to_string().contains("timed out after 1s"), "{}", err);
+        // The process group was torn down and reaped
+        assert!(child.try_wait().unwrap().is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn tee_command_output_writes_log() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let log_path = dir.path().join("logs").join("tee.log");
+
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo hello; echo oops >&2; exit 3"]);
+        let status = tee_command_output(cmd, &log_path).unwrap();
+        assert_eq!(status.code(), Some(3));
+        let mut cmd = Command::new("echo");
+        cmd.arg("again");
+        assert!(tee_command_output(cmd, &log_path).unwrap().success());
+
+        let log = fs_err::read_to_string(&log_path).unwrap();
+        assert!(log.contains("hello\n"), "{}", log);
+        assert!(log.contains("STDERR: oops\n"), "{}", log);
+        // Appended to, not overwritten
+        assert!(log.ends_with("again\n"), "{}", log);
+    }
+
+    #[test]
+    fn verify_sha256_fixture() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("toolchain.tar.xz");
+        fs_err::write(&path, "hello").unwrap();
+
+        verify_sha256(
+            &path,
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+        )
+        .unwrap();
+        verify_sha256(
+            &path,
+            "2CF24DBA5FB0A30E26E83B2AC5B9E29E1B161E5C1FA7425E73043362938B9824",
+        )
+        .unwrap();
+        let err = verify_sha256(&path, &"0".repeat(64)).unwrap_err();
+        assert!(err.to_string().contains("expected 0000"), "{}", err);
+    }
+
+    #[test]
+    fn assert_aarch64_elf_checks_machine() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let elf = |name: &str, machine: u16| {
+            let mut header = [0u8; 64];
+            header[..4].copy_from_slice(b"\x7fELF");
+            header[18..20].copy_from_slice(&machine.to_le_bytes());
+            let path = dir.path().join(name);
+            fs_err::write(&path, header).unwrap();
+            path
+        };
+
+        assert_aarch64_elf(&elf("aarch64", 0xB7)).unwrap();
+        let err = assert_aarch64_elf(&elf("x86_64", 0x3E)).unwrap_err();
+        assert!(
+            err.to_string().contains("for x86-64, not AArch64"),
+            "{}",
+            err
+        );
+        let script = dir.path().join("script");
+        fs_err::write(&script, "#!/bin/sh\necho this is not an ELF binary\n").unwrap();
+        let err = assert_aarch64_elf(&script).unwrap_err();
+        assert!(err.to_string().contains("is not an ELF binary"), "{}", err);
+    }
+
+    #[test]
+    fn report_invalid_params_lists_every_problem() {
+        report_invalid_params("run", Vec::new()).unwrap();
+        let err = report_invalid_params("run", vec!["a".to_string()]).unwrap_err();
+        assert_eq!(err.to_string(), "invalid run parameters: a");
+        let err = report_invalid_params("run", vec!["a".to_string(), "b".to_string()]).unwrap_err();
+        assert_eq!(err.to_string(), "invalid run parameters:\n  a\n  b");
+    }
+
+    #[test]
+    fn tfa_version_from_banner() {
+        let image = b"\0BL1: %s\0v2\0v2.10.0(release):v2.10.0-dirty\0";
+        assert_eq!(find_tfa_version(image).as_deref(), Some("2.10.0"));
+        assert_eq!(find_tfa_version(b"v2.10.0 without build type"), None);
+        assert!(tfa_version_matches("2.10.0", "2.10"));
+        assert!(tfa_version_matches("2.10.0", "v2.10.0"));
+        assert!(!tfa_version_matches("2.10.0", "2.1"));
+        assert!(!tfa_version_matches("2.1", "2.10"));
+    }
+}